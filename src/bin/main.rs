@@ -1,8 +1,62 @@
+use std::collections::HashSet;
 use std::env;
-use std::env::Args;
 use std::error::Error;
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinError;
+use tracing_subscriber::EnvFilter;
 
 use bank_lib::bank::Bank;
+use bank_lib::config::{BankConfig, ProcessOptions, ProgressCallback, ZeroAmountPolicy};
+use bank_lib::report::{ProfileReport, ProgressUpdate};
+use bank_lib::shared_types::ClientId;
+
+/// Command line arguments of the runner
+struct CliArgs {
+    csv_paths: Vec<String>,
+    /// Log every processed transaction to stderr
+    verbose: bool,
+    /// Only write the accounts of these clients, in this order
+    clients: Option<Vec<ClientId>>,
+    /// Add an open_disputes column to the account output
+    extended_output: bool,
+    /// Only check the files, writing the problems found to stderr
+    dry_run: bool,
+    /// File to write the accounts to instead of stdout
+    output: Option<String>,
+    format: OutputFormat,
+    /// Address to serve the HTTP API on instead of processing files, given with `serve`
+    listen: Option<String>,
+    /// Most files processed at the same time
+    jobs: usize,
+    /// Write how far each file got to stderr
+    progress: bool,
+    /// Csv of account balances carried over from another system, imported before anything is processed
+    opening_balances: Option<String>,
+    /// Write how long the bank's locks were waited for and held to stderr
+    profile: bool,
+    /// Clients whose transactions are rejected without opening an account
+    reserved_clients: HashSet<ClientId>,
+    /// Process deposits and withdrawals of zero instead of rejecting them
+    accept_zero_amounts: bool,
+}
+
+/// The format the accounts are written in
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Csv,
+    Json,
+    /// Only to a file given with `--output`, see [Bank::write_accounts_parquet]
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+/// Address the HTTP API is served on if `serve` is given without `--listen`
+const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:8080";
 
 /// Takes in a space separated list of csv file paths from stdin
 /// Simultaneously processes all contained transactions to a central bank
@@ -10,37 +64,404 @@ use bank_lib::bank::Bank;
 ///
 /// In the event that one CSV file is malformed, processing continues on the rest.
 /// Unless an unexpected crash occurs where the bank data is poisoned.
+/// A summary of how many files were processed and how many failed is written to stderr,
+/// counting files that were read but had no rows, empty or only a header row, as empty.
+///
+/// On Ctrl-C no more files are started, the files in progress are finished and the accounts
+/// processed so far are written as usual before exiting with code 130. A second Ctrl-C exits straight away.
+///
+/// Logging to stderr is enabled with `--verbose` or filtered with the `RUST_LOG` environment variable.
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let bank = Bank::new();
+    let args = get_args()?;
+    init_tracing(args.verbose);
+    let bank = Bank::with_config(BankConfig {
+        extended_output: args.extended_output,
+        reserved_clients: args.reserved_clients,
+        zero_amount_policy: if args.accept_zero_amounts { ZeroAmountPolicy::Accept } else { ZeroAmountPolicy::Reject },
+        ..BankConfig::default()
+    });
+    bank.set_profiling(args.profile);
+    if let Some(path) = &args.opening_balances {
+        bank.import_opening_balances(File::open(path)?)?;
+    }
+    if let Some(listen) = args.listen {
+        return serve(bank, &listen).await;
+    }
+    if args.dry_run {
+        if !validate(&bank, &args.csv_paths) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    let processes = get_csv_paths().into_iter().map(
-        |csv_path| spawn_tokio_process_for_csv(csv_path, &bank)
-    );
-    for process in processes {
-        match process.await {
-            _ => (),
+    let shutdown = shutdown_on_ctrl_c();
+    let progress = args.progress;
+    let processes = process_with_limit(args.csv_paths, args.jobs, shutdown.clone(), |csv_path| {
+        let tokio_bank = Bank::new_for_tokio(&bank);
+        async move {
+            if !progress {
+                return tokio_bank.process_transactions_from_csv_path(&csv_path).await;
+            }
+            let started = std::time::Instant::now();
+            let options = ProcessOptions { on_progress: Some(progress_printer(&csv_path)), ..ProcessOptions::default() };
+            let report = tokio_bank.process_transactions_from_csv_path_with_options(&csv_path, &options).await?;
+            print_progress(&csv_path, "done", ProgressUpdate { rows: report.rows, bytes: report.bytes, elapsed: started.elapsed() });
+            Ok(report)
         }
+    }).await;
+    let total = processes.len();
+    let (mut failed, mut skipped, mut empty) = (0, 0, 0);
+    for (csv_path, outcome) in processes {
+        let error = match outcome {
+            Ok(Some(Ok(report))) => {
+                if report.rows == 0 {
+                    empty += 1;
+                    eprintln!("no rows in {}", csv_path);
+                }
+                continue;
+            },
+            Ok(None) => {
+                skipped += 1;
+                continue;
+            },
+            Ok(Some(Err(e))) => e.to_string(),
+            Err(e) => e.to_string(),
+        };
+        failed += 1;
+        eprintln!("failed to process {}: {}", csv_path, error);
+    }
+    let mut summary = format!("processed {}/{} files, {} failed", total - failed - skipped, total, failed);
+    if empty > 0 {
+        summary.push_str(&format!(", {} empty", empty));
+    }
+    if skipped > 0 {
+        summary.push_str(&format!(", {} skipped after interrupt", skipped));
     }
+    eprintln!("{}", summary);
 
-    bank.write_accounts()?;
+    #[cfg(feature = "parquet")]
+    if let (OutputFormat::Parquet, Some(path)) = (args.format, &args.output) {
+        bank.write_accounts_parquet(std::path::Path::new(path))?;
+        if args.profile {
+            print_profile(&bank.profile_report());
+        }
+        if *shutdown.borrow() {
+            std::process::exit(130);
+        }
+        return Ok(());
+    }
+    let output: Box<dyn io::Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    match (args.clients, args.format) {
+        (Some(clients), format) => {
+            let report = match format {
+                OutputFormat::Csv => bank.write_accounts_filtered(output, &clients)?,
+                OutputFormat::Json => bank.write_accounts_json_filtered(output, &clients)?,
+                #[cfg(feature = "parquet")]
+                OutputFormat::Parquet => unreachable!("rejected by get_args"),
+            };
+            for client in report.unknown_clients {
+                eprintln!("no account for client {}", client);
+            }
+        },
+        (None, OutputFormat::Csv) => bank.write_accounts_to(output)?,
+        (None, OutputFormat::Json) => bank.write_accounts_json(output)?,
+        #[cfg(feature = "parquet")]
+        (None, OutputFormat::Parquet) => unreachable!("written above as --output is required"),
+    }
+    if args.profile {
+        print_profile(&bank.profile_report());
+    }
+    if *shutdown.borrow() {
+        std::process::exit(130);
+    }
     Ok(())
 }
 
-/// Spawns and returns a process for the given csv
-fn spawn_tokio_process_for_csv(csv_path: String, bank: &Bank) -> tokio::task::JoinHandle<()> {
-    let tokio_bank = Bank::new_for_tokio(bank);
+/// Returns a flag that is raised on the first Ctrl-C, exiting the process on the second
+fn shutdown_on_ctrl_c() -> watch::Receiver<bool> {
+    let (sender, receiver) = watch::channel(false);
     tokio::spawn(async move {
-        Bank::process_transactions_from_csv_path(
-            &csv_path, tokio_bank
-        ).await;
-    })
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        eprintln!("interrupted, finishing the files in progress before writing the accounts");
+        let _ = sender.send(true);
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+    receiver
+}
+
+/// Returns a callback writing the progress of processing the csv at the given path to stderr
+fn progress_printer(csv_path: &str) -> ProgressCallback {
+    let csv_path = csv_path.to_string();
+    Arc::new(move |update| print_progress(&csv_path, "processing", update))
+}
+
+/// Writes a line such as `transactions.csv: processing, 200000 rows, 6.1 MB in 1.2s` to stderr
+fn print_progress(csv_path: &str, state: &str, update: ProgressUpdate) {
+    eprintln!("{}: {}, {} rows, {:.1} MB in {:.1}s",
+        csv_path, state, update.rows, update.bytes as f64 / 1_000_000.0, update.elapsed.as_secs_f64());
+}
+
+/// Writes the throughput and a line per lock such as `accounts: 300000 locks, waited 21.3ms, held 412.5ms` to stderr
+fn print_profile(profile: &ProfileReport) {
+    eprintln!("{} records in {:.3}s, {:.0} records/s",
+        profile.records, profile.elapsed.as_secs_f64(), profile.records_per_second());
+    for lock in &profile.locks {
+        eprintln!("{}: {} locks, waited {:?}, held {:?}", lock.lock, lock.acquisitions, lock.wait, lock.hold);
+    }
+}
+
+/// Checks the csvs at the given paths without applying them, writing every problem found to stderr
+///
+/// Returns whether all files could be read and had no problems.
+fn validate(bank: &Bank, csv_paths: &[String]) -> bool {
+    let mut problems = 0;
+    for csv_path in csv_paths {
+        match bank.validate_csv_path(csv_path) {
+            Ok(report) => {
+                for problem in &report.problems {
+                    eprintln!("{}: {}", csv_path, problem);
+                }
+                problems += report.problems.len();
+            },
+            Err(e) => {
+                eprintln!("failed to validate {}: {}", csv_path, e);
+                problems += 1;
+            },
+        }
+    }
+    eprintln!("validated {} files, {} problems", csv_paths.len(), problems);
+    problems == 0
+}
+
+/// Serves the HTTP API for the bank until the server fails
+#[cfg(feature = "server")]
+async fn serve(bank: Bank, listen: &str) -> Result<(), Box<dyn Error>> {
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    eprintln!("listening on {}", listener.local_addr()?);
+    bank_lib::server::serve(bank, listener).await?;
+    Ok(())
+}
+
+#[cfg(not(feature = "server"))]
+async fn serve(_bank: Bank, _listen: &str) -> Result<(), Box<dyn Error>> {
+    Err("serve requires building with the server feature, e.g. cargo run --features server -- serve".into())
+}
+
+/// Processes each csv path on its own tokio task, with at most `jobs` of them running at the same time,
+/// and returns the outcome of each in the order of the paths
+///
+/// The task of a path only starts `process` once it may run, so files are not opened before then.
+/// Once `shutdown` is raised no more paths are started, their outcome is None,
+/// while the ones already started run to the end.
+async fn process_with_limit<F, Fut>(
+    csv_paths: Vec<String>,
+    jobs: usize,
+    shutdown: watch::Receiver<bool>,
+    process: F,
+) -> Vec<(String, Result<Option<Fut::Output>, JoinError>)>
+    where F: Fn(String) -> Fut,
+          Fut: Future + Send + 'static,
+          Fut::Output: Send + 'static,
+{
+    let permits = Arc::new(Semaphore::new(jobs.max(1)));
+    let tasks: Vec<_> = csv_paths.into_iter().map(|csv_path| {
+        let permits = permits.clone();
+        let shutdown = shutdown.clone();
+        let processing = process(csv_path.clone());
+        (csv_path, tokio::spawn(async move {
+            let _permit = permits.acquire_owned().await.expect("the semaphore is never closed");
+            if *shutdown.borrow() {
+                return None;
+            }
+            Some(processing.await)
+        }))
+    }).collect();
+    let mut outcomes = Vec::with_capacity(tasks.len());
+    for (csv_path, task) in tasks {
+        outcomes.push((csv_path, task.await));
+    }
+    outcomes
+}
+
+/// Gets the csv paths and flags from stdin
+///
+/// `--clients 1,7,42` limits the output to the given clients.
+/// `--extended-output` adds an `open_disputes` column to the output.
+/// `--dry-run` only checks the files, writing the problems found to stderr and failing if there are any.
+/// `--output accounts.csv` writes the accounts to a file instead of stdout.
+/// `--format json` writes the accounts as a JSON array instead of csv.
+/// `--format parquet` writes the accounts as a Parquet file, requiring `--output` and the parquet feature.
+/// `--jobs 4` processes at most 4 files at the same time, the default is the number of CPUs.
+/// `--progress` writes a line to stderr every 100000 rows of a file and once it is done.
+/// `--reserve-client 0` rejects the transactions of client 0 without opening an account for it, it can be given more than once.
+/// `--accept-zero-amounts` processes deposits and withdrawals of zero, which are rejected otherwise.
+/// `--profile` writes the records processed per second and how long each lock of the bank was waited for and held to stderr.
+/// `--opening-balances balances.csv` creates the accounts in the file before processing, see [Bank::import_opening_balances].
+/// `serve [--listen 127.0.0.1:8080]` serves the HTTP API instead of processing files.
+fn get_args() -> Result<CliArgs, String> {
+    let mut args = CliArgs {
+        csv_paths: Vec::new(),
+        verbose: false,
+        clients: None,
+        extended_output: false,
+        dry_run: false,
+        output: None,
+        format: OutputFormat::Csv,
+        listen: None,
+        jobs: std::thread::available_parallelism().map_or(1, |cpus| cpus.get()),
+        progress: false,
+        opening_balances: None,
+        profile: false,
+        reserved_clients: HashSet::new(),
+        accept_zero_amounts: false,
+    };
+    let mut raw_args = env::args().skip(1).peekable();
+    if raw_args.peek().is_some_and(|arg| arg == "serve") {
+        raw_args.next();
+        args.listen = Some(DEFAULT_LISTEN_ADDRESS.to_string());
+    }
+    while let Some(arg) = raw_args.next() {
+        match arg.as_str() {
+            "--verbose" => args.verbose = true,
+            "--extended-output" => args.extended_output = true,
+            "--dry-run" => args.dry_run = true,
+            "--progress" => args.progress = true,
+            "--profile" => args.profile = true,
+            "--accept-zero-amounts" => args.accept_zero_amounts = true,
+            "--listen" if args.listen.is_some() => {
+                args.listen = Some(raw_args.next().ok_or("--listen requires an address such as 127.0.0.1:8080")?);
+            },
+            "--jobs" => {
+                args.jobs = raw_args.next().and_then(|jobs| jobs.parse().ok()).filter(|&jobs| jobs > 0)
+                    .ok_or("--jobs requires a number of files greater than zero")?;
+            },
+            "--opening-balances" => {
+                args.opening_balances = Some(raw_args.next().ok_or("--opening-balances requires a file path")?);
+            },
+            "--reserve-client" => {
+                let client = raw_args.next().ok_or("--reserve-client requires a client id")?;
+                args.reserved_clients.insert(parse_client(&client)?);
+            },
+            "--output" => args.output = Some(raw_args.next().ok_or("--output requires a file path")?),
+            "--format" => {
+                args.format = match raw_args.next().as_deref() {
+                    Some("csv") => OutputFormat::Csv,
+                    Some("json") => OutputFormat::Json,
+                    #[cfg(feature = "parquet")]
+                    Some("parquet") => OutputFormat::Parquet,
+                    #[cfg(not(feature = "parquet"))]
+                    Some("parquet") => return Err("--format parquet requires building with the parquet feature".to_string()),
+                    _ => return Err("--format requires csv, json or parquet".to_string()),
+                };
+            },
+            "--clients" => {
+                let list = raw_args.next().ok_or("--clients requires a comma separated list of client ids")?;
+                args.clients = Some(parse_clients(&list)?);
+            },
+            _ => args.csv_paths.push(arg),
+        }
+    }
+    #[cfg(feature = "parquet")]
+    if args.format == OutputFormat::Parquet {
+        if args.output.is_none() {
+            return Err("--format parquet requires --output".to_string());
+        }
+        if args.clients.is_some() {
+            return Err("--clients can't be used with --format parquet".to_string());
+        }
+    }
+    Ok(args)
 }
 
-/// Gets the csv paths from stdin
-fn get_csv_paths() -> Args {
-    let mut args = env::args().into_iter();
-    args.next();
-    args
+/// Parses a comma separated list of client ids such as `1,7,42`
+fn parse_clients(list: &str) -> Result<Vec<ClientId>, String> {
+    list.split(',').map(parse_client).collect()
 }
 
+fn parse_client(client: &str) -> Result<ClientId, String> {
+    client.trim().parse().map(ClientId).map_err(|_| format!("invalid client id: {:?}", client))
+}
+
+/// Installs a subscriber writing log events to stderr if `RUST_LOG` is set or `verbose` is given
+fn init_tracing(verbose: bool) {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) if verbose => EnvFilter::new("debug"),
+        Err(_) => return,
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::sync::watch;
+
+    use bank_lib::bank::Bank;
+    use bank_lib::shared_types::ClientId;
+
+    use super::process_with_limit;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_at_most_jobs_files_processed_at_once() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let high_water_mark = Arc::new(AtomicUsize::new(0));
+        let csv_paths: Vec<String> = (0..20).map(|i| format!("file_{}.csv", i)).collect();
+
+        let (_shutdown, never_raised) = watch::channel(false);
+
+        let outcomes = process_with_limit(csv_paths.clone(), 3, never_raised, |csv_path| {
+            let (running, high_water_mark) = (running.clone(), high_water_mark.clone());
+            async move {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                high_water_mark.fetch_max(now_running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                csv_path.len()
+            }
+        }).await;
+
+        assert_eq!(high_water_mark.load(Ordering::SeqCst), 3);
+        let paths: Vec<&String> = outcomes.iter().map(|(csv_path, _)| csv_path).collect();
+        assert_eq!(paths, csv_paths.iter().collect::<Vec<_>>());
+        assert!(outcomes.iter().all(|(csv_path, outcome)| *outcome.as_ref().unwrap() == Some(csv_path.len())));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_finishes_started_files_and_keeps_their_accounts() {
+        let bank = Bank::new();
+        let (shutdown, raised) = watch::channel(false);
+        let shutdown = Arc::new(shutdown);
+        let csv_paths = vec!["tests/fixtures/split_part_1.csv".to_string(), "tests/fixtures/dispute_resolve.csv".to_string()];
+
+        let outcomes = process_with_limit(csv_paths, 1, raised, |csv_path| {
+            let (tokio_bank, shutdown) = (Bank::new_for_tokio(&bank), shutdown.clone());
+            async move {
+                // Interrupted while the first file is in progress
+                shutdown.send(true).unwrap();
+                tokio_bank.process_transactions_from_csv_path(&csv_path).await.unwrap().applied
+            }
+        }).await;
+
+        assert_eq!(outcomes[0].1.as_ref().unwrap(), &Some(2));
+        assert_eq!(outcomes[1].1.as_ref().unwrap(), &None);
+        let mut output = Vec::new();
+        bank.write_accounts_filtered(&mut output, &[ClientId(1), ClientId(2)]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,5.0,0.0,5.0,false\n2,1.0,0.0,1.0,false\n");
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+
+use serde::Serialize;
+
+use crate::bank::{Bank, OutputAmount};
+use crate::config::AmountOutputFormat;
+use crate::error::BankError;
+use crate::shared_types::{Amount, AmountValue, ClientId, TxId};
+
+/// The columns of the activity log output
+const ACTIVITY_HEADERS: [&str; 5] = ["sequence", "event", "client", "tx", "amount"];
+
+impl Bank {
+    /// Outputs the activity log to the given writer in csv format, one row per event in the order they happened
+    ///
+    /// Events are only recorded while [crate::config::BankConfig::record_activity] is on, so the log
+    /// has only the header row if it is off.
+    pub fn write_activity_log_csv<W: io::Write>(&self, writer: W) -> Result<(), BankError> {
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+        wtr.write_record(ACTIVITY_HEADERS)?;
+        if let Some(activity) = &self.activity {
+            for event in &activity.lock().unwrap().events {
+                wtr.serialize(event)?;
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Adds an event to the activity log if the bank records one
+    ///
+    /// Called while the accounts are locked, so events are numbered in the order they were applied
+    /// even when several files are processed at once.
+    pub(crate) fn log_activity(&self, kind: ActivityKind, client: ClientId, tx: Option<TxId>, amount: Option<AmountValue>) {
+        if let Some(activity) = &self.activity {
+            let amount = amount.map(|value| OutputAmount::new(&Amount { value }, self.amount_style(AmountOutputFormat::Decimal)));
+            activity.lock().unwrap().record(kind, client, tx, amount);
+        }
+    }
+}
+
+/// A change to the state of an account
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ActivityKind {
+    AccountCreated,
+    AccountLocked,
+    DisputeOpened,
+    DisputeResolved,
+    Chargeback,
+    Reversal,
+}
+
+/// A row of the activity log, see [Bank::write_activity_log_csv]
+#[derive(Serialize, Debug)]
+pub(crate) struct ActivityEvent {
+    /// Position of the event among all events of the bank, starting at 1
+    sequence: u64,
+    event: ActivityKind,
+    client: ClientId,
+    /// The transaction that caused the event, none for accounts opened directly on the bank
+    tx: Option<TxId>,
+    amount: Option<OutputAmount>,
+}
+
+/// Every change to the state of the accounts of a bank, in the order they happened
+#[derive(Debug, Default)]
+pub(crate) struct ActivityLog {
+    events: Vec<ActivityEvent>,
+}
+
+impl ActivityLog {
+    fn record(&mut self, kind: ActivityKind, client: ClientId, tx: Option<TxId>, amount: Option<OutputAmount>) {
+        let sequence = self.events.len() as u64 + 1;
+        self.events.push(ActivityEvent { sequence, event: kind, client, tx, amount });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::Bank;
+    use crate::config::BankConfig;
+    use crate::shared_types::{ClientId, TxId};
+    use crate::transaction::{TxRequest, TxType};
+
+    fn activity_log(bank: &Bank) -> String {
+        let mut output = Vec::new();
+        bank.write_activity_log_csv(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_dispute_then_chargeback_activity() {
+        let bank = Bank::with_config(BankConfig { record_activity: true, ..BankConfig::default() });
+        let apply = |type_, client, tx, amount| bank.apply(TxRequest { type_, client: ClientId(client), tx: TxId(tx), amount, currency: None });
+
+        apply(TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(TxType::Deposit, 2, 2, Some(20000)).unwrap();
+        apply(TxType::Dispute, 2, 2, None).unwrap();
+        apply(TxType::Resolve, 2, 2, None).unwrap();
+        apply(TxType::Dispute, 1, 1, None).unwrap();
+        apply(TxType::Chargeback, 1, 1, None).unwrap();
+        bank.open_account(ClientId(3));
+
+        assert_eq!(activity_log(&bank), "sequence,event,client,tx,amount\n\
+            1,account_created,1,1,\n\
+            2,account_created,2,2,\n\
+            3,dispute_opened,2,2,2.0\n\
+            4,dispute_resolved,2,2,2.0\n\
+            5,dispute_opened,1,1,1.0\n\
+            6,chargeback,1,1,1.0\n\
+            7,account_locked,1,1,\n\
+            8,account_created,3,,\n");
+    }
+
+    #[test]
+    fn test_no_activity_recorded_by_default() {
+        let bank = Bank::new();
+        bank.apply(TxRequest { type_: TxType::Deposit, client: ClientId(1), tx: TxId(1), amount: Some(10000), currency: None }).unwrap();
+
+        assert_eq!(activity_log(&bank), "sequence,event,client,tx,amount\n");
+    }
+}
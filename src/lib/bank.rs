@@ -1,65 +1,904 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io;
+use std::io::{self, Seek};
+use std::path::Path;
 use csv;
-use csv::{Reader, ReaderBuilder};
+use serde::ser::{SerializeSeq, Serializer};
 use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use crate::shared_types::{ClientId, TxId, Amount};
-use crate::transaction::Tx;
+use crate::activity::{ActivityKind, ActivityLog};
+use crate::checkpoint::Checkpoint;
+use crate::config::{AmountOutputFormat, BankConfig, PartialChargebackRemainder, ProcessOptions, TxOrdering};
+use crate::error::{BankError, CloseAccountError};
+use crate::journal::{Journal, JournalEntry};
+use crate::lock::{LockRank, OrderedLock};
+use crate::metrics::{BankMetrics, SharedMetrics};
+use crate::pending::PendingDisputes;
+use crate::profile::Profiler;
+use crate::registry::{hex_sha256, ProcessedRegistry};
+use crate::reader::TxReader;
+use crate::recent::RecentTxIds;
+use crate::report::{BatchReport, LineError, OutputReport, ProcessReport, ProgressUpdate, RejectionReason, RowError};
+use crate::shared_types::{ClientId, TxId, Amount, AmountValue, CurrencyCode, SourceId};
+use crate::store::{StoredTx, TransactionStore};
+use crate::transaction::{TxRequest, Tx, TxType};
 
-#[derive(Debug)]
+/// Every client's account, by client
+pub(crate) type Accounts = HashMap<ClientId, Account>;
+/// The transaction sheet of deposits and withdrawals
+pub(crate) type Transactions = Box<dyn TransactionStore>;
+
+/// The columns of the account output
+const ACCOUNT_HEADERS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+/// The columns of the account output with [BankConfig::extended_output]
+const EXTENDED_ACCOUNT_HEADERS: [&str; 6] = ["client", "available", "held", "total", "locked", "open_disputes"];
+
+/// The columns of the transaction sheet output
+const TRANSACTION_HEADERS: [&str; 6] = ["tx", "client", "type", "amount", "disputed", "source"];
+
+/// Holds the accounts of all clients and the transactions applied to them
+///
+/// Each part of the bank is behind its own lock. Code taking more than one of them must take them
+/// in the order accounts, transactions, pending disputes, losses, fees, which panics otherwise, see [LockRank].
+/// Accounts and the transaction sheet, which are needed together most, are taken with
+/// [Bank::with_accounts_and_sheet].
 pub struct Bank {
-    pub(crate) transactions: Arc<Mutex<HashMap<TxId, Tx>>>,
-    pub(crate) accounts: Arc<Mutex<HashMap<ClientId, Account>>>
+    pub(crate) transactions: Arc<OrderedLock<Transactions>>,
+    pub(crate) accounts: Arc<OrderedLock<Accounts>>,
+    pub(crate) pending_disputes: Arc<OrderedLock<PendingDisputes>>,
+    /// Shortfall left on each client's available balance by chargebacks, i.e. what the bank lost
+    pub(crate) losses: Arc<OrderedLock<HashMap<ClientId, Amount>>>,
+    /// Fees collected on withdrawals by currency, see [BankConfig::fee_schedule]
+    pub(crate) fees: Arc<OrderedLock<HashMap<CurrencyCode, AmountValue>>>,
+    /// Number of accounts created so far, see [Bank::new_account]
+    pub(crate) accounts_created: Arc<AtomicU64>,
+    pub(crate) config: Arc<BankConfig>,
+    pub(crate) metrics: Arc<SharedMetrics>,
+    /// Where changes are journaled, see [Bank::with_journal]
+    pub(crate) journal: Option<Arc<Journal>>,
+    /// The csv files processed before, see [Bank::with_processed_registry]
+    pub(crate) registry: Option<Arc<Mutex<ProcessedRegistry>>>,
+    /// Changes to the accounts, if [BankConfig::record_activity] is on
+    pub(crate) activity: Option<Arc<Mutex<ActivityLog>>>,
+    /// Paths of the files transactions were read from, indexed by [SourceId]
+    pub(crate) sources: Arc<Mutex<Vec<String>>>,
+    /// Tx ids of the latest deposits and withdrawals, if [BankConfig::duplicate_window] is set
+    pub(crate) recent_tx_ids: Option<Arc<Mutex<RecentTxIds>>>,
+    /// Number of times an account failed the checks of [BankConfig::debug_invariants]
+    pub(crate) invariant_violations: Arc<AtomicU64>,
+    /// The highest tx id processed plus one, zero before any, see [Bank::max_seen_tx_id]
+    pub(crate) max_tx_id: Arc<AtomicU64>,
+    /// Number of deposits and withdrawals stored on the transaction sheet so far, see [BankConfig::dispute_window]
+    pub(crate) tx_seq: Arc<AtomicU64>,
+    /// Measures the locks while turned on, see [Bank::set_profiling]
+    pub(crate) profiler: Arc<Profiler>,
+}
+
+/// Describes the bank by its number of accounts, transactions and locked accounts, e.g.
+/// `Bank { accounts: 3, transactions: 12, locked_accounts: 1 }`
+///
+/// Never waits for a lock, a count whose part of the bank is locked is written as `<busy>`.
+impl fmt::Debug for Bank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (accounts, locked_accounts) = match self.accounts.try_lock() {
+            Some(accounts) => (Some(accounts.len()), Some(accounts.values().filter(|account| account.locked).count())),
+            None => (None, None),
+        };
+        let transactions = self.transactions.try_lock().map(|transactions| transactions.len());
+        f.debug_struct("Bank")
+            .field("accounts", &DebugCount(accounts))
+            .field("transactions", &DebugCount(transactions))
+            .field("locked_accounts", &DebugCount(locked_accounts))
+            .finish()
+    }
+}
+
+/// A count in the debug output of a bank, None if it couldn't be taken as the bank was locked
+struct DebugCount(Option<usize>);
+
+impl fmt::Debug for DebugCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(count) => write!(f, "{}", count),
+            None => write!(f, "<busy>"),
+        }
+    }
+}
+
+impl Default for Bank {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Bank {
     pub fn new() -> Self {
+        Self::with_config(BankConfig::default())
+    }
+
+    /// Creates an empty bank that processes transactions according to the given config
+    ///
+    /// Panics if [BankConfig::decimal_places] or any of [BankConfig::currency_decimal_places]
+    /// is more than [Amount::MAX_DECIMAL_PLACES].
+    pub fn with_config(config: BankConfig) -> Self {
+        Self::with_config_and_profiler(config, Arc::new(Profiler::default()))
+    }
+
+    /// Creates an empty bank whose locks are measured by the given profiler, e.g. that of the bank it is a shard of
+    pub(crate) fn with_config_and_profiler(config: BankConfig, profiler: Arc<Profiler>) -> Self {
+        assert!(
+            config.decimal_places <= Amount::MAX_DECIMAL_PLACES,
+            "decimal_places is {}, at most {} are supported", config.decimal_places, Amount::MAX_DECIMAL_PLACES,
+        );
+        for (currency, &places) in &config.currency_decimal_places {
+            assert!(
+                places <= Amount::MAX_DECIMAL_PLACES,
+                "decimal places of {} are {}, at most {} are supported", currency, places, Amount::MAX_DECIMAL_PLACES,
+            );
+        }
+        Self {
+            transactions: Arc::new(OrderedLock::<Transactions>::new(LockRank::Transactions, Box::new(HashMap::<TxId, StoredTx>::new())).profiled(&profiler)),
+            accounts: Arc::new(OrderedLock::new(LockRank::Accounts, HashMap::new()).profiled(&profiler)),
+            pending_disputes: Arc::new(OrderedLock::new(LockRank::PendingDisputes, PendingDisputes::new(config.max_pending_disputes)).profiled(&profiler)),
+            losses: Arc::new(OrderedLock::new(LockRank::Losses, HashMap::new()).profiled(&profiler)),
+            fees: Arc::new(OrderedLock::new(LockRank::Fees, HashMap::new()).profiled(&profiler)),
+            accounts_created: Arc::new(AtomicU64::new(0)),
+            metrics: Arc::new(SharedMetrics::new()),
+            journal: None,
+            registry: None,
+            activity: config.record_activity.then(|| Arc::new(Mutex::new(ActivityLog::default()))),
+            sources: Arc::new(Mutex::new(Vec::new())),
+            invariant_violations: Arc::new(AtomicU64::new(0)),
+            max_tx_id: Arc::new(AtomicU64::new(0)),
+            tx_seq: Arc::new(AtomicU64::new(0)),
+            recent_tx_ids: config.duplicate_window.map(|capacity| Arc::new(Mutex::new(RecentTxIds::new(capacity)))),
+            config: Arc::new(config),
+            profiler,
+        }
+    }
+
+    /// Creates an empty bank that keeps its transaction sheet in the given store instead of in memory
+    pub fn with_store(config: BankConfig, store: Box<dyn TransactionStore>) -> Self {
+        let bank = Bank::with_config(config);
         Self {
-            transactions: Arc::new(Mutex::new(HashMap::new())),
-            accounts: Arc::new(Mutex::new(HashMap::new())),
+            transactions: Arc::new(OrderedLock::new(LockRank::Transactions, store).profiled(&bank.profiler)),
+            ..bank
         }
     }
 
     pub fn new_for_tokio(bank: &Bank) -> Self {
         Self {
             transactions: bank.transactions.clone(),
-            accounts: bank.accounts.clone()
+            accounts: bank.accounts.clone(),
+            pending_disputes: bank.pending_disputes.clone(),
+            losses: bank.losses.clone(),
+            fees: bank.fees.clone(),
+            accounts_created: bank.accounts_created.clone(),
+            config: bank.config.clone(),
+            metrics: bank.metrics.clone(),
+            journal: bank.journal.clone(),
+            registry: bank.registry.clone(),
+            activity: bank.activity.clone(),
+            sources: bank.sources.clone(),
+            recent_tx_ids: bank.recent_tx_ids.clone(),
+            invariant_violations: bank.invariant_violations.clone(),
+            max_tx_id: bank.max_tx_id.clone(),
+            tx_seq: bank.tx_seq.clone(),
+            profiler: bank.profiler.clone(),
+        }
+    }
+
+    /// Sends events of processing transactions to the given metrics from now on,
+    /// for this bank and all other handles to it
+    pub fn set_metrics(&self, metrics: Arc<dyn BankMetrics + Send + Sync>) {
+        self.metrics.set(metrics);
+    }
+
+    /// Processes all transactions in the csv at the given path
+    ///
+    /// Rows that fail to parse are skipped and counted in the returned report.
+    /// Fails if the file can't be opened, or if the bank is poisoned, in which case processing stops
+    /// at the transaction that failed to be journaled, see [Bank::is_poisoned].
+    ///
+    /// If the bank has a [processed registry](Bank::with_processed_registry), a file that was
+    /// processed before is skipped and an empty report is returned.
+    #[cfg(feature = "async")]
+    pub async fn process_transactions_from_csv_path(&self, csv_path: &str) -> Result<ProcessReport, BankError> {
+        self.process_transactions_from_csv_path_with_options(csv_path, &ProcessOptions::default()).await
+    }
+
+    /// Processes all transactions in the csv at the given path, reading it according to the given options
+    ///
+    /// The file is processed by [Bank::process_transactions_from_csv_path_with_options_blocking]
+    /// on a blocking thread of the tokio runtime, so its other tasks keep running meanwhile.
+    /// Dropping the returned future doesn't stop the file from being processed to the end.
+    #[cfg(feature = "async")]
+    pub async fn process_transactions_from_csv_path_with_options(&self, csv_path: &str, options: &ProcessOptions) -> Result<ProcessReport, BankError> {
+        let (bank, csv_path, options) = (Bank::new_for_tokio(self), csv_path.to_string(), options.clone());
+        let span = tracing::Span::current();
+        let processing = tokio::task::spawn_blocking(move || {
+            span.in_scope(|| bank.process_transactions_from_csv_path_with_options_blocking(&csv_path, &options))
+        });
+        match processing.await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    /// Processes all transactions in the csv at the given path on the calling thread, without an async runtime
+    ///
+    /// Otherwise the same as [Bank::process_transactions_from_csv_path].
+    pub fn process_transactions_from_csv_path_blocking(&self, csv_path: &str) -> Result<ProcessReport, BankError> {
+        self.process_transactions_from_csv_path_with_options_blocking(csv_path, &ProcessOptions::default())
+    }
+
+    /// Processes all transactions in the csv at the given path on the calling thread, reading it
+    /// according to the given options, without an async runtime
+    ///
+    /// With [ProcessOptions::pipeline] the file is parsed on a thread of its own
+    /// while the transactions parsed so far are applied. With [ProcessOptions::resume] it is
+    /// read from its checkpoint on, if it has one.
+    pub fn process_transactions_from_csv_path_with_options_blocking(&self, csv_path: &str, options: &ProcessOptions) -> Result<ProcessReport, BankError> {
+        let _span = tracing::info_span!("process_csv", path = csv_path).entered();
+        let source = self.register_source(csv_path);
+        let resume_from = if options.resume { Checkpoint::read(csv_path)? } else { None };
+        let checkpoint = options.checkpoint_every
+            .filter(|_| options.ordering == TxOrdering::FileOrder)
+            .map(|every| Checkpoint::new(csv_path, every, self.journal.clone()));
+        let Some(registry) = &self.registry else {
+            let file_reader = match resume_from {
+                Some(point) => TxReader::resumed(File::open(csv_path)?, options, point)?,
+                None => TxReader::from_path(csv_path, options)?,
+            };
+            let report = self.process_records_with(file_reader.with_source(source), options, checkpoint);
+            self.check_journal()?;
+            return Ok(report);
+        };
+        // Hashed in a pass of its own and then processed from the start of the same open file,
+        // so the file is never held in memory
+        let mut file = File::open(csv_path)?;
+        let hash = hex_sha256(&mut file)?;
+        if !registry.lock().unwrap().claim(&hash) {
+            tracing::info!("skipping file that was processed before");
+            return Ok(ProcessReport::default());
+        }
+        let tx_reader = file.rewind().map_err(BankError::from).and_then(|()| match resume_from {
+            Some(point) => TxReader::resumed(file, options, point),
+            None => TxReader::from_reader(file, options).map_err(BankError::from),
+        });
+        let report = match tx_reader {
+            Ok(tx_reader) => self.process_records_with(tx_reader.with_source(source), options, checkpoint),
+            Err(e) => {
+                registry.lock().unwrap().release(&hash);
+                return Err(e);
+            },
+        };
+        if let Err(e) = self.check_journal() {
+            registry.lock().unwrap().release(&hash);
+            return Err(e);
+        }
+        registry.lock().unwrap().record(hash, csv_path, &report)?;
+        Ok(report)
+    }
+
+    /// Processes the transactions read by a [TxReader] in a pipeline if the options ask for one, inline otherwise
+    fn process_records_with<R: io::Read + Send>(&self, tx_reader: TxReader<R>, options: &ProcessOptions, checkpoint: Option<Checkpoint>) -> ProcessReport {
+        match options.pipeline {
+            Some(capacity) => self.process_records_pipelined(tx_reader, options, capacity, checkpoint),
+            None => self.process_records(tx_reader, options, checkpoint),
+        }
+    }
+
+    /// Processes all transactions of csv data from any reader, e.g. the body of a request,
+    /// reading it according to the given options
+    ///
+    /// Fails if the data can't be read at all, or if the bank is poisoned, see [Bank::is_poisoned].
+    pub fn process_transactions_from_reader<R: io::Read>(&self, reader: R, options: &ProcessOptions) -> Result<ProcessReport, BankError> {
+        let tx_reader = TxReader::from_reader(reader, options)?;
+        let report = self.process_records(tx_reader, options, None);
+        self.check_journal()?;
+        Ok(report)
+    }
+
+    /// Processes csv lines held in memory, e.g. buffered from a message queue,
+    /// as if they were the lines of a file. The first line is the header row.
+    pub fn process_lines(lines: &[String], bank: &Bank) -> Result<ProcessReport, BankError> {
+        bank.process_transactions_from_reader(lines.join("\n").as_bytes(), &ProcessOptions::default())
+    }
+
+    /// Processes the transactions read by a [TxReader] and reports on them, keeping the checkpoint if given
+    pub(crate) fn process_records<R: io::Read>(&self, tx_reader: TxReader<R>, options: &ProcessOptions, mut checkpoint: Option<Checkpoint>) -> ProcessReport {
+        let mut tx_reader = tx_reader.with_decimal_places(self.config.decimal_places);
+        let mut report = ProcessReport::default();
+        let mut collected = Vec::new();
+        let started = Instant::now();
+        while let Some(record) = tx_reader.next() {
+            self.process_read_record(&mut report, &mut collected, options, tx_reader.line(), record);
+            if let Some(checkpoint) = &mut checkpoint {
+                checkpoint.row_processed(tx_reader.resume_point());
+            }
+            // Between records, so the bank is not locked
+            report_progress(options, ProgressUpdate { rows: report.rows, bytes: tx_reader.bytes(), elapsed: started.elapsed() });
+            if self.is_poisoned() {
+                break;
+            }
+        }
+        report.bytes = tx_reader.bytes();
+        self.process_collected(&mut report, collected);
+        if let Some(checkpoint) = &mut checkpoint {
+            checkpoint.write(tx_reader.resume_point());
+        }
+        report
+    }
+
+    /// Counts a record read from the given line of a csv in the report and processes it, or collects it
+    /// to be processed once all are read if the options order transactions by timestamp
+    pub(crate) fn process_read_record(
+        &self,
+        report: &mut ProcessReport,
+        collected: &mut Vec<(u64, Tx)>,
+        options: &ProcessOptions,
+        line: u64,
+        record: Result<Tx, LineError>,
+    ) {
+        report.rows += 1;
+        match record {
+            Ok(record) if options.ordering == TxOrdering::Timestamp => collected.push((line, record)),
+            Ok(record) => self.process_record(report, line, record),
+            Err(e) => {
+                tracing::warn!(line = e.line, error = %e.error, "skipped row");
+                self.metrics.get().row_skipped(&e.error);
+                report.record_row_error(e)
+            },
+        }
+    }
+
+    /// Processes the records collected by [Bank::process_read_record] in timestamp order, ties broken by tx id
+    pub(crate) fn process_collected(&self, report: &mut ProcessReport, mut collected: Vec<(u64, Tx)>) {
+        collected.sort_by_key(|(_, record)| (record.timestamp, record.tx));
+        for (line, record) in collected {
+            if self.is_poisoned() {
+                break;
+            }
+            self.process_record(report, line, record);
+        }
+    }
+
+    /// Processes a transaction read from the given line of a csv and records its outcome in the report
+    ///
+    /// With [BankConfig::strict_disputes], a resolve or chargeback rejected as not disputed
+    /// or of an unknown transaction is recorded as a row error instead of a rejection.
+    pub(crate) fn process_record(&self, report: &mut ProcessReport, line: u64, record: Tx) {
+        let (type_, tx) = (record.type_, record.tx);
+        match record.process(self) {
+            Err(RejectionReason::NotDisputed | RejectionReason::UnknownTx)
+                if self.config.strict_disputes && matches!(type_, TxType::Resolve | TxType::Chargeback) => {
+                report.record_row_error(LineError { line, error: RowError::NotDisputed { type_, tx } });
+            },
+            outcome => report.record(outcome),
+        }
+    }
+
+    /// Processes the csvs at the given paths one after the other into a new bank
+    /// and returns its accounts in csv format, ordered by client
+    ///
+    /// Meant for tests that compare the output of whole files against an expected output.
+    #[cfg(feature = "async")]
+    pub async fn run_files_to_string(csv_paths: &[&str]) -> Result<String, BankError> {
+        let bank = Bank::new();
+        for csv_path in csv_paths {
+            bank.process_transactions_from_csv_path(csv_path).await?;
+        }
+        let mut clients: Vec<ClientId> = bank.accounts.lock().keys().copied().collect();
+        clients.sort();
+        let mut output = Vec::new();
+        bank.write_accounts_filtered(&mut output, &clients)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(String::from_utf8(output).expect("csv output is utf-8"))
+    }
+
+    /// Processes a single transaction, e.g. one received over a network request
+    ///
+    /// Returns the reason the transaction was rejected if it was not applied.
+    pub fn apply(&self, tx: TxRequest) -> Result<(), RejectionReason> {
+        Tx::from(tx).process(self)
+    }
+
+    /// Processes a batch of transactions in order, with the same rules as rows of a csv
+    ///
+    /// The returned report holds the outcome of each transaction at the same position as in the batch.
+    pub fn process_batch(&self, batch: Vec<TxRequest>) -> BatchReport {
+        BatchReport {
+            outcomes: batch.into_iter().map(|tx| Tx::from(tx).process(self)).collect(),
+        }
+    }
+
+    /// Runs `f` with the accounts and transaction sheet locked, in the order every code path takes them
+    pub(crate) fn with_accounts_and_sheet<T>(&self, f: impl FnOnce(&mut Accounts, &mut Transactions) -> T) -> T {
+        let mut accounts = self.accounts.lock();
+        let mut transactions = self.transactions.lock();
+        f(&mut accounts, &mut transactions)
+    }
+
+    /// Removes all accounts, transactions, parked disputes, recorded chargeback losses and collected fees,
+    /// and all entries of the bank's journal if it has one
+    ///
+    /// Other handles to this bank, e.g. ones from [Bank::new_for_tokio], see the empty bank too.
+    pub fn clear(&self) {
+        self.with_accounts_and_sheet(|accounts, transactions| {
+            accounts.clear();
+            transactions.clear();
+            self.pending_disputes.lock().clear();
+            self.losses.lock().clear();
+            self.fees.lock().clear();
+        });
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.clear() {
+                tracing::error!(error = %e, "failed to clear journal, the bank is poisoned");
+            }
+        }
+    }
+
+    /// Removes a client's account along with all of their transactions and parked disputes,
+    /// so a later dispute can't recreate the account from a stale transaction.
+    ///
+    /// Returns the final state of the account, if it existed.
+    pub fn remove_account(&self, client: ClientId) -> Option<AccountSnapshot> {
+        self.with_accounts_and_sheet(|accounts, transactions| {
+            let account = accounts.remove(&client)?;
+            transactions.remove_client(client);
+            self.pending_disputes.lock().remove_client(client);
+            let _ = self.append_to_journal(&JournalEntry::RemoveAccount { client });
+            Some(account.snapshot())
+        })
+    }
+
+    /// Closes a client's account that has no held funds and no open disputes,
+    /// removing it along with their transactions and parked disputes like [Bank::remove_account]
+    ///
+    /// Returns the final state of the account, whose available balance is what is left to pay out,
+    /// or None if the client has no account.
+    pub fn close_account(&self, client: ClientId) -> Result<Option<AccountSnapshot>, CloseAccountError> {
+        self.with_accounts_and_sheet(|accounts, transactions| {
+            let Some(account) = accounts.get(&client) else {
+                return Ok(None);
+            };
+            if account.held.value != 0 || account.currencies.values().any(|balance| balance.held.value != 0) {
+                return Err(CloseAccountError::HeldFunds);
+            }
+            if transactions.has_disputed(client) {
+                return Err(CloseAccountError::OpenDisputes);
+            }
+            let account = accounts.remove(&client).unwrap();
+            transactions.remove_client(client);
+            self.pending_disputes.lock().remove_client(client);
+            let _ = self.append_to_journal(&JournalEntry::RemoveAccount { client });
+            Ok(Some(account.snapshot()))
+        })
+    }
+
+    /// Creates an empty account for a client, numbered after every account created before it
+    ///
+    /// Only called while the accounts are locked, so the numbers follow the order accounts were inserted in.
+    pub(crate) fn new_account(&self, client: ClientId) -> Account {
+        let created_seq = self.accounts_created.fetch_add(1, Ordering::Relaxed) + 1;
+        Account { created_seq, ..Account::new(client) }
+    }
+
+    /// The id of the file at the given path, registering the path the first time it is seen
+    pub(crate) fn register_source(&self, path: &str) -> SourceId {
+        let mut sources = self.sources.lock().unwrap();
+        let index = sources.iter().position(|source| source == path).unwrap_or_else(|| {
+            sources.push(path.to_string());
+            sources.len() - 1
+        });
+        SourceId(index as u32)
+    }
+
+    /// The path of the file transactions with the given source were read from, see [Tx::source]
+    pub fn source_path(&self, source: SourceId) -> Option<String> {
+        self.sources.lock().unwrap().get(source.0 as usize).cloned()
+    }
+
+    /// How amounts are written in the given format, with the bank's [BankConfig::decimal_places]
+    pub(crate) fn amount_style(&self, format: AmountOutputFormat) -> AmountStyle {
+        AmountStyle { decimal_places: self.config.decimal_places, format, currency_places: None }
+    }
+
+    /// The highest tx id of any transaction processed so far, applied or not, or None before the first
+    ///
+    /// Tx ids are 32 bit, so this shows how close a feed with increasing ids is to running out of them.
+    pub fn max_seen_tx_id(&self) -> Option<TxId> {
+        match self.max_tx_id.load(Ordering::Relaxed) {
+            0 => None,
+            next => Some(TxId((next - 1) as u32)),
         }
     }
 
-    pub async fn process_transactions_from_csv_path(csv_path: &str, mut bank: Bank) {
-        let mut file_reader = Bank::get_csv_reader(&csv_path)
-            .expect(&format!("Failed to open csv {}", csv_path));
-        for record in file_reader.deserialize() {
-            let record: Tx = record.expect("Invalid raw data for transaction");
-            record.process(&mut bank);
+    /// Records that a transaction with the given id was processed, see [Bank::max_seen_tx_id]
+    pub(crate) fn record_seen_tx_id(&self, id: TxId) {
+        self.max_tx_id.fetch_max(id.0 as u64 + 1, Ordering::Relaxed);
+    }
+
+    /// Number of deposits and withdrawals stored on the transaction sheet so far
+    pub(crate) fn tx_seq(&self) -> u64 {
+        self.tx_seq.load(Ordering::Relaxed)
+    }
+
+    /// The sequence number of a deposit or withdrawal about to be stored on the transaction sheet,
+    /// called while the sheet is locked so numbers follow the order transactions are stored in
+    pub(crate) fn next_tx_seq(&self) -> u64 {
+        self.tx_seq.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Number of disputes, resolves and chargebacks waiting for the transaction they reference
+    pub fn pending_dispute_count(&self) -> usize {
+        self.pending_disputes.lock().len()
+    }
+
+    /// Amount each client's available balance went below zero because of a chargeback,
+    /// e.g. when the disputed deposit had already been withdrawn
+    ///
+    /// Amounts are in the bank's [internal integer representation](Amount).
+    pub fn chargeback_losses(&self) -> HashMap<ClientId, AmountValue> {
+        self.losses.lock().iter()
+            .map(|(client, loss)| (*client, loss.value))
+            .collect()
+    }
+
+    /// Fees collected on withdrawals in each currency, see [BankConfig::fee_schedule]
+    ///
+    /// Amounts are in the bank's [internal integer representation](Amount).
+    pub fn collected_fees(&self) -> HashMap<CurrencyCode, AmountValue> {
+        self.fees.lock().clone()
+    }
+
+    /// Counts of what the bank currently holds
+    pub fn stats(&self) -> BankStats {
+        let accounts = self.accounts.lock();
+        let transactions = self.transactions.lock();
+        let pending_disputes = self.pending_disputes.lock();
+        let losses = self.losses.lock();
+        let fees = self.fees.lock();
+        BankStats {
+            accounts: accounts.len(),
+            locked_accounts: accounts.values().filter(|account| account.locked).count(),
+            transactions: transactions.len(),
+            pending_disputes: pending_disputes.len(),
+            chargeback_losses: losses.values().fold(0, |sum: AmountValue, loss| sum.saturating_add(loss.value)),
+            held_funds: accounts.values().fold(0, |sum: AmountValue, account| sum.saturating_add(account.held.value)),
+            collected_fees: fees.get(&self.config.base_currency).copied().unwrap_or(0),
+            invariant_violations: self.invariant_violations.load(Ordering::Relaxed),
         }
     }
 
-    fn get_csv_reader(csv_path: &str) -> Result<Reader<File>, Box<dyn Error>>  {
-        Ok(ReaderBuilder::new()
-            .has_headers(true)
-            .trim(csv::Trim::All)
-            .flexible(true)
-            .from_path(csv_path)?
-        )
+    /// The bank's [stats](Bank::stats) in the Prometheus text exposition format
+    pub fn metrics_text(&self) -> String {
+        let stats = self.stats();
+        let places = self.config.decimal_places;
+        let metrics = [
+            ("bank_accounts", "gauge", "Number of client accounts", stats.accounts.to_string()),
+            ("bank_accounts_locked", "gauge", "Number of locked client accounts", stats.locked_accounts.to_string()),
+            ("bank_transactions", "gauge", "Number of deposits and withdrawals on the transaction sheet", stats.transactions.to_string()),
+            ("bank_pending_disputes", "gauge", "Number of disputes waiting for the transaction they reference", stats.pending_disputes.to_string()),
+            ("bank_held_funds", "gauge", "Funds held by disputes in the base currency", format_amount(stats.held_funds, places)),
+            ("bank_chargeback_losses", "gauge", "Funds lost to chargebacks of already spent deposits", format_amount(stats.chargeback_losses, places)),
+            ("bank_collected_fees", "counter", "Fees collected on withdrawals in the base currency", format_amount(stats.collected_fees, places)),
+        ];
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            text.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value));
+        }
+        text
     }
 
     /// Outputs the bank's accounts to stdout in csv format
     pub fn write_accounts(&self) -> Result<(), Box<dyn Error>> {
-        let mut wtr = csv::Writer::from_writer(io::stdout());
-        for account in self.accounts.lock().unwrap().values_mut() {
-            account.calculate_total();
-            wtr.serialize(account).unwrap();
+        self.write_accounts_to(io::stdout())
+    }
+
+    /// Outputs the bank's accounts to the given writer in csv format
+    ///
+    /// The header row is always written, even if the bank has no accounts.
+    /// If every transaction was in the base currency the output has one row per client.
+    /// Otherwise a currency column is added after the client column and there is one
+    /// row per client per currency the client holds funds in.
+    /// With [BankConfig::extended_output] an `open_disputes` column is added at the end.
+    ///
+    /// Fails if the writer does, e.g. on a closed pipe or a full disk, after flushing what it could.
+    pub fn write_accounts_to<W: io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let clients: Vec<ClientId> = self.accounts.lock().keys().copied().collect();
+        self.write_accounts_filtered(writer, &clients)?;
+        Ok(())
+    }
+
+    /// Outputs only the accounts of the given clients, in the given order, to the writer in csv format
+    ///
+    /// The output is formatted like [Bank::write_accounts_to], so it has a currency column
+    /// if any account in the bank holds funds in another currency.
+    /// Clients without an account are skipped and listed in the returned report.
+    pub fn write_accounts_filtered<W: io::Write>(&self, writer: W, clients: &[ClientId]) -> Result<OutputReport, Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        let report = self.for_each_account_row(clients, |row| Ok(wtr.serialize(row)?))?;
+        if report.written == 0 {
+            // The csv writer only writes headers along with the first record
+            if self.config.extended_output {
+                wtr.write_record(EXTENDED_ACCOUNT_HEADERS)?;
+            } else {
+                wtr.write_record(ACCOUNT_HEADERS)?;
+            }
+        }
+        wtr.flush()?;
+        Ok(report)
+    }
+
+    /// Outputs the bank's accounts to the given writer as a JSON array
+    ///
+    /// There is an object per row of [Bank::write_accounts_to], with the columns as fields.
+    pub fn write_accounts_json<W: io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let clients: Vec<ClientId> = self.accounts.lock().keys().copied().collect();
+        self.write_accounts_json_filtered(writer, &clients)?;
+        Ok(())
+    }
+
+    /// Outputs only the accounts of the given clients, in the given order, to the writer as a JSON array,
+    /// see [Bank::write_accounts_filtered]
+    pub fn write_accounts_json_filtered<W: io::Write>(&self, mut writer: W, clients: &[ClientId]) -> Result<OutputReport, Box<dyn Error>> {
+        let mut serializer = serde_json::Serializer::new(&mut writer);
+        let mut array = serializer.serialize_seq(None)?;
+        let report = self.for_each_account_row(clients, |row| Ok(array.serialize_element(&row)?))?;
+        array.end()?;
+        writeln!(writer)?;
+        Ok(report)
+    }
+
+    /// Passes each output row of the given clients' accounts to `emit`, in the layout described at [Bank::write_accounts_to]
+    fn for_each_account_row(
+        &self,
+        clients: &[ClientId],
+        mut emit: impl FnMut(AccountRow) -> Result<(), Box<dyn Error>>,
+    ) -> Result<OutputReport, Box<dyn Error>> {
+        let mut accounts = self.accounts.lock();
+        let single_currency = accounts.values().all(|account| account.currencies.is_empty());
+        let mut report = OutputReport::default();
+        for client in clients {
+            let Some(account) = accounts.get_mut(client) else {
+                tracing::warn!(client = client.0, "no account to write for client");
+                report.unknown_clients.push(*client);
+                continue;
+            };
+            let clamp = self.config.clamp_negative_totals;
+            let extended = self.config.extended_output;
+            let style = self.amount_style(self.config.amount_output_format);
+            if single_currency {
+                account.calculate_total();
+                if clamp {
+                    account.total.value = account.total.value.max(0);
+                }
+                let style = style.in_currency(self.config.base_currency, &self.config.currency_decimal_places);
+                let mut row = BaseAccountRow::new(account, style);
+                row.open_disputes = extended.then_some(account.open_disputes);
+                emit(AccountRow::Base(row))?;
+            } else {
+                for mut row in account.currency_rows(self.config.base_currency, style, &self.config.currency_decimal_places) {
+                    if clamp {
+                        row.total.value = row.total.value.max(0);
+                    }
+                    row.open_disputes = extended.then_some(account.open_disputes);
+                    emit(AccountRow::Currency(row))?;
+                }
+            }
+            report.written += 1;
+        }
+        Ok(report)
+    }
+
+    /// Outputs a client's statement to the given writer in csv format: every transaction applied
+    /// to their account in order, with the available and held balances it left behind
+    /// and the file it was read from, if any
+    ///
+    /// Disputes, resolves and chargebacks appear with the id and amount of the transaction they reference.
+    /// Transactions are only recorded while [BankConfig::record_statements] is on, so the statement
+    /// has only the header row if it is off or the client has no account.
+    pub fn write_statement<W: io::Write>(&self, client: ClientId, writer: W) -> Result<(), BankError> {
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+        wtr.write_record(STATEMENT_HEADERS)?;
+        let sources = self.sources.lock().unwrap().clone();
+        if let Some(account) = self.accounts.lock().get(&client) {
+            for entry in &account.statement {
+                wtr.serialize(StatementRow::new(entry, &sources, self.amount_style(AmountOutputFormat::Decimal)))?;
+            }
         }
         wtr.flush()?;
         Ok(())
     }
 
+    /// Outputs the transaction sheet to the given writer in csv format, sorted by tx id,
+    /// with whether each transaction is currently disputed and the file it was read from, if any
+    ///
+    /// The header row is always written, even if the sheet is empty.
+    pub fn write_transactions<W: io::Write>(&self, writer: W) -> Result<(), BankError> {
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+        wtr.write_record(TRANSACTION_HEADERS)?;
+        let sources = self.sources.lock().unwrap().clone();
+        let style = self.amount_style(AmountOutputFormat::Decimal);
+        let mut rows: Vec<StoredTx> = self.transactions.lock().iter().collect();
+        rows.sort_by_key(|tx| tx.tx);
+        for tx in rows {
+            let source = source_name(tx.source, &sources);
+            let style = style.in_currency(tx.currency_or(self.config.base_currency), &self.config.currency_decimal_places);
+            let amount = OutputAmount::new(&Amount { value: tx.amount }, style);
+            wtr.serialize(TransactionRow { tx: tx.tx, client: tx.client, type_: tx.type_, amount, disputed: tx.disputed, source })?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Writes each account to its own `client_<id>.csv` file in the given directory
+    ///
+    /// Each file is formatted like the output of [Bank::write_accounts_to] for just that client.
+    /// Existing files are overwritten.
+    pub fn write_accounts_per_client(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let clients: Vec<ClientId> = self.accounts.lock().keys().copied().collect();
+        for client in clients {
+            let file = File::create(dir.join(format!("client_{}.csv", client)))?;
+            self.write_accounts_filtered(file, &[client])?;
+        }
+        Ok(())
+    }
+
+    /// A handle that can only read the bank, e.g. to pass to reporting code that must not change it
+    pub fn read_view(&self) -> BankReadView<'_> {
+        BankReadView { bank: self }
+    }
+
+    /// Returns a copy of the current state of a client's account, if it exists
+    pub fn account_snapshot(&self, client: ClientId) -> Option<AccountSnapshot> {
+        self.accounts.lock().get(&client).map(Account::snapshot)
+    }
+
+    /// The client's deposits minus their withdrawals in the base currency over the transaction sheet,
+    /// whatever was disputed, resolved or charged back since, zero for a client without transactions
+    ///
+    /// Withdrawals rejected for insufficient funds are kept on the sheet but are not subtracted,
+    /// as they never took anything, and can't be disputed either. Fees are not included.
+    pub fn net_position(&self, client: ClientId) -> AmountValue {
+        let base_currency = self.config.base_currency;
+        self.transactions.lock().iter()
+            .filter(|tx| tx.client == client && !tx.failed && tx.currency_or(base_currency) == base_currency)
+            .map(|tx| match tx.type_ {
+                TxType::Deposit => tx.amount,
+                TxType::Withdrawal => -tx.amount,
+                _ => 0,
+            })
+            .fold(0, AmountValue::saturating_add)
+    }
+
+    /// The clients whose account is locked, sorted by client id
+    pub fn locked_clients(&self) -> Vec<ClientId> {
+        let mut clients: Vec<ClientId> = self.accounts.lock().values()
+            .filter(|account| account.locked)
+            .map(|account| account.client)
+            .collect();
+        clients.sort();
+        clients
+    }
+
+    /// Opens an empty account for a client, required before their first transaction
+    /// if [BankConfig::strict_accounts] is set
+    ///
+    /// Returns false if the client already has an account or is one of [BankConfig::reserved_clients].
+    pub fn open_account(&self, client: ClientId) -> bool {
+        self.ensure_clients(&[client]) == 1
+    }
+
+    /// Opens an empty account for each of the clients that doesn't have one, so a known roster of
+    /// clients shows up in the output even without transactions, returning the number of accounts opened
+    ///
+    /// Clients in [BankConfig::reserved_clients] are skipped, as they never get an account.
+    pub fn ensure_clients(&self, ids: &[ClientId]) -> usize {
+        let mut accounts = self.accounts.lock();
+        let mut opened = Vec::new();
+        for &client in ids {
+            if accounts.contains_key(&client) || self.config.reserved_clients.contains(&client) {
+                continue;
+            }
+            accounts.insert(client, self.new_account(client));
+            self.log_activity(ActivityKind::AccountCreated, client, None, None);
+            opened.push(client);
+        }
+        let count = opened.len();
+        if count > 0 {
+            let _ = self.append_to_journal(&JournalEntry::OpenAccounts { clients: opened });
+        }
+        count
+    }
+
+}
+
+/// A point in time summary of a bank
+///
+/// Amounts are in the bank's [internal integer representation](Amount).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BankStats {
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    /// Deposits and withdrawals on the transaction sheet
+    pub transactions: usize,
+    pub pending_disputes: usize,
+    /// Total of [Bank::chargeback_losses] over all clients, saturating at the largest amount
+    pub chargeback_losses: AmountValue,
+    /// Total held balance of all accounts in the base currency, saturating at the largest amount
+    pub held_funds: AmountValue,
+    /// Fees collected on withdrawals in the base currency
+    pub collected_fees: AmountValue,
+    /// Number of times an account's balances didn't add up, always zero unless [BankConfig::debug_invariants] is on
+    pub invariant_violations: u64,
+}
+
+/// Borrows a bank to read it without being able to change it, see [Bank::read_view]
+#[derive(Debug, Clone, Copy)]
+pub struct BankReadView<'a> {
+    bank: &'a Bank,
+}
+
+impl BankReadView<'_> {
+    /// See [Bank::account_snapshot]
+    pub fn account_snapshot(&self, client: ClientId) -> Option<AccountSnapshot> {
+        self.bank.account_snapshot(client)
+    }
+
+    /// Copies of all accounts sorted by client id, taken at once so later changes to the bank don't show
+    pub fn iter_accounts(&self) -> impl Iterator<Item = AccountSnapshot> {
+        let mut snapshots: Vec<AccountSnapshot> = self.bank.accounts.lock().values().map(Account::snapshot).collect();
+        snapshots.sort_by_key(|snapshot| snapshot.client);
+        snapshots.into_iter()
+    }
+
+    /// See [Bank::stats]
+    pub fn stats(&self) -> BankStats {
+        self.bank.stats()
+    }
+}
+
+/// Calls [ProcessOptions::on_progress] if the update is at the end of one of its intervals
+pub(crate) fn report_progress(options: &ProcessOptions, update: ProgressUpdate) {
+    if let Some(on_progress) = &options.on_progress {
+        if update.rows.is_multiple_of(options.progress_interval.max(1)) {
+            on_progress(update);
+        }
+    }
+}
+
+/// Formats an amount in the internal representation with the given decimal places as a decimal number,
+/// exactly as the csv output writes it
+fn format_amount(value: AmountValue, decimal_places: u32) -> String {
+    Amount::from_scaled(value).to_short_decimal_with(decimal_places)
+}
+
+/// A point in time copy of a client's account
+///
+/// Amounts are in the bank's [internal integer representation](Amount).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountSnapshot {
+    pub client: ClientId,
+    pub available: AmountValue,
+    pub held: AmountValue,
+    pub total: AmountValue,
+    pub locked: bool,
+    /// The highest available balance the account has reached
+    pub peak_available: AmountValue,
+    /// The highest held balance the account has reached
+    pub peak_held: AmountValue,
+    /// Number of the client's transactions currently disputed
+    pub open_disputes: u32,
+    /// Position of the account in the order accounts were created in, starting at 1
+    pub created_seq: u64,
 }
 
 /// The account state of a client
@@ -67,13 +906,38 @@ impl Bank {
 /// The client id is only used for writing to stdout
 /// The total balance is only used for writing to stdout
 /// So both can be optimized away, but this is more readable for now.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub(crate) struct Account {
     pub(crate) client: ClientId,
     pub(crate) available: Amount,
     pub(crate) held: Amount,
     pub(crate) total: Amount,
     pub(crate) locked: bool,
+    /// Balances in currencies other than the bank's base currency
+    #[serde(skip)]
+    pub(crate) currencies: BTreeMap<CurrencyCode, CurrencyBalance>,
+    #[serde(skip)]
+    pub(crate) peak_available: AmountValue,
+    #[serde(skip)]
+    pub(crate) peak_held: AmountValue,
+    /// Every transaction applied to the account in order, if the bank records statements
+    #[serde(skip)]
+    pub(crate) statement: Vec<StatementEntry>,
+    /// Number of the client's transactions currently disputed, raised by disputes
+    /// and lowered by resolves and chargebacks
+    #[serde(skip)]
+    pub(crate) open_disputes: u32,
+    /// See [AccountSnapshot::created_seq], zero for accounts not created by a bank
+    #[serde(skip)]
+    pub(crate) created_seq: u64,
+    /// Deposits made over the life of the account by the file they were read from and currency,
+    /// see [crate::config::Limits::max_cumulative_deposit]
+    #[serde(skip)]
+    pub(crate) deposited: BTreeMap<(Option<SourceId>, CurrencyCode), AmountValue>,
+    /// Deposits less withdrawals, fees and chargebacks by currency, what available plus held should add up to.
+    /// Only kept with [BankConfig::debug_invariants] on.
+    #[serde(skip)]
+    pub(crate) net_flows: BTreeMap<CurrencyCode, i128>,
 }
 
 impl Account {
@@ -84,92 +948,2217 @@ impl Account {
             held: Amount::new(),
             total: Amount::new(),
             locked: false,
+            currencies: BTreeMap::new(),
+            peak_available: 0,
+            peak_held: 0,
+            statement: Vec::new(),
+            open_disputes: 0,
+            created_seq: 0,
+            deposited: BTreeMap::new(),
+            net_flows: BTreeMap::new(),
         }
     }
 
-    /// Calculates the total balance of the account. Used for writing display output.
-    pub(crate) fn calculate_total(&mut self) {
-        self.total.value = self.available.value + self.held.value;
+    /// Raises the peak balances to the current balances if they have been exceeded.
+    /// Called after every change to the account's balances, which in debug builds checks that no held balance went negative.
+    pub(crate) fn update_peaks(&mut self) {
+        debug_assert!(self.held_not_negative(), "held balance of client {} went negative", self.client.0);
+        self.peak_available = self.peak_available.max(self.available.value);
+        self.peak_held = self.peak_held.max(self.held.value);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::bank::{Account, Bank};
-    use crate::shared_types::Amount;
-    use crate::transaction::{Tx, TxType};
+    /// Whether the held balance is zero or more in every currency, which resolves and chargebacks can't change
+    fn held_not_negative(&self) -> bool {
+        self.held.value >= 0 && self.currencies.values().all(|balance| balance.held.value >= 0)
+    }
 
-    #[test]
-    fn test_new_for_tokio_bank_data_different_address() {
-        // Make banks
-        let bank = Bank::new();
-        let tokio_bank = Bank::new_for_tokio(&bank);
-        let tokio_bank_2 = Bank::new_for_tokio(&bank);
+    /// Adds a transaction that was just applied to the account's statement,
+    /// along with the balances in its currency it left behind
+    pub(crate) fn record_statement(&mut self, tx: TxId, type_: TxType, amount: AmountValue, currency: CurrencyCode, base_currency: CurrencyCode, source: Option<SourceId>) {
+        let (available, held) = self.balances_mut(currency, base_currency);
+        let (available_after, held_after) = (*available, *held);
+        self.statement.push(StatementEntry { tx, type_, amount: Amount { value: amount }, available_after, held_after, source });
+    }
 
-        assert_ne!(std::ptr::addr_of!(bank), std::ptr::addr_of!(tokio_bank));
-        assert_ne!(std::ptr::addr_of!(bank.transactions), std::ptr::addr_of!(tokio_bank.transactions));
-        assert_ne!(std::ptr::addr_of!(bank.accounts), std::ptr::addr_of!(tokio_bank.accounts));
+    /// Returns the available and held balances of the account in the given currency
+    ///
+    /// The base currency balances are the account's own, any other currency
+    /// gets a zero balance the first time it is used.
+    pub(crate) fn balances_mut(&mut self, currency: CurrencyCode, base_currency: CurrencyCode) -> (&mut Amount, &mut Amount) {
+        if currency == base_currency {
+            (&mut self.available, &mut self.held)
+        } else {
+            let balance = self.currencies.entry(currency).or_default();
+            (&mut balance.available, &mut balance.held)
+        }
+    }
 
-        assert_ne!(std::ptr::addr_of!(tokio_bank), std::ptr::addr_of!(tokio_bank_2));
-        assert_ne!(std::ptr::addr_of!(tokio_bank.transactions), std::ptr::addr_of!(tokio_bank_2.transactions));
-        assert_ne!(std::ptr::addr_of!(tokio_bank.accounts), std::ptr::addr_of!(tokio_bank_2.accounts));
+    /// The available balance in the given currency
+    pub(crate) fn available_in(&self, currency: CurrencyCode, base_currency: CurrencyCode) -> AmountValue {
+        if currency == base_currency {
+            self.available.value
+        } else {
+            self.currencies.get(&currency).map_or(0, |balance| balance.available.value)
+        }
     }
 
-    #[test]
-    fn test_new_for_tokio_same_data() {
-        // Make banks
-        let bank = Bank::new();
-        let tokio_bank = Bank::new_for_tokio(&bank);
-        let tokio_bank_2 = Bank::new_for_tokio(&bank);
-        // Make sample tx
-        let tx = Tx {
-            type_: TxType::Deposit,
-            client: 0,
-            tx: 0,
-            amount: Amount { value: 500 },
-            disputed: false
-        };
-        // Insert sample tx
-        tokio_bank_2.transactions.lock().unwrap().insert(0, tx);
+    /// Adds a deposit to the available balance in the given currency
+    ///
+    /// Refused if the account is locked or the balance would go beyond the range of amounts.
+    pub(crate) fn deposit(&mut self, amount: Amount, currency: CurrencyCode, base_currency: CurrencyCode) -> Result<(), RejectionReason> {
+        if self.locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+        let (available, _) = self.balances_mut(currency, base_currency);
+        available.value = available.value.checked_add(amount.value).ok_or(RejectionReason::Overflow)?;
+        self.update_peaks();
+        Ok(())
+    }
 
-        // Get data
-        let bank_amount = bank.transactions.lock().unwrap().get(&0).unwrap().amount.value.clone();
-        let tokio_bank_amount = tokio_bank.transactions.lock().unwrap().get(&0).unwrap().amount.value.clone();
-        let tokio_bank_2_amount = tokio_bank_2.transactions.lock().unwrap().get(&0).unwrap().amount.value.clone();
+    /// Takes a withdrawal and its fee from the available balance in the given currency
+    ///
+    /// Refused if the account is locked or the available balance doesn't cover both.
+    pub(crate) fn withdraw(&mut self, amount: Amount, fee: Amount, currency: CurrencyCode, base_currency: CurrencyCode) -> Result<(), RejectionReason> {
+        if self.locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+        let (available, _) = self.balances_mut(currency, base_currency);
+        available.value = available.value
+            .checked_sub(amount.value)
+            .and_then(|value| value.checked_sub(fee.value))
+            .filter(|&value| value >= 0)
+            .ok_or(RejectionReason::InsufficientFunds)?;
+        self.update_peaks();
+        Ok(())
+    }
 
-        // Compare data
-        assert_eq!(tokio_bank_amount, tokio_bank_2_amount);
-        assert_eq!(bank_amount, tokio_bank_2_amount);
+    /// Moves a disputed amount from the available to the held balance in the given currency.
+    /// The available balance can go negative, e.g. when the disputed deposit was already withdrawn.
+    ///
+    /// Refused if the account is locked, the held balance would go below zero for a negative amount
+    /// or either balance would go beyond the range of amounts.
+    pub(crate) fn hold(&mut self, amount: Amount, currency: CurrencyCode, base_currency: CurrencyCode) -> Result<(), RejectionReason> {
+        if self.locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+        let (available, held) = self.balances_mut(currency, base_currency);
+        let (Some(new_available), Some(new_held)) = (available.value.checked_sub(amount.value), held.value.checked_add(amount.value)) else {
+            return Err(RejectionReason::Overflow);
+        };
+        if new_held < 0 {
+            return Err(RejectionReason::InsufficientHeld);
+        }
+        available.value = new_available;
+        held.value = new_held;
+        self.update_peaks();
+        Ok(())
     }
 
-    #[test]
-    fn test_calculate_total_avail_only() {
-        let mut account = Account::new(1);
-        account.available.value = 20;
+    /// Moves a disputed amount back from the held to the available balance in the given currency
+    ///
+    /// Refused if the account is locked, the held balance doesn't cover the amount
+    /// or the available balance would go beyond the range of amounts.
+    pub(crate) fn release(&mut self, amount: Amount, currency: CurrencyCode, base_currency: CurrencyCode) -> Result<(), RejectionReason> {
+        if self.locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+        let (available, held) = self.balances_mut(currency, base_currency);
+        if held.value < amount.value {
+            return Err(RejectionReason::InsufficientHeld);
+        }
+        available.value = available.value.checked_add(amount.value).ok_or(RejectionReason::Overflow)?;
+        held.value -= amount.value;
+        self.update_peaks();
+        Ok(())
+    }
 
-        account.calculate_total();
+    /// Charges back `charged` of a `disputed` amount held in the given currency and locks the account.
+    /// The rest of the disputed amount is released or stays held as `remainder` says.
+    ///
+    /// Returns how far the available balance is below zero afterwards, which the bank has lost.
+    /// Refused if the account is locked, `charged` is negative or more than `disputed`,
+    /// the held balance doesn't cover the disputed amount, or releasing the rest would overflow
+    /// the available balance.
+    pub(crate) fn charge_back(
+        &mut self,
+        disputed: Amount,
+        charged: Amount,
+        remainder: PartialChargebackRemainder,
+        currency: CurrencyCode,
+        base_currency: CurrencyCode,
+    ) -> Result<AmountValue, RejectionReason> {
+        if self.locked {
+            return Err(RejectionReason::AccountLocked);
+        }
+        if charged.value < 0 || charged.value > disputed.value {
+            return Err(RejectionReason::InvalidChargebackAmount);
+        }
+        let (available, held) = self.balances_mut(currency, base_currency);
+        if held.value < disputed.value {
+            return Err(RejectionReason::InsufficientHeld);
+        }
+        let released = match remainder {
+            PartialChargebackRemainder::Release => disputed.value - charged.value,
+            PartialChargebackRemainder::Hold => 0,
+        };
+        // Checked before anything changes, so a refused chargeback leaves the account as it was
+        available.value = available.value.checked_add(released).ok_or(RejectionReason::Overflow)?;
+        held.value -= charged.value + released;
+        let shortfall = available.value.min(0).saturating_neg();
+        self.locked = true;
+        self.update_peaks();
+        Ok(shortfall)
+    }
 
-        assert_eq!(account.total.value, account.available.value)
+    /// The output rows of this account for a bank that has seen more than one currency
+    ///
+    /// The base currency row is left out if the client never held any funds in it.
+    /// Amounts are written with the decimal places of their currency in `currency_places`, if it has any.
+    fn currency_rows(&self, base_currency: CurrencyCode, style: AmountStyle, currency_places: &HashMap<CurrencyCode, u32>) -> Vec<CurrencyAccountRow> {
+        self.balances(base_currency).into_iter()
+            .map(|(currency, available, held)| CurrencyAccountRow::new(self, currency, available, held, style.in_currency(currency, currency_places)))
+            .collect()
     }
 
-    #[test]
-    fn test_calculate_total_held_only() {
-        let mut account = Account::new(1);
-        account.held.value = 20;
+    /// The available and held balance of each currency of this account, the base currency first
+    ///
+    /// The base currency is left out if the client never held any funds in it but did in another currency.
+    pub(crate) fn balances(&self, base_currency: CurrencyCode) -> Vec<(CurrencyCode, &Amount, &Amount)> {
+        let mut balances = Vec::new();
+        if self.available.value != 0 || self.held.value != 0 || self.currencies.is_empty() {
+            balances.push((base_currency, &self.available, &self.held));
+        }
+        for (currency, balance) in &self.currencies {
+            balances.push((*currency, &balance.available, &balance.held));
+        }
+        balances
+    }
 
-        account.calculate_total();
+    pub(crate) fn snapshot(&self) -> AccountSnapshot {
+        AccountSnapshot {
+            client: self.client,
+            available: self.available.value,
+            held: self.held.value,
+            total: self.available.value.saturating_add(self.held.value),
+            locked: self.locked,
+            peak_available: self.peak_available,
+            peak_held: self.peak_held,
+            open_disputes: self.open_disputes,
+            created_seq: self.created_seq,
+        }
+    }
 
-        assert_eq!(account.total.value, account.held.value)
+    /// Calculates the total balance of the account. Used for writing display output.
+    ///
+    /// The total is always available plus held. Available can be negative, e.g. when a deposit
+    /// that was already withdrawn is disputed, in which case the total can be negative too.
+    pub(crate) fn calculate_total(&mut self) {
+        self.total.value = self.available.value.saturating_add(self.held.value);
     }
+}
 
-    #[test]
-    fn test_calculate_total_both() {
-        let mut account = Account::new(1);
-        account.available.value = 20;
-        account.held.value = 10;
+/// A row of a client's statement, see [Bank::write_statement]
+#[derive(Debug, Clone)]
+pub(crate) struct StatementEntry {
+    tx: TxId,
+    type_: TxType,
+    amount: Amount,
+    available_after: Amount,
+    held_after: Amount,
+    /// The file the transaction was read from
+    pub(crate) source: Option<SourceId>,
+}
 
-        account.calculate_total();
+/// A row of a statement output, a [StatementEntry] with the path of its source
+#[derive(Serialize)]
+struct StatementRow<'a> {
+    tx: TxId,
+    #[serde(rename = "type")]
+    type_: TxType,
+    amount: OutputAmount,
+    available_after: OutputAmount,
+    held_after: OutputAmount,
+    source: Option<&'a str>,
+}
 
-        assert_eq!(account.total.value, account.available.value + account.held.value)
+impl<'a> StatementRow<'a> {
+    fn new(entry: &StatementEntry, sources: &'a [String], style: AmountStyle) -> Self {
+        Self {
+            tx: entry.tx,
+            type_: entry.type_,
+            amount: OutputAmount::new(&entry.amount, style),
+            available_after: OutputAmount::new(&entry.available_after, style),
+            held_after: OutputAmount::new(&entry.held_after, style),
+            source: source_name(entry.source, sources),
+        }
     }
-}
\ No newline at end of file
+}
+
+/// A row of the transaction sheet output, see [Bank::write_transactions]
+#[derive(Serialize)]
+struct TransactionRow<'a> {
+    tx: TxId,
+    client: ClientId,
+    #[serde(rename = "type")]
+    type_: TxType,
+    amount: OutputAmount,
+    disputed: bool,
+    source: Option<&'a str>,
+}
+
+/// The path of a source among the bank's registered paths
+fn source_name(source: Option<SourceId>, sources: &[String]) -> Option<&str> {
+    source.and_then(|source| sources.get(source.0 as usize)).map(String::as_str)
+}
+
+/// The columns of a statement
+const STATEMENT_HEADERS: [&str; 6] = ["tx", "type", "amount", "available_after", "held_after", "source"];
+
+/// The balances of an account in a currency other than the bank's base currency
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CurrencyBalance {
+    pub(crate) available: Amount,
+    pub(crate) held: Amount,
+}
+
+/// How the amounts of an output are written, see [Bank::amount_style]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AmountStyle {
+    decimal_places: u32,
+    format: AmountOutputFormat,
+    /// Exact number of decimal places to write decimals with, see [BankConfig::currency_decimal_places]
+    currency_places: Option<u32>,
+}
+
+impl AmountStyle {
+    /// This style for amounts in the given currency, with its decimal places if it has any
+    pub(crate) fn in_currency(self, currency: CurrencyCode, currency_places: &HashMap<CurrencyCode, u32>) -> Self {
+        Self { currency_places: currency_places.get(&currency).copied(), ..self }
+    }
+}
+
+/// An amount of an output, written in the bank's [BankConfig::decimal_places] as set by
+/// [BankConfig::amount_output_format] for the account output and as a decimal otherwise
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OutputAmount {
+    value: AmountValue,
+    style: AmountStyle,
+}
+
+impl OutputAmount {
+    pub(crate) fn new(amount: &Amount, style: AmountStyle) -> Self {
+        Self { value: amount.value, style }
+    }
+}
+
+impl Serialize for OutputAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.style.format {
+            AmountOutputFormat::Decimal => {
+                let amount = Amount { value: self.value };
+                match self.style.currency_places {
+                    Some(places) => serializer.serialize_str(&amount.to_fixed_decimal(self.style.decimal_places, places)),
+                    None => serializer.serialize_str(&amount.to_short_decimal_with(self.style.decimal_places)),
+                }
+            },
+            AmountOutputFormat::ScaledInteger => serializer.serialize_i64(self.value),
+        }
+    }
+}
+
+/// An output row for one currency of a client's account
+#[derive(Serialize)]
+struct CurrencyAccountRow {
+    client: ClientId,
+    currency: CurrencyCode,
+    available: OutputAmount,
+    held: OutputAmount,
+    total: OutputAmount,
+    locked: bool,
+    /// Only written with [BankConfig::extended_output]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    open_disputes: Option<u32>,
+}
+
+impl CurrencyAccountRow {
+    fn new(account: &Account, currency: CurrencyCode, available: &Amount, held: &Amount, style: AmountStyle) -> Self {
+        Self {
+            client: account.client,
+            currency,
+            available: OutputAmount::new(available, style),
+            held: OutputAmount::new(held, style),
+            total: OutputAmount { value: available.value.saturating_add(held.value), style },
+            locked: account.locked,
+            open_disputes: None,
+        }
+    }
+}
+
+/// An output row of an account in one of the layouts of [Bank::write_accounts_to]
+#[derive(Serialize)]
+#[serde(untagged)]
+enum AccountRow {
+    Base(BaseAccountRow),
+    Currency(CurrencyAccountRow),
+}
+
+/// An output row of a bank that has only seen its base currency
+#[derive(Serialize)]
+struct BaseAccountRow {
+    client: ClientId,
+    available: OutputAmount,
+    held: OutputAmount,
+    total: OutputAmount,
+    locked: bool,
+    /// Only written with [BankConfig::extended_output]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    open_disputes: Option<u32>,
+}
+
+impl BaseAccountRow {
+    fn new(account: &Account, style: AmountStyle) -> Self {
+        Self {
+            client: account.client,
+            available: OutputAmount::new(&account.available, style),
+            held: OutputAmount::new(&account.held, style),
+            total: OutputAmount::new(&account.total, style),
+            locked: account.locked,
+            open_disputes: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+
+    use crate::bank::{Account, AccountSnapshot, Bank};
+    use crate::config::{AmountOutputFormat, BankConfig, DuplicateTxIdPolicy, ExcessPrecision, FeeSchedule, Limits, PartialChargebackRemainder, ProcessOptions, ZeroAmountPolicy};
+    use crate::error::{BankError, CloseAccountError};
+    use crate::report::{LineError, ProcessReport, ProgressUpdate, RejectionReason, RowError};
+    use crate::shared_types::{Amount, ClientId, CurrencyCode, TxId};
+    use crate::transaction::{TxRequest, Tx, TxType};
+    #[cfg(feature = "async")]
+    use crate::config::TxOrdering;
+    #[cfg(feature = "async")]
+    use crate::shared_types::SourceId;
+
+    fn process(bank: &Bank, type_: TxType, tx: u32, value: i64, currency: Option<&str>) -> Result<(), RejectionReason> {
+        Tx {
+            type_,
+            client: ClientId(1),
+            tx: TxId(tx),
+            // Left out for meta-transactions, as a chargeback with an amount is a partial one
+            amount: matches!(type_, TxType::Deposit | TxType::Withdrawal).then_some(Amount { value }),
+            currency: currency.map(|c| c.parse().unwrap()),
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(bank)
+    }
+
+    fn accounts_output(bank: &Bank) -> String {
+        let mut output = Vec::new();
+        bank.write_accounts_to(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_new_for_tokio_bank_data_different_address() {
+        // Make banks
+        let bank = Bank::new();
+        let tokio_bank = Bank::new_for_tokio(&bank);
+        let tokio_bank_2 = Bank::new_for_tokio(&bank);
+
+        assert_ne!(std::ptr::addr_of!(bank), std::ptr::addr_of!(tokio_bank));
+        assert_ne!(std::ptr::addr_of!(bank.transactions), std::ptr::addr_of!(tokio_bank.transactions));
+        assert_ne!(std::ptr::addr_of!(bank.accounts), std::ptr::addr_of!(tokio_bank.accounts));
+
+        assert_ne!(std::ptr::addr_of!(tokio_bank), std::ptr::addr_of!(tokio_bank_2));
+        assert_ne!(std::ptr::addr_of!(tokio_bank.transactions), std::ptr::addr_of!(tokio_bank_2.transactions));
+        assert_ne!(std::ptr::addr_of!(tokio_bank.accounts), std::ptr::addr_of!(tokio_bank_2.accounts));
+    }
+
+    #[test]
+    fn test_new_for_tokio_same_data() {
+        // Make banks
+        let bank = Bank::new();
+        let tokio_bank = Bank::new_for_tokio(&bank);
+        let tokio_bank_2 = Bank::new_for_tokio(&bank);
+        // Make sample tx
+        let tx = Tx {
+            type_: TxType::Deposit,
+            client: ClientId(0),
+            tx: TxId(0),
+            amount: Some(Amount { value: 500 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        };
+        // Insert sample tx
+        tokio_bank_2.transactions.lock().insert(tx.into_stored());
+
+        // Get data
+        let bank_amount = bank.transactions.lock().get(TxId(0)).unwrap().amount;
+        let tokio_bank_amount = tokio_bank.transactions.lock().get(TxId(0)).unwrap().amount;
+        let tokio_bank_2_amount = tokio_bank_2.transactions.lock().get(TxId(0)).unwrap().amount;
+
+        // Compare data
+        assert_eq!(tokio_bank_amount, tokio_bank_2_amount);
+        assert_eq!(bank_amount, tokio_bank_2_amount);
+    }
+
+    #[test]
+    fn test_calculate_total_avail_only() {
+        let mut account = Account::new(ClientId(1));
+        account.available.value = 20;
+
+        account.calculate_total();
+
+        assert_eq!(account.total.value, account.available.value)
+    }
+
+    #[test]
+    fn test_calculate_total_held_only() {
+        let mut account = Account::new(ClientId(1));
+        account.held.value = 20;
+
+        account.calculate_total();
+
+        assert_eq!(account.total.value, account.held.value)
+    }
+
+    #[test]
+    fn test_calculate_total_both() {
+        let mut account = Account::new(ClientId(1));
+        account.available.value = 20;
+        account.held.value = 10;
+
+        account.calculate_total();
+
+        assert_eq!(account.total.value, account.available.value + account.held.value)
+    }
+
+    #[test]
+    fn test_calculate_total_negative_available() {
+        let mut account = Account::new(ClientId(1));
+        account.available.value = -30;
+        account.held.value = 10;
+
+        account.calculate_total();
+        assert_eq!(account.total.value, -20);
+
+        account.held.value = 50;
+        account.calculate_total();
+        assert_eq!(account.total.value, 20);
+    }
+
+    #[test]
+    fn test_negative_total_after_dispute_of_withdrawn_deposit() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 100000, None).unwrap();
+        process(&bank, TxType::Deposit, 2, 20000, None).unwrap();
+        process(&bank, TxType::Withdrawal, 3, 110000, None).unwrap();
+        process(&bank, TxType::Dispute, 2, 0, None).unwrap();
+
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked\n1,-1.0,2.0,1.0,false\n");
+        process(&bank, TxType::Chargeback, 2, 0, None).unwrap();
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked\n1,-1.0,0.0,-1.0,true\n");
+    }
+
+    #[test]
+    fn test_clamp_negative_totals() {
+        let bank = Bank::with_config(BankConfig { clamp_negative_totals: true, ..BankConfig::default() });
+        process(&bank, TxType::Deposit, 1, 100000, None).unwrap();
+        process(&bank, TxType::Withdrawal, 2, 100000, None).unwrap();
+        process(&bank, TxType::Dispute, 1, 0, None).unwrap();
+        process(&bank, TxType::Chargeback, 1, 0, None).unwrap();
+
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked\n1,-10.0,0.0,0.0,true\n");
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().total, -100000);
+    }
+
+    #[test]
+    fn test_snapshot_peak_held_reflects_intermediate_maximum() {
+        let bank = Bank::new();
+        for (type_, tx, value) in [
+            (TxType::Deposit, 1, 30),
+            (TxType::Deposit, 2, 20),
+            (TxType::Dispute, 1, 0),
+            (TxType::Resolve, 1, 0),
+        ] {
+            Tx {
+                type_,
+                client: ClientId(1),
+                tx: TxId(tx),
+                amount: Some(Amount { value }),
+                currency: None,
+                timestamp: None,
+                disputed: false,
+                reversed: false,
+                seq: 0,
+                source: None
+            }.process(&bank).unwrap();
+        }
+
+        let snapshot = bank.account_snapshot(ClientId(1)).unwrap();
+        assert_eq!(snapshot.held, 0);
+        assert_eq!(snapshot.available, 50);
+        assert_eq!(snapshot.peak_held, 30);
+        assert_eq!(snapshot.peak_available, 50);
+    }
+
+    #[test]
+    fn test_snapshot_unknown_client() {
+        let bank = Bank::new();
+
+        assert_eq!(bank.account_snapshot(ClientId(1)), None);
+    }
+
+    #[test]
+    fn test_mixed_currency_deposits_kept_separate() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 15000, Some("EUR")).unwrap();
+        process(&bank, TxType::Deposit, 2, 20000, Some("usd")).unwrap();
+        process(&bank, TxType::Deposit, 3, 10000, None).unwrap();
+        process(&bank, TxType::Dispute, 1, 0, None).unwrap();
+
+        let accounts = bank.accounts.lock();
+        let account = accounts.get(&ClientId(1)).unwrap();
+        assert_eq!(account.available.value, 30000);
+        assert_eq!(account.held.value, 0);
+        let eur = account.currencies.get(&"EUR".parse::<CurrencyCode>().unwrap()).unwrap();
+        assert_eq!(eur.available.value, 0);
+        assert_eq!(eur.held.value, 15000);
+    }
+
+    #[test]
+    fn test_withdrawal_cannot_spend_other_currency() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 15000, Some("USD")).unwrap();
+
+        let outcome = process(&bank, TxType::Withdrawal, 2, 10000, Some("EUR"));
+
+        assert_eq!(outcome, Err(RejectionReason::InsufficientFunds));
+        assert_eq!(bank.accounts.lock().get(&ClientId(1)).unwrap().available.value, 15000);
+    }
+
+    #[test]
+    fn test_single_currency_output_unchanged() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 20000, None).unwrap();
+        process(&bank, TxType::Withdrawal, 2, 5000, Some("USD")).unwrap();
+
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n");
+    }
+
+    #[test]
+    fn test_output_amounts_are_plain_decimals() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(i64::MAX)).unwrap();
+        apply(&bank, TxType::Deposit, 2, 2, Some(12345)).unwrap();
+        apply(&bank, TxType::Withdrawal, 2, 3, Some(12345)).unwrap();
+
+        let mut output = Vec::new();
+        bank.write_accounts_filtered(&mut output, &[ClientId(1), ClientId(2)]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,922337203685477.5807,0.0,922337203685477.5807,false\n2,0.0,0.0,0.0,false\n"
+        );
+    }
+
+    #[test]
+    fn test_scaled_integer_amount_output() {
+        let bank = Bank::with_config(BankConfig { amount_output_format: AmountOutputFormat::ScaledInteger, ..BankConfig::default() });
+        process(&bank, TxType::Deposit, 1, 15000, None).unwrap();
+
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked\n1,15000,0,15000,false\n");
+        let mut json = Vec::new();
+        bank.write_accounts_json(&mut json).unwrap();
+        assert_eq!(String::from_utf8(json).unwrap(), "[{\"client\":1,\"available\":15000,\"held\":0,\"total\":15000,\"locked\":false}]\n");
+
+        process(&bank, TxType::Deposit, 2, 5000, Some("EUR")).unwrap();
+        assert_eq!(accounts_output(&bank), "client,currency,available,held,total,locked\n1,USD,15000,0,15000,false\n1,EUR,5000,0,5000,false\n");
+    }
+
+    /// The same deposits and withdrawals at any number of decimal places, with two amounts
+    /// that need more than two places and one that needs more than six
+    const DECIMAL_PLACES_CSV: &str = "type, client, tx, amount\n\
+        deposit, 1, 1, 1.5\n\
+        deposit, 1, 2, 0.25\n\
+        withdrawal, 1, 3, 0.125\n\
+        deposit, 2, 4, 2.0000001\n\
+        deposit, 2, 5, 3.005\n";
+
+    fn process_at_decimal_places(decimal_places: u32, format: AmountOutputFormat) -> (Bank, ProcessReport) {
+        let bank = Bank::with_config(BankConfig { decimal_places, amount_output_format: format, ..BankConfig::default() });
+        let options = ProcessOptions { excess_precision: ExcessPrecision::Reject, ..ProcessOptions::default() };
+        let report = bank.process_transactions_from_reader(DECIMAL_PLACES_CSV.as_bytes(), &options).unwrap();
+        (bank, report)
+    }
+
+    #[test]
+    fn test_two_decimal_places() {
+        let (bank, report) = process_at_decimal_places(2, AmountOutputFormat::Decimal);
+
+        assert_eq!((report.applied, report.precision_errors), (2, 3));
+        assert_eq!(report.row_errors[0].error.to_string(), "amount 0.125 has more than 2 decimal places");
+        let mut output = Vec::new();
+        bank.write_accounts_filtered(&mut output, &[ClientId(1)]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,1.75,0.0,1.75,false\n");
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 175);
+        let mut sheet = Vec::new();
+        bank.write_transactions(&mut sheet).unwrap();
+        assert_eq!(String::from_utf8(sheet).unwrap(), "tx,client,type,amount,disputed,source\n1,1,deposit,1.5,false,\n2,1,deposit,0.25,false,\n");
+
+        let (bank, _) = process_at_decimal_places(2, AmountOutputFormat::ScaledInteger);
+        let mut output = Vec::new();
+        bank.write_accounts_filtered(&mut output, &[ClientId(1)]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,175,0,175,false\n");
+    }
+
+    #[test]
+    fn test_six_decimal_places() {
+        let (bank, report) = process_at_decimal_places(6, AmountOutputFormat::Decimal);
+
+        assert_eq!((report.applied, report.precision_errors), (4, 1));
+        assert_eq!(report.row_errors[0].error.to_string(), "amount 2.0000001 has more than 6 decimal places");
+        let mut output = Vec::new();
+        bank.write_accounts_filtered(&mut output, &[ClientId(1), ClientId(2)]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,1.625,0.0,1.625,false\n2,3.005,0.0,3.005,false\n");
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 1_625_000);
+
+        let (bank, _) = process_at_decimal_places(6, AmountOutputFormat::ScaledInteger);
+        let mut output = Vec::new();
+        bank.write_accounts_filtered(&mut output, &[ClientId(2)]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n2,3005000,0,3005000,false\n");
+    }
+
+    #[test]
+    fn test_default_decimal_places_unchanged() {
+        let (bank, report) = process_at_decimal_places(4, AmountOutputFormat::Decimal);
+
+        assert_eq!((report.applied, report.precision_errors), (4, 1));
+        assert_eq!(report.row_errors[0].error.to_string(), "amount 2.0000001 has more than 4 decimal places");
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 16250);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 9 are supported")]
+    fn test_too_many_decimal_places() {
+        Bank::with_config(BankConfig { decimal_places: 10, ..BankConfig::default() });
+    }
+
+    #[test]
+    fn test_multi_currency_output_has_row_per_currency() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 20000, None).unwrap();
+        process(&bank, TxType::Deposit, 2, 5000, Some("EUR")).unwrap();
+
+        assert_eq!(
+            accounts_output(&bank),
+            "client,currency,available,held,total,locked\n1,USD,2.0,0.0,2.0,false\n1,EUR,0.5,0.0,0.5,false\n"
+        );
+    }
+
+    #[test]
+    fn test_currency_decimal_places_in_output() {
+        let bank = Bank::with_config(BankConfig {
+            decimal_places: 8,
+            currency_decimal_places: HashMap::from([("USD".parse().unwrap(), 2), ("JPY".parse().unwrap(), 0), ("BTC".parse().unwrap(), 8)]),
+            ..BankConfig::default()
+        });
+        process(&bank, TxType::Deposit, 1, 1_250_000_000, None).unwrap();
+        process(&bank, TxType::Deposit, 2, 150_000_000_000, Some("JPY")).unwrap();
+        process(&bank, TxType::Deposit, 3, 12_345_678, Some("BTC")).unwrap();
+        process(&bank, TxType::Dispute, 3, 0, None).unwrap();
+
+        assert_eq!(
+            accounts_output(&bank),
+            "client,currency,available,held,total,locked\n\
+            1,USD,12.50,0.00,12.50,false\n\
+            1,BTC,0.00000000,0.12345678,0.12345678,false\n\
+            1,JPY,1500,0,1500,false\n"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "decimal places of BTC are 10, at most 9 are supported")]
+    fn test_currency_decimal_places_limited() {
+        Bank::with_config(BankConfig {
+            currency_decimal_places: HashMap::from([("BTC".parse().unwrap(), 10)]),
+            ..BankConfig::default()
+        });
+    }
+
+    #[test]
+    fn test_max_seen_tx_id() {
+        let bank = Bank::new();
+        assert_eq!(bank.max_seen_tx_id(), None);
+
+        process(&bank, TxType::Deposit, 7, 10000, None).unwrap();
+        process(&bank, TxType::Deposit, 3, 10000, None).unwrap();
+        // Rejected transactions count too
+        process(&bank, TxType::Withdrawal, 12, 50000, None).unwrap_err();
+        assert_eq!(Bank::new_for_tokio(&bank).max_seen_tx_id(), Some(TxId(12)));
+
+        process(&bank, TxType::Deposit, u32::MAX, 10000, None).unwrap();
+        assert_eq!(bank.max_seen_tx_id(), Some(TxId(u32::MAX)));
+    }
+
+    #[test]
+    fn test_apply_public_tx() {
+        let bank = Bank::new();
+
+        bank.apply(TxRequest {
+            type_: TxType::Deposit,
+            client: ClientId(3),
+            tx: TxId(1),
+            amount: Some(25000),
+            currency: None,
+        }).unwrap();
+        let outcome = bank.apply(TxRequest {
+            type_: TxType::Withdrawal,
+            client: ClientId(3),
+            tx: TxId(2),
+            amount: Some(30000),
+            currency: None,
+        });
+
+        assert_eq!(outcome, Err(RejectionReason::InsufficientFunds));
+        assert_eq!(bank.account_snapshot(ClientId(3)).unwrap().available, 25000);
+    }
+
+    /// A writer that fails every write, like stdout piped into a process that has exited
+    struct BrokenPipe;
+
+    impl std::io::Write for BrokenPipe {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "broken pipe"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_error_returned() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 20000, None).unwrap();
+
+        let error = bank.write_accounts_to(BrokenPipe).unwrap_err();
+        assert!(error.to_string().contains("broken pipe"), "{}", error);
+        assert!(bank.write_accounts_json(BrokenPipe).unwrap_err().to_string().contains("broken pipe"));
+        assert!(Bank::new().write_accounts_to(BrokenPipe).is_err());
+    }
+
+    #[test]
+    fn test_empty_bank_output_has_header() {
+        let bank = Bank::new();
+
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked\n");
+    }
+
+    #[test]
+    fn test_dispute_before_deposit_applied_when_deposit_arrives() {
+        let bank = Bank::with_config(BankConfig { max_pending_disputes: 10, ..BankConfig::default() });
+        process(&bank, TxType::Dispute, 7, 0, None).unwrap();
+        assert_eq!(bank.pending_dispute_count(), 1);
+
+        process(&bank, TxType::Deposit, 7, 20000, None).unwrap();
+
+        let snapshot = bank.account_snapshot(ClientId(1)).unwrap();
+        assert_eq!(snapshot.held, 20000);
+        assert_eq!(snapshot.available, 0);
+        assert_eq!(bank.pending_dispute_count(), 0);
+    }
+
+    #[test]
+    fn test_parked_dispute_and_chargeback_applied_in_order() {
+        let bank = Bank::with_config(BankConfig { max_pending_disputes: 10, ..BankConfig::default() });
+        process(&bank, TxType::Dispute, 7, 0, None).unwrap();
+        process(&bank, TxType::Chargeback, 7, 0, None).unwrap();
+
+        process(&bank, TxType::Deposit, 7, 20000, None).unwrap();
+
+        let snapshot = bank.account_snapshot(ClientId(1)).unwrap();
+        assert_eq!(snapshot.held, 0);
+        assert_eq!(snapshot.available, 0);
+        assert!(snapshot.locked);
+    }
+
+    #[test]
+    fn test_parked_dispute_from_other_client_rejected_on_arrival() {
+        let bank = Bank::with_config(BankConfig { max_pending_disputes: 10, ..BankConfig::default() });
+        apply(&bank, TxType::Dispute, 2, 7, None).unwrap();
+        apply(&bank, TxType::Chargeback, 2, 7, None).unwrap();
+
+        apply(&bank, TxType::Deposit, 1, 7, Some(20000)).unwrap();
+
+        assert_eq!(balances(&bank, 1), (20000, 0, false));
+        assert!(bank.account_snapshot(ClientId(2)).is_none());
+        assert_eq!(bank.pending_dispute_count(), 0);
+    }
+
+    #[test]
+    fn test_parked_dispute_kept_when_record_rejected_as_duplicate() {
+        let config = BankConfig { max_pending_disputes: 10, duplicate_tx_ids: DuplicateTxIdPolicy::Reject, ..BankConfig::default() };
+        let bank = Bank::with_config(config.clone());
+        apply(&bank, TxType::Dispute, 1, 7, None).unwrap();
+        // The merged deposit is stored without being processed, so the dispute stays parked
+        let other = Bank::with_config(config);
+        apply(&other, TxType::Deposit, 1, 7, Some(20000)).unwrap();
+        bank.merge(&other);
+
+        assert_eq!(apply(&bank, TxType::Deposit, 1, 7, Some(10000)), Err(RejectionReason::DuplicateTxId));
+
+        assert_eq!(balances(&bank, 1), (20000, 0, false));
+        assert_eq!(bank.pending_dispute_count(), 1);
+    }
+
+    #[test]
+    fn test_pending_disputes_bounded() {
+        let bank = Bank::with_config(BankConfig { max_pending_disputes: 1, ..BankConfig::default() });
+        process(&bank, TxType::Dispute, 7, 0, None).unwrap();
+
+        assert_eq!(process(&bank, TxType::Dispute, 8, 0, None), Err(RejectionReason::UnknownTx));
+        assert_eq!(process(&bank, TxType::Resolve, 9, 0, None), Err(RejectionReason::UnknownTx));
+    }
+
+    #[test]
+    fn test_pending_disputes_disabled_by_default() {
+        let bank = Bank::new();
+
+        assert_eq!(process(&bank, TxType::Dispute, 7, 0, None), Err(RejectionReason::UnknownTx));
+        process(&bank, TxType::Deposit, 7, 20000, None).unwrap();
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().held, 0);
+    }
+
+    #[test]
+    fn test_remove_account_purges_transactions() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 20000, None).unwrap();
+        process(&bank, TxType::Deposit, 2, 10000, None).unwrap();
+        bank.apply(TxRequest {
+            type_: TxType::Deposit,
+            client: ClientId(2),
+            tx: TxId(3),
+            amount: Some(5000),
+            currency: None,
+        }).unwrap();
+
+        let removed = bank.remove_account(ClientId(1)).unwrap();
+
+        assert_eq!(removed.available, 30000);
+        assert_eq!(bank.account_snapshot(ClientId(1)), None);
+        assert_eq!(bank.transactions.lock().len(), 1);
+        assert_eq!(bank.remove_account(ClientId(1)), None);
+        assert_eq!(process(&bank, TxType::Dispute, 1, 0, None), Err(RejectionReason::UnknownTx));
+        assert_eq!(bank.account_snapshot(ClientId(1)), None);
+    }
+
+    #[test]
+    fn test_remove_account_during_concurrent_deposits() {
+        let bank = Bank::new();
+        bank.apply(TxRequest {
+            type_: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(0),
+            amount: Some(10000),
+            currency: None,
+        }).unwrap();
+
+        let workers: Vec<_> = (2..6u16).map(|client| {
+            let bank = Bank::new_for_tokio(&bank);
+            std::thread::spawn(move || {
+                for i in 0..500u32 {
+                    bank.apply(TxRequest {
+                        type_: TxType::Deposit,
+                        client: ClientId(client),
+                        tx: TxId(client as u32 * 1000 + i + 1),
+                        amount: Some(1),
+                        currency: None,
+                    }).unwrap();
+                }
+            })
+        }).collect();
+        bank.remove_account(ClientId(1)).unwrap();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(bank.account_snapshot(ClientId(1)), None);
+        for client in 2..6 {
+            assert_eq!(bank.account_snapshot(ClientId(client)).unwrap().available, 500);
+        }
+        assert_eq!(bank.transactions.lock().len(), 2000);
+    }
+
+    #[test]
+    fn test_clear_empties_all_handles() {
+        let bank = Bank::new();
+        let other_handle = Bank::new_for_tokio(&bank);
+        process(&bank, TxType::Deposit, 1, 20000, None).unwrap();
+
+        other_handle.clear();
+
+        assert_eq!(bank.account_snapshot(ClientId(1)), None);
+        assert_eq!(bank.transactions.lock().len(), 0);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_timestamp_ordering_applies_chronologically() {
+        let path = std::env::temp_dir().join("bank_lib_test_timestamp_ordering.csv");
+        std::fs::write(&path, "type, client, tx, amount, currency, timestamp\n\
+            withdrawal, 1, 2, 1.0, , 2024-01-01T10:00:02Z\n\
+            dispute, 1, 1, , , 2024-01-01T10:00:03Z\n\
+            deposit, 1, 1, 3.0, , 2024-01-01T11:00:01+01:00\n").unwrap();
+        let options = ProcessOptions { ordering: TxOrdering::Timestamp, ..ProcessOptions::default() };
+
+        let bank = Bank::new();
+        let report = bank.process_transactions_from_csv_path_with_options(path.to_str().unwrap(), &options).await.unwrap();
+
+        assert_eq!(report.applied, 3);
+        let snapshot = bank.account_snapshot(ClientId(1)).unwrap();
+        assert_eq!((snapshot.available, snapshot.held), (-10000, 30000));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_timestamp_ordering_without_timestamps_uses_tx_id() {
+        let path = std::env::temp_dir().join("bank_lib_test_timestamp_ordering_tx_id.csv");
+        std::fs::write(&path, "type, client, tx, amount\nwithdrawal, 1, 2, 1.0\ndeposit, 1, 1, 3.0\n").unwrap();
+        let options = ProcessOptions { ordering: TxOrdering::Timestamp, ..ProcessOptions::default() };
+
+        let bank = Bank::new();
+        let report = bank.process_transactions_from_csv_path_with_options(path.to_str().unwrap(), &options).await.unwrap();
+
+        assert_eq!(report.applied, 2);
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 20000);
+    }
+
+    #[test]
+    fn test_chargeback_after_withdrawal_records_loss() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 100000, None).unwrap();
+        process(&bank, TxType::Withdrawal, 2, 100000, None).unwrap();
+        process(&bank, TxType::Dispute, 1, 0, None).unwrap();
+        process(&bank, TxType::Chargeback, 1, 0, None).unwrap();
+
+        let snapshot = bank.account_snapshot(ClientId(1)).unwrap();
+        assert_eq!((snapshot.available, snapshot.held, snapshot.locked), (-100000, 0, true));
+        assert_eq!(bank.chargeback_losses().get(&ClientId(1)), Some(&100000));
+        assert_eq!(bank.stats().chargeback_losses, 100000);
+        assert_eq!(bank.stats().locked_accounts, 1);
+    }
+
+    #[test]
+    fn test_chargeback_covered_by_balance_records_no_loss() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 100000, None).unwrap();
+        process(&bank, TxType::Deposit, 2, 50000, None).unwrap();
+        process(&bank, TxType::Dispute, 1, 0, None).unwrap();
+        process(&bank, TxType::Chargeback, 1, 0, None).unwrap();
+
+        assert!(bank.chargeback_losses().is_empty());
+        assert_eq!(bank.stats().chargeback_losses, 0);
+    }
+
+    #[test]
+    fn test_locked_clients_sorted() {
+        let bank = Bank::new();
+        let request = |type_, client, tx, amount| TxRequest { type_, client: ClientId(client), tx: TxId(tx), amount, currency: None };
+        for (client, tx) in [(3, 1), (1, 2), (2, 3)] {
+            bank.apply(request(TxType::Deposit, client, tx, Some(10000))).unwrap();
+        }
+        assert!(bank.locked_clients().is_empty());
+
+        for (client, tx) in [(3, 1), (1, 2)] {
+            bank.apply(request(TxType::Dispute, client, tx, None)).unwrap();
+            bank.apply(request(TxType::Chargeback, client, tx, None)).unwrap();
+        }
+
+        assert_eq!(bank.locked_clients(), vec![ClientId(1), ClientId(3)]);
+    }
+
+    #[test]
+    fn test_net_position_ignores_disputes() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 50000, None).unwrap();
+        process(&bank, TxType::Withdrawal, 2, 20000, None).unwrap();
+        process(&bank, TxType::Deposit, 3, 10000, Some("EUR")).unwrap();
+
+        assert_eq!(bank.net_position(ClientId(1)), 30000);
+
+        process(&bank, TxType::Dispute, 1, 0, None).unwrap();
+        process(&bank, TxType::Chargeback, 1, 0, None).unwrap();
+        assert_eq!(bank.net_position(ClientId(1)), 30000);
+        assert_eq!(bank.net_position(ClientId(2)), 0);
+    }
+
+    #[test]
+    fn test_net_position_skips_withdrawal_rejected_for_insufficient_funds() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 50000, None).unwrap();
+        assert_eq!(process(&bank, TxType::Withdrawal, 2, 60000, None), Err(RejectionReason::InsufficientFunds));
+        process(&bank, TxType::Withdrawal, 3, 20000, None).unwrap();
+
+        assert!(bank.transactions.lock().get(TxId(2)).unwrap().failed);
+        assert_eq!(bank.net_position(ClientId(1)), 30000);
+    }
+
+    #[test]
+    fn test_withdrawal_rejected_for_insufficient_funds_cannot_be_disputed() {
+        let bank = Bank::with_config(BankConfig { allow_direct_chargeback: true, ..BankConfig::default() });
+        apply(&bank, TxType::Deposit, 1, 1, Some(50000)).unwrap();
+        assert_eq!(apply(&bank, TxType::Withdrawal, 1, 2, Some(100000)), Err(RejectionReason::InsufficientFunds));
+
+        for type_ in [TxType::Dispute, TxType::Resolve, TxType::Chargeback, TxType::Reversal] {
+            assert_eq!(apply(&bank, type_, 1, 2, None), Err(RejectionReason::FailedTx), "{:?}", type_);
+        }
+
+        assert_eq!(balances(&bank, 1), (50000, 0, false));
+        assert_eq!(bank.net_position(ClientId(1)), 50000);
+        assert!(!bank.transactions.lock().get(TxId(2)).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_ensure_clients_without_transactions_in_output() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 20000, None).unwrap();
+
+        assert_eq!(bank.ensure_clients(&[ClientId(3), ClientId(1), ClientId(3)]), 1);
+
+        let mut output = Vec::new();
+        bank.write_accounts_filtered(&mut output, &[ClientId(1), ClientId(3)]).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,2.0,0.0,2.0,false\n3,0.0,0.0,0.0,false\n"
+        );
+        assert_eq!(bank.stats().accounts, 2);
+        assert_eq!(bank.ensure_clients(&[ClientId(1), ClientId(3)]), 0);
+    }
+
+    #[test]
+    fn test_ensure_clients_skips_reserved_clients() {
+        let bank = Bank::with_config(BankConfig { reserved_clients: HashSet::from([ClientId(0)]), ..BankConfig::default() });
+
+        assert_eq!(bank.ensure_clients(&[ClientId(0), ClientId(1)]), 1);
+        assert!(!bank.open_account(ClientId(0)));
+
+        assert!(bank.account_snapshot(ClientId(0)).is_none());
+        assert_eq!(bank.stats().accounts, 1);
+    }
+
+    #[test]
+    fn test_strict_accounts_rejects_unknown_client() {
+        let bank = Bank::with_config(BankConfig { strict_accounts: true, ..BankConfig::default() });
+
+        assert_eq!(process(&bank, TxType::Deposit, 1, 10000, None), Err(RejectionReason::UnknownClient));
+        assert!(bank.account_snapshot(ClientId(1)).is_none());
+
+        assert!(bank.open_account(ClientId(1)));
+        assert!(!bank.open_account(ClientId(1)));
+        assert_eq!(process(&bank, TxType::Deposit, 2, 10000, None), Ok(()));
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 10000);
+    }
+
+    #[test]
+    fn test_default_accounts_opened_by_first_transaction() {
+        let bank = Bank::new();
+
+        assert_eq!(process(&bank, TxType::Deposit, 1, 10000, None), Ok(()));
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 10000);
+    }
+
+    #[test]
+    fn test_write_accounts_filtered_skips_unknown_clients() {
+        let bank = Bank::new();
+        for (client, tx) in [(1, 1), (7, 2), (42, 3)] {
+            bank.apply(TxRequest { type_: TxType::Deposit, client: ClientId(client), tx: TxId(tx), amount: Some(15000), currency: None }).unwrap();
+        }
+
+        let mut output = Vec::new();
+        let report = bank.write_accounts_filtered(&mut output, &[ClientId(42), ClientId(3), ClientId(1)]).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n42,1.5,0.0,1.5,false\n1,1.5,0.0,1.5,false\n");
+        assert_eq!(report.written, 2);
+        assert_eq!(report.unknown_clients, vec![ClientId(3)]);
+    }
+
+    #[test]
+    fn test_write_accounts_filtered_only_unknown_clients_writes_header() {
+        let bank = Bank::new();
+        bank.apply(TxRequest { type_: TxType::Deposit, client: ClientId(1), tx: TxId(1), amount: Some(15000), currency: None }).unwrap();
+
+        let mut output = Vec::new();
+        let report = bank.write_accounts_filtered(&mut output, &[ClientId(2)]).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n");
+        assert_eq!(report.written, 0);
+    }
+
+    #[test]
+    fn test_close_account_without_held_funds() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 20000, None).unwrap();
+        process(&bank, TxType::Dispute, 1, 0, None).unwrap();
+        process(&bank, TxType::Resolve, 1, 0, None).unwrap();
+
+        let snapshot = bank.close_account(ClientId(1)).unwrap().unwrap();
+
+        assert_eq!((snapshot.available, snapshot.held), (20000, 0));
+        assert!(bank.account_snapshot(ClientId(1)).is_none());
+        assert!(bank.transactions.lock().is_empty());
+        assert_eq!(bank.close_account(ClientId(1)), Ok(None));
+    }
+
+    #[test]
+    fn test_close_account_refused_with_held_funds() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 20000, None).unwrap();
+        process(&bank, TxType::Dispute, 1, 0, None).unwrap();
+
+        assert_eq!(bank.close_account(ClientId(1)), Err(CloseAccountError::HeldFunds));
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().held, 20000);
+    }
+
+    fn apply(bank: &Bank, type_: TxType, client: u16, tx: u32, amount: Option<i64>) -> Result<(), RejectionReason> {
+        bank.apply(TxRequest { type_, client: ClientId(client), tx: TxId(tx), amount, currency: None })
+    }
+
+    fn balances(bank: &Bank, client: u16) -> (i64, i64, bool) {
+        let snapshot = bank.account_snapshot(ClientId(client)).unwrap();
+        (snapshot.available, snapshot.held, snapshot.locked)
+    }
+
+    #[test]
+    fn test_cross_client_resolve_rejected() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 2, 2, Some(5000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Resolve, 2, 1, None), Err(RejectionReason::ClientMismatch));
+        assert_eq!(balances(&bank, 1), (0, 10000, false));
+        assert_eq!(balances(&bank, 2), (5000, 0, false));
+    }
+
+    #[test]
+    fn test_deposit_limits_allow_boundary_and_reject_one_unit_over() {
+        let limits = Limits { max_single_deposit: Some(Amount { value: 100000 }), max_cumulative_deposit: Some(Amount { value: 150000 }), ..Limits::default() };
+        let bank = Bank::with_config(BankConfig { limits, ..BankConfig::default() });
+        let data = "type, client, tx, amount, currency\n\
+            deposit, 1, 1, 10.0001\ndeposit, 1, 2, 10.0\n\
+            withdrawal, 1, 3, 4.0\ndeposit, 1, 4, 5.0001\ndeposit, 1, 5, 5.0\ndeposit, 1, 6, 0.0001\n\
+            deposit, 2, 7, 10.0\ndeposit, 1, 8, 1.0, EUR\n\
+            dispute, 1, 1,\ndispute, 1, 4,\ndispute, 1, 6,\n";
+
+        let report = bank.process_transactions_from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap();
+
+        assert_eq!(report.rejected_for(RejectionReason::ExceedsSingleLimit), 1);
+        assert_eq!(report.rejected_for(RejectionReason::ExceedsCumulativeLimit), 2);
+        assert_eq!(report.rejected_for(RejectionReason::UnknownTx), 3);
+        assert_eq!(balances(&bank, 1), (110000, 0, false));
+        assert_eq!(balances(&bank, 2), (100000, 0, false));
+        let transactions = bank.transactions.lock();
+        assert!([1, 4, 6].iter().all(|&tx| transactions.get(TxId(tx)).is_none()));
+        assert_eq!(transactions.len(), 5);
+    }
+
+    #[test]
+    fn test_cumulative_deposit_limit_counted_per_file_and_currency() {
+        let limits = Limits { max_cumulative_deposit: Some(Amount { value: 100000 }), ..Limits::default() };
+        let bank = Bank::with_config(BankConfig { limits, ..BankConfig::default() });
+        let files = [
+            ("first", "type, client, tx, amount, currency\ndeposit, 1, 1, 10.0\ndeposit, 1, 2, 10.0, EUR\ndeposit, 1, 3, 0.0001, EUR\n"),
+            ("second", "type, client, tx, amount, currency\ndeposit, 1, 4, 10.0\ndeposit, 1, 5, 10.0, EUR\ndeposit, 1, 6, 0.0001\n"),
+        ];
+
+        for (name, data) in files {
+            let path = std::env::temp_dir().join(format!("bank_lib_test_limits_{}.csv", name));
+            std::fs::write(&path, data).unwrap();
+            let report = bank.process_transactions_from_csv_path_blocking(path.to_str().unwrap()).unwrap();
+            assert_eq!((report.applied, report.rejected_for(RejectionReason::ExceedsCumulativeLimit)), (2, 1));
+        }
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 200000);
+
+        // Deposits not read from a file share a counter
+        assert_eq!(apply(&bank, TxType::Deposit, 1, 20, Some(100000)), Ok(()));
+        assert_eq!(apply(&bank, TxType::Deposit, 1, 21, Some(1)), Err(RejectionReason::ExceedsCumulativeLimit));
+    }
+
+    #[test]
+    fn test_deposit_to_locked_account_rejected_before_limits() {
+        let limits = Limits { max_single_deposit: Some(Amount { value: 100000 }), ..Limits::default() };
+        let bank = Bank::with_config(BankConfig { limits, ..BankConfig::default() });
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+        apply(&bank, TxType::Chargeback, 1, 1, None).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Deposit, 1, 2, Some(100001)), Err(RejectionReason::AccountLocked));
+    }
+
+    #[test]
+    fn test_deposit_over_max_balance_rejected() {
+        let limits = Limits { max_balance: Some(Amount { value: 100000 }), ..Limits::default() };
+        let bank = Bank::with_config(BankConfig { limits, ..BankConfig::default() });
+        let data = "type, client, tx, amount\ndeposit, 1, 1, 6.0\ndeposit, 1, 2, 4.0\ndeposit, 1, 3, 0.0001\n\
+            withdrawal, 1, 4, 2.0\ndeposit, 1, 5, 2.0001\ndeposit, 1, 6, 2.0\n";
+
+        let report = bank.process_transactions_from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap();
+
+        assert_eq!((report.applied, report.rejected_for(RejectionReason::ExceedsMaxBalance)), (4, 2));
+        assert_eq!(balances(&bank, 1), (100000, 0, false));
+        assert!(bank.transactions.lock().get(TxId(3)).is_none());
+    }
+
+    #[test]
+    fn test_negative_withdrawal_cannot_bypass_max_balance() {
+        let limits = Limits { max_balance: Some(Amount { value: 1000000 }), ..Limits::default() };
+        let bank = Bank::with_config(BankConfig { limits, ..BankConfig::default() });
+        apply(&bank, TxType::Deposit, 1, 1, Some(500000)).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Withdrawal, 1, 2, Some(-10000000000)), Err(RejectionReason::NegativeAmount));
+        assert_eq!(apply(&bank, TxType::Deposit, 1, 3, Some(-100000)), Err(RejectionReason::NegativeAmount));
+
+        assert_eq!(balances(&bank, 1), (500000, 0, false));
+        apply(&bank, TxType::Deposit, 1, 4, Some(200000)).unwrap();
+        assert!(bank.transactions.lock().get(TxId(2)).is_none());
+        assert!(bank.transactions.lock().get(TxId(3)).is_none());
+    }
+
+    #[test]
+    fn test_max_balance_applies_to_each_currency() {
+        let limits = Limits { max_balance: Some(Amount { value: 100000 }), ..Limits::default() };
+        let bank = Bank::with_config(BankConfig { limits, ..BankConfig::default() });
+        let data = "type, client, tx, amount, currency\ndeposit, 1, 1, 10.0\ndeposit, 1, 2, 10.0, EUR\n\
+            deposit, 1, 3, 0.0001, EUR\ndeposit, 1, 4, 0.0001\n";
+
+        let report = bank.process_transactions_from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap();
+
+        assert_eq!((report.applied, report.rejected_for(RejectionReason::ExceedsMaxBalance)), (2, 2));
+        let mut output = Vec::new();
+        bank.write_accounts_filtered(&mut output, &[ClientId(1)]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "client,currency,available,held,total,locked\n1,USD,10.0,0.0,10.0,false\n1,EUR,10.0,0.0,10.0,false\n");
+    }
+
+    #[test]
+    fn test_zero_deposit_rejected_or_accepted_by_policy() {
+        let data = "type, client, tx, amount\ndeposit, 1, 1, 0.0\ndispute, 1, 1,\n";
+        let process = |zero_amount_policy| {
+            let bank = Bank::with_config(BankConfig { zero_amount_policy, ..BankConfig::default() });
+            let report = bank.process_transactions_from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap();
+            (report, bank.stats(), bank.account_snapshot(ClientId(1)))
+        };
+
+        let (report, stats, snapshot) = process(ZeroAmountPolicy::Reject);
+        assert_eq!((report.rejected_for(RejectionReason::ZeroAmount), report.rejected_for(RejectionReason::UnknownTx)), (1, 1));
+        assert_eq!((stats.accounts, stats.transactions, snapshot), (0, 0, None));
+
+        let (report, stats, snapshot) = process(ZeroAmountPolicy::Accept);
+        assert_eq!((report.applied, report.rejected()), (2, 0));
+        assert_eq!((stats.accounts, stats.transactions), (1, 1));
+        let snapshot = snapshot.unwrap();
+        assert_eq!((snapshot.available, snapshot.held, snapshot.open_disputes), (0, 0, 1));
+    }
+
+    #[test]
+    fn test_read_view_queries_balances() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 2, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 1, 2, Some(5000)).unwrap();
+        let view = bank.read_view();
+
+        assert_eq!(view.account_snapshot(ClientId(2)).unwrap().available, 10000);
+        assert_eq!(view.account_snapshot(ClientId(3)), None);
+        let snapshots: Vec<AccountSnapshot> = view.iter_accounts().collect();
+        apply(&bank, TxType::Withdrawal, 2, 3, Some(4000)).unwrap();
+        assert_eq!(snapshots.iter().map(|snapshot| (snapshot.client.0, snapshot.available)).collect::<Vec<_>>(), vec![(1, 5000), (2, 10000)]);
+        assert_eq!(bank.read_view().stats().accounts, 2);
+        assert_eq!(bank.read_view().account_snapshot(ClientId(2)).unwrap().available, 6000);
+    }
+
+    #[test]
+    fn test_held_never_negative_after_cross_client_and_double_resolve() {
+        let bank = Bank::new();
+        let process = |data: &str| bank.process_transactions_from_reader(format!("type, client, tx, amount\n{}", data).as_bytes(), &ProcessOptions::default()).unwrap();
+
+        let report = process("deposit, 1, 1, 10.0\ndeposit, 2, 2, 5.0\ndispute, 1, 1,\nresolve, 2, 1,\nchargeback, 2, 1,\nresolve, 1, 1,\nresolve, 1, 1,\n");
+        assert_eq!((report.rejected_for(RejectionReason::ClientMismatch), report.rejected_for(RejectionReason::NotDisputed)), (2, 1));
+        assert_eq!(balances(&bank, 1), (100000, 0, false));
+        assert_eq!(balances(&bank, 2), (50000, 0, false));
+
+        // As if the sheet was edited by hand to mark the resolved deposit disputed again
+        bank.transactions.lock().set_disputed(TxId(1), true);
+        let report = process("resolve, 1, 1,\nchargeback, 1, 1,\n");
+        assert_eq!(report.rejected_for(RejectionReason::InsufficientHeld), 2);
+        assert_eq!(balances(&bank, 1), (100000, 0, false));
+    }
+
+    #[test]
+    fn test_dispute_of_negative_deposit_rejected() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 1, 2, Some(5000)).unwrap();
+        // As if the sheet was edited by hand, negative deposits are rejected when processed
+        let mut stored = bank.transactions.lock().get(TxId(2)).unwrap();
+        stored.amount = -5000;
+        bank.transactions.lock().insert(stored);
+
+        assert_eq!(apply(&bank, TxType::Dispute, 1, 2, None), Err(RejectionReason::InsufficientHeld));
+        assert_eq!(balances(&bank, 1), (15000, 0, false));
+        assert!(!bank.transactions.lock().get(TxId(2)).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_cross_client_chargeback_rejected() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 2, 2, Some(5000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Chargeback, 2, 1, None), Err(RejectionReason::ClientMismatch));
+        assert_eq!(balances(&bank, 1), (0, 10000, false));
+        assert_eq!(balances(&bank, 2), (5000, 0, false));
+    }
+
+    #[test]
+    fn test_cross_client_dispute_rejected() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 2, 2, Some(5000)).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Dispute, 2, 1, None), Err(RejectionReason::ClientMismatch));
+        assert_eq!(balances(&bank, 1), (10000, 0, false));
+        assert_eq!(balances(&bank, 2), (5000, 0, false));
+    }
+
+    #[test]
+    fn test_chargeback_refused_when_held_does_not_cover_dispute() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+        bank.accounts.lock().get_mut(&ClientId(1)).unwrap().held.value = 4000;
+
+        assert_eq!(apply(&bank, TxType::Chargeback, 1, 1, None), Err(RejectionReason::InsufficientHeld));
+        assert_eq!(balances(&bank, 1), (0, 4000, false));
+        assert!(bank.transactions.lock().get(TxId(1)).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_direct_chargeback_rejected_by_default() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Chargeback, 1, 1, None), Err(RejectionReason::NotDisputed));
+        assert_eq!(balances(&bank, 1), (10000, 0, false));
+    }
+
+    #[test]
+    fn test_direct_chargeback_disputes_implicitly() {
+        let bank = Bank::with_config(BankConfig { allow_direct_chargeback: true, ..BankConfig::default() });
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 1, 2, Some(5000)).unwrap();
+
+        apply(&bank, TxType::Chargeback, 1, 1, None).unwrap();
+
+        assert_eq!(balances(&bank, 1), (5000, 0, true));
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().open_disputes, 0);
+        assert!(bank.transactions.lock().get(TxId(1)).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_direct_chargeback_over_disputed_amount_holds_nothing() {
+        let bank = Bank::with_config(BankConfig { allow_direct_chargeback: true, ..BankConfig::default() });
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Chargeback, 1, 1, Some(20000)), Err(RejectionReason::InvalidChargebackAmount));
+        assert_eq!(balances(&bank, 1), (10000, 0, false));
+        assert!(!bank.transactions.lock().get(TxId(1)).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_dispute_inside_window() {
+        let bank = Bank::with_config(BankConfig { dispute_window: Some(2), ..BankConfig::default() });
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 1, 2, Some(10000)).unwrap();
+        apply(&bank, TxType::Withdrawal, 1, 3, Some(5000)).unwrap();
+
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+
+        assert_eq!(balances(&bank, 1), (5000, 10000, false));
+    }
+
+    #[test]
+    fn test_dispute_outside_window_rejected_and_counted() {
+        let bank = Bank::with_config(BankConfig { dispute_window: Some(2), ..BankConfig::default() });
+        let data = "type, client, tx, amount\ndeposit, 1, 1, 1.0\ndeposit, 1, 2, 1.0\ndeposit, 1, 3, 1.0\ndeposit, 1, 4, 1.0\ndispute, 1, 1,\ndispute, 1, 2,\n";
+
+        let report = bank.process_transactions_from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap();
+
+        assert_eq!((report.rows, report.applied), (6, 5));
+        assert_eq!(report.rejected_for(RejectionReason::DisputeWindowExpired), 1);
+        assert_eq!(balances(&bank, 1), (30000, 10000, false));
+        assert!(!bank.transactions.lock().get(TxId(1)).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_reserved_client_rejected_without_account() {
+        let bank = Bank::with_config(BankConfig { reserved_clients: HashSet::from([ClientId(0)]), ..BankConfig::default() });
+        let data = "type, client, tx, amount\ndeposit, 0, 1, 5.0\ndeposit, 1, 2, 1.0\ndispute, 0, 1,\ndispute, 1, 1,\nwithdrawal, 0, 3, 1.0\n";
+
+        let report = bank.process_transactions_from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap();
+
+        assert_eq!((report.rows, report.applied), (5, 1));
+        assert_eq!(report.rejected_for(RejectionReason::ReservedClient), 3);
+        assert_eq!(report.rejected_for(RejectionReason::UnknownTx), 1);
+        assert!(bank.account_snapshot(ClientId(0)).is_none());
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked\n1,1.0,0.0,1.0,false\n");
+        assert!(!bank.transactions.lock().contains(TxId(1)));
+        assert_eq!(balances(&bank, 1), (10000, 0, false));
+    }
+
+    #[test]
+    fn test_write_accounts_per_client() {
+        let dir = std::env::temp_dir().join("bank_lib_test_accounts_per_client");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(15000)).unwrap();
+        apply(&bank, TxType::Deposit, 7, 2, Some(20000)).unwrap();
+
+        bank.write_accounts_per_client(&dir).unwrap();
+
+        let mut files: Vec<String> = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["client_1.csv", "client_7.csv"]);
+        assert_eq!(std::fs::read_to_string(dir.join("client_1.csv")).unwrap(), "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n");
+        assert_eq!(std::fs::read_to_string(dir.join("client_7.csv")).unwrap(), "client,available,held,total,locked\n7,2.0,0.0,2.0,false\n");
+    }
+
+    #[test]
+    fn test_process_batch_outcomes_by_position() {
+        let bank = Bank::new();
+        let request = |type_, tx, amount| TxRequest { type_, client: ClientId(1), tx: TxId(tx), amount, currency: None };
+
+        let report = bank.process_batch(vec![
+            request(TxType::Deposit, 1, Some(20000)),
+            request(TxType::Withdrawal, 2, Some(50000)),
+            request(TxType::Dispute, 9, None),
+            request(TxType::Withdrawal, 3, Some(5000)),
+        ]);
+
+        assert_eq!(report.outcomes, vec![
+            Ok(()),
+            Err(RejectionReason::InsufficientFunds),
+            Err(RejectionReason::UnknownTx),
+            Ok(()),
+        ]);
+        assert_eq!((report.applied(), report.rejected()), (2, 2));
+        assert_eq!(balances(&bank, 1), (15000, 0, false));
+    }
+
+    #[test]
+    fn test_metrics_text() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(15000)).unwrap();
+        apply(&bank, TxType::Deposit, 2, 2, Some(20000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+        apply(&bank, TxType::Chargeback, 1, 1, None).unwrap();
+        apply(&bank, TxType::Deposit, 2, 3, Some(5000)).unwrap();
+        apply(&bank, TxType::Dispute, 2, 3, None).unwrap();
+
+        let metrics = bank.metrics_text();
+
+        assert!(metrics.contains("# TYPE bank_accounts gauge\nbank_accounts 2\n"));
+        assert!(metrics.contains("\nbank_accounts_locked 1\n"));
+        assert!(metrics.contains("\nbank_transactions 3\n"));
+        assert!(metrics.contains("# TYPE bank_held_funds gauge\nbank_held_funds 0.5\n"));
+    }
+
+    #[test]
+    fn test_stats_saturate_totals_of_near_max_holds() {
+        let bank = Bank::new();
+        for client in [1, 2] {
+            apply(&bank, TxType::Deposit, client, client as u32, Some(i64::MAX)).unwrap();
+            apply(&bank, TxType::Dispute, client, client as u32, None).unwrap();
+        }
+
+        assert_eq!(bank.stats().held_funds, i64::MAX);
+        // Written exactly like the csv output, which a float can't for amounts above 2^53
+        assert!(bank.metrics_text().contains("\nbank_held_funds 922337203685477.5807\n"));
+    }
+
+    #[test]
+    fn test_dispute_for_unknown_tx_does_not_create_account() {
+        let bank = Bank::new();
+
+        assert_eq!(apply(&bank, TxType::Dispute, 3, 1, None), Err(RejectionReason::UnknownTx));
+        assert_eq!(apply(&bank, TxType::Resolve, 3, 1, None), Err(RejectionReason::UnknownTx));
+        assert_eq!(apply(&bank, TxType::Chargeback, 3, 1, None), Err(RejectionReason::UnknownTx));
+        assert_eq!(bank.account_snapshot(ClientId(3)), None);
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked\n");
+    }
+
+    #[test]
+    fn test_dispute_refused_when_held_would_overflow() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        bank.accounts.lock().get_mut(&ClientId(1)).unwrap().held.value = i64::MAX - 5000;
+
+        assert_eq!(apply(&bank, TxType::Dispute, 1, 1, None), Err(RejectionReason::Overflow));
+        assert_eq!(balances(&bank, 1), (10000, i64::MAX - 5000, false));
+        assert!(!bank.transactions.lock().get(TxId(1)).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_blocking_processing_without_runtime() {
+        let bank = Bank::new();
+
+        let report = bank.process_transactions_from_csv_path_blocking("tests/fixtures/dispute_resolve.csv").unwrap();
+
+        assert_eq!((report.rows, report.applied), (6, 5));
+        assert_eq!(balances(&bank, 1), (40000, 0, false));
+        assert!(matches!(bank.process_transactions_from_csv_path_blocking("tests/fixtures/missing.csv"), Err(BankError::Io(_))));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_processing_matches_blocking() {
+        let (blocking, tokio) = (Bank::new(), Bank::new());
+
+        let blocking_report = blocking.process_transactions_from_csv_path_blocking("tests/fixtures/dispute_chargeback.csv").unwrap();
+        let tokio_report = tokio.process_transactions_from_csv_path("tests/fixtures/dispute_chargeback.csv").await.unwrap();
+
+        assert_eq!(blocking_report, tokio_report);
+        assert_eq!(blocking.account_snapshot(ClientId(1)), tokio.account_snapshot(ClientId(1)));
+        assert_eq!(blocking.source_path(SourceId(0)), tokio.source_path(SourceId(0)));
+    }
+
+    #[test]
+    fn test_process_lines() {
+        let bank = Bank::new();
+        let lines: Vec<String> = ["type, client, tx, amount", "deposit, 1, 1, 2.0", "withdrawal, 1, 2, 0.5", "dispute, 1, 1,"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+
+        let report = Bank::process_lines(&lines, &bank).unwrap();
+
+        assert_eq!((report.rows, report.applied), (3, 3));
+        assert_eq!(balances(&bank, 1), (-5000, 20000, false));
+    }
+
+    #[test]
+    fn test_progress_reported_every_interval() {
+        let bank = Bank::new();
+        let mut data = String::from("type, client, tx, amount\n");
+        for tx in 1..=10_000 {
+            data.push_str(&format!("deposit, {}, {}, 1.0\n", tx % 100, tx));
+        }
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let (recorded, tokio_bank) = (updates.clone(), Bank::new_for_tokio(&bank));
+        let options = ProcessOptions {
+            on_progress: Some(Arc::new(move |update: ProgressUpdate| {
+                // Would deadlock if called with the accounts locked
+                tokio_bank.account_snapshot(ClientId(1));
+                recorded.lock().unwrap().push(update);
+            })),
+            progress_interval: 1000,
+            ..ProcessOptions::default()
+        };
+
+        let report = bank.process_transactions_from_reader(data.as_bytes(), &options).unwrap();
+
+        let updates = updates.lock().unwrap();
+        assert_eq!(updates.len(), 10);
+        assert_eq!(updates.iter().map(|update| update.rows).collect::<Vec<_>>(), (1..=10).map(|i| i * 1000).collect::<Vec<_>>());
+        assert!(updates.windows(2).all(|pair| pair[0].bytes < pair[1].bytes && pair[0].elapsed <= pair[1].elapsed));
+        assert_eq!(report.bytes, data.len() as u64);
+        assert!(updates[9].bytes <= report.bytes);
+    }
+
+    #[test]
+    fn test_debug_output_summarizes_bank() {
+        let bank = Bank::new();
+        process(&bank, TxType::Deposit, 1, 20000, None).unwrap();
+        process(&bank, TxType::Dispute, 1, 0, None).unwrap();
+        process(&bank, TxType::Chargeback, 1, 0, None).unwrap();
+
+        assert_eq!(format!("{:?}", bank), "Bank { accounts: 1, transactions: 1, locked_accounts: 1 }");
+    }
+
+    #[test]
+    fn test_debug_output_while_accounts_locked() {
+        let bank = Bank::new();
+        let tokio_bank = Bank::new_for_tokio(&bank);
+        let (locked, wait_for_lock) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let holder = std::thread::spawn(move || {
+            let _accounts = tokio_bank.accounts.lock();
+            locked.send(()).unwrap();
+            released.recv().unwrap();
+        });
+        wait_for_lock.recv().unwrap();
+
+        let started = std::time::Instant::now();
+        let output = format!("{:?}", bank);
+
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(output, "Bank { accounts: <busy>, transactions: 0, locked_accounts: <busy> }");
+        release.send(()).unwrap();
+        holder.join().unwrap();
+        assert_eq!(format!("{:?}", bank), "Bank { accounts: 0, transactions: 0, locked_accounts: 0 }");
+    }
+
+    #[test]
+    fn test_vendor_column_names() {
+        let bank = Bank::new();
+        let column_names = [("txn_type", "type"), ("customer", "client"), ("reference", "tx"), ("value", "amount")];
+        let options = ProcessOptions {
+            column_names: column_names.iter().map(|(from, to)| (from.to_string(), to.to_string())).collect(),
+            ..ProcessOptions::default()
+        };
+        let data = "txn_type, customer, reference, value\ndeposit, 1, 1, 2.0\nwithdrawal, 1, 2, 0.5\ndispute, 1, 2,\n";
+
+        let report = bank.process_transactions_from_reader(data.as_bytes(), &options).unwrap();
+
+        assert_eq!((report.rows, report.applied), (3, 3));
+        assert_eq!(balances(&bank, 1), (10000, 5000, false));
+    }
+
+    #[test]
+    fn test_concurrent_disputes_deposits_and_writes() {
+        let bank = Bank::new();
+        let clients: Vec<_> = (1..=4u16).map(|client| {
+            let bank = Bank::new_for_tokio(&bank);
+            std::thread::spawn(move || {
+                for i in 0..250 {
+                    let tx = client as u32 * 1000 + i;
+                    apply(&bank, TxType::Deposit, client, tx, Some(10000)).unwrap();
+                    apply(&bank, TxType::Dispute, client, tx, None).unwrap();
+                    apply(&bank, TxType::Resolve, client, tx, None).unwrap();
+                }
+            })
+        }).collect();
+        let writer = {
+            let bank = Bank::new_for_tokio(&bank);
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    bank.write_accounts_to(std::io::sink()).unwrap();
+                    bank.stats();
+                }
+            })
+        };
+        for thread in clients {
+            thread.join().unwrap();
+        }
+        writer.join().unwrap();
+
+        for client in 1..=4 {
+            assert_eq!(balances(&bank, client), (2_500_000, 0, false));
+        }
+        assert_eq!(bank.stats().transactions, 1000);
+    }
+
+    #[test]
+    fn test_statement_running_balances() {
+        let bank = Bank::with_config(BankConfig { record_statements: true, ..BankConfig::default() });
+        apply(&bank, TxType::Deposit, 1, 1, Some(50000)).unwrap();
+        apply(&bank, TxType::Withdrawal, 1, 2, Some(15000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+        apply(&bank, TxType::Withdrawal, 1, 3, Some(10000)).unwrap_err();
+        apply(&bank, TxType::Resolve, 1, 1, None).unwrap();
+        apply(&bank, TxType::Deposit, 2, 4, Some(10000)).unwrap();
+
+        let mut output = Vec::new();
+        bank.write_statement(ClientId(1), &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "tx,type,amount,available_after,held_after,source\n\
+            1,deposit,5.0,5.0,0.0,\n\
+            2,withdrawal,1.5,3.5,0.0,\n\
+            1,dispute,5.0,-1.5,5.0,\n\
+            1,resolve,5.0,3.5,0.0,\n");
+    }
+
+    #[test]
+    fn test_statement_off_by_default() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(50000)).unwrap();
+
+        let mut output = Vec::new();
+        bank.write_statement(ClientId(1), &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "tx,type,amount,available_after,held_after,source\n");
+    }
+
+    #[test]
+    fn test_open_disputes_through_dispute_lifecycle() {
+        let bank = Bank::with_config(BankConfig { extended_output: true, ..BankConfig::default() });
+        let open_disputes = || bank.account_snapshot(ClientId(1)).unwrap().open_disputes;
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 1, 2, Some(20000)).unwrap();
+
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+        apply(&bank, TxType::Dispute, 1, 2, None).unwrap();
+        assert_eq!(open_disputes(), 2);
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked,open_disputes\n1,0.0,3.0,3.0,false,2\n");
+        apply(&bank, TxType::Resolve, 1, 1, None).unwrap();
+        assert_eq!(open_disputes(), 1);
+        apply(&bank, TxType::Chargeback, 1, 2, None).unwrap();
+        assert_eq!(open_disputes(), 0);
+
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked,open_disputes\n1,1.0,0.0,1.0,true,0\n");
+    }
+
+    #[test]
+    fn test_default_output_has_no_open_disputes_column() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().open_disputes, 1);
+        assert_eq!(accounts_output(&bank), "client,available,held,total,locked\n1,0.0,1.0,1.0,false\n");
+    }
+
+    #[test]
+    fn test_write_transactions_sorted_with_disputed_flag() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 2, 3, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 1, 1, Some(25000)).unwrap();
+        apply(&bank, TxType::Withdrawal, 1, 2, Some(5000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+
+        let mut output = Vec::new();
+        bank.write_transactions(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tx,client,type,amount,disputed,source\n1,1,deposit,2.5,true,\n2,1,withdrawal,0.5,false,\n3,2,deposit,1.0,false,\n"
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_empty_file_has_no_rows() {
+        let bank = Bank::new();
+
+        let report = bank.process_transactions_from_csv_path("tests/fixtures/empty.csv").await.unwrap();
+
+        assert_eq!(report, ProcessReport::default());
+        assert!(bank.accounts.lock().is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_header_only_file_has_no_rows() {
+        let bank = Bank::new();
+
+        let report = bank.process_transactions_from_csv_path("tests/fixtures/header_only.csv").await.unwrap();
+
+        assert_eq!(report, ProcessReport { bytes: 25, ..ProcessReport::default() });
+        assert!(bank.accounts.lock().is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_transactions_attributed_to_their_file() {
+        let bank = Bank::with_config(BankConfig { record_statements: true, ..BankConfig::default() });
+        bank.process_transactions_from_csv_path("tests/fixtures/split_part_1.csv").await.unwrap();
+        bank.process_transactions_from_csv_path("tests/fixtures/split_part_2.csv").await.unwrap();
+
+        let mut ledger = Vec::new();
+        bank.write_transactions(&mut ledger).unwrap();
+        assert_eq!(String::from_utf8(ledger).unwrap(), "tx,client,type,amount,disputed,source\n\
+            1,1,deposit,5.0,true,tests/fixtures/split_part_1.csv\n\
+            2,2,deposit,1.0,false,tests/fixtures/split_part_1.csv\n\
+            3,2,withdrawal,0.5,false,tests/fixtures/split_part_2.csv\n");
+
+        let mut statement = Vec::new();
+        bank.write_statement(ClientId(1), &mut statement).unwrap();
+        assert_eq!(String::from_utf8(statement).unwrap(), "tx,type,amount,available_after,held_after,source\n\
+            1,deposit,5.0,5.0,0.0,tests/fixtures/split_part_1.csv\n\
+            1,dispute,5.0,0.0,5.0,tests/fixtures/split_part_2.csv\n\
+            1,chargeback,5.0,0.0,0.0,tests/fixtures/split_part_2.csv\n");
+
+        let source = bank.transactions.lock().get(TxId(3)).unwrap().source.unwrap();
+        assert_eq!(bank.source_path(source).as_deref(), Some("tests/fixtures/split_part_2.csv"));
+    }
+
+    #[test]
+    fn test_resolve_without_dispute_rejected_when_lenient() {
+        let data = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nresolve, 1, 1,\nchargeback, 1, 9,\n";
+
+        let report = Bank::new().process_transactions_from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap();
+
+        assert_eq!(report.rejected_for(RejectionReason::NotDisputed), 1);
+        assert_eq!(report.rejected_for(RejectionReason::UnknownTx), 1);
+        assert_eq!(report.parse_errors, 0);
+    }
+
+    #[test]
+    fn test_resolve_without_dispute_row_error_when_strict() {
+        let data = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nresolve, 1, 1,\nchargeback, 1, 9,\ndispute, 1, 1,\nresolve, 1, 1,\n";
+        let bank = Bank::with_config(BankConfig { strict_disputes: true, ..BankConfig::default() });
+
+        let report = bank.process_transactions_from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap();
+
+        assert_eq!((report.applied, report.rejected(), report.parse_errors), (3, 0, 2));
+        assert_eq!(report.row_errors, vec![
+            LineError { line: 3, error: RowError::NotDisputed { type_: TxType::Resolve, tx: TxId(1) } },
+            LineError { line: 4, error: RowError::NotDisputed { type_: TxType::Chargeback, tx: TxId(9) } },
+        ]);
+        assert_eq!(report.row_errors[0].to_string(), "line 3: resolve of tx 1 which is not disputed");
+    }
+
+    #[test]
+    fn test_accounts_numbered_in_creation_order() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 7, 1, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 3, 2, Some(10000)).unwrap();
+        apply(&bank, TxType::Deposit, 7, 3, Some(10000)).unwrap();
+        bank.open_account(ClientId(5));
+
+        let created_seq = |client| bank.account_snapshot(ClientId(client)).unwrap().created_seq;
+        assert_eq!((created_seq(7), created_seq(3), created_seq(5)), (1, 2, 3));
+    }
+
+    #[test]
+    fn test_write_accounts_json() {
+        let bank = Bank::new();
+        let json = |bank: &Bank| {
+            let mut output = Vec::new();
+            bank.write_accounts_json(&mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(json(&bank), "[]\n");
+
+        apply(&bank, TxType::Deposit, 1, 1, Some(15000)).unwrap();
+        bank.apply(TxRequest { type_: TxType::Deposit, client: ClientId(1), tx: TxId(2), amount: Some(20000), currency: Some("EUR".parse().unwrap()) }).unwrap();
+
+        assert_eq!(json(&bank), "[{\"client\":1,\"currency\":\"USD\",\"available\":\"1.5\",\"held\":\"0.0\",\"total\":\"1.5\",\"locked\":false},\
+            {\"client\":1,\"currency\":\"EUR\",\"available\":\"2.0\",\"held\":\"0.0\",\"total\":\"2.0\",\"locked\":false}]\n");
+    }
+
+    fn bank_with_fees(fee_schedule: FeeSchedule) -> Bank {
+        Bank::with_config(BankConfig { fee_schedule, ..BankConfig::default() })
+    }
+
+    #[test]
+    fn test_flat_withdrawal_fee() {
+        let bank = bank_with_fees(FeeSchedule::Flat(5000));
+        apply(&bank, TxType::Deposit, 1, 1, Some(100000)).unwrap();
+
+        apply(&bank, TxType::Withdrawal, 1, 2, Some(40000)).unwrap();
+        apply(&bank, TxType::Deposit, 1, 3, Some(40000)).unwrap();
+
+        assert_eq!(balances(&bank, 1), (95000, 0, false));
+        assert_eq!(bank.stats().collected_fees, 5000);
+    }
+
+    #[test]
+    fn test_negative_withdrawal_pays_no_fee() {
+        let bank = bank_with_fees(FeeSchedule::Percentage { basis_points: 25, minimum: 100 });
+        apply(&bank, TxType::Deposit, 1, 1, Some(100000)).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Withdrawal, 1, 2, Some(-40000)), Err(RejectionReason::NegativeAmount));
+
+        assert_eq!(balances(&bank, 1), (100000, 0, false));
+        assert_eq!(bank.stats().collected_fees, 0);
+    }
+
+    #[test]
+    fn test_percentage_withdrawal_fee_rounded_half_up() {
+        let bank = bank_with_fees(FeeSchedule::Percentage { basis_points: 25, minimum: 0 });
+        apply(&bank, TxType::Deposit, 1, 1, Some(100000)).unwrap();
+
+        // 0.25% of 1.02 is 0.00255 and of 1.01 is 0.002525
+        apply(&bank, TxType::Withdrawal, 1, 2, Some(10200)).unwrap();
+        apply(&bank, TxType::Withdrawal, 1, 3, Some(10100)).unwrap();
+
+        assert_eq!(balances(&bank, 1), (100000 - 10200 - 26 - 10100 - 25, 0, false));
+        assert_eq!(bank.stats().collected_fees, 51);
+    }
+
+    #[test]
+    fn test_percentage_withdrawal_fee_minimum() {
+        let bank = bank_with_fees(FeeSchedule::Percentage { basis_points: 25, minimum: 1000 });
+        apply(&bank, TxType::Deposit, 1, 1, Some(1000000)).unwrap();
+
+        // 0.25% of 10.0 is 0.025, below the minimum of 0.1, while 0.25% of 80.0 is 0.2
+        apply(&bank, TxType::Withdrawal, 1, 2, Some(100000)).unwrap();
+        apply(&bank, TxType::Withdrawal, 1, 3, Some(800000)).unwrap();
+
+        assert_eq!(balances(&bank, 1), (1000000 - 100000 - 1000 - 800000 - 2000, 0, false));
+        assert_eq!(bank.collected_fees(), HashMap::from([(CurrencyCode::USD, 3000)]));
+    }
+
+    #[test]
+    fn test_dispute_of_withdrawal_holds_principal_only() {
+        let bank = bank_with_fees(FeeSchedule::Flat(5000));
+        apply(&bank, TxType::Deposit, 1, 1, Some(100000)).unwrap();
+        apply(&bank, TxType::Withdrawal, 1, 2, Some(40000)).unwrap();
+
+        apply(&bank, TxType::Dispute, 1, 2, None).unwrap();
+
+        assert_eq!(balances(&bank, 1), (15000, 40000, false));
+    }
+
+    #[test]
+    fn test_withdrawal_rejected_when_fee_not_covered() {
+        let bank = bank_with_fees(FeeSchedule::Flat(5000));
+        apply(&bank, TxType::Deposit, 1, 1, Some(100000)).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Withdrawal, 1, 2, Some(100000)), Err(RejectionReason::InsufficientFunds));
+        assert_eq!(balances(&bank, 1), (100000, 0, false));
+        apply(&bank, TxType::Withdrawal, 1, 3, Some(95000)).unwrap();
+        assert_eq!(balances(&bank, 1), (0, 0, false));
+    }
+
+    #[test]
+    fn test_partial_chargeback_releases_remainder() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(50000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+
+        apply(&bank, TxType::Chargeback, 1, 1, Some(20000)).unwrap();
+
+        assert_eq!(balances(&bank, 1), (30000, 0, true));
+    }
+
+    #[test]
+    fn test_partial_chargeback_holds_remainder() {
+        let config = BankConfig { partial_chargeback_remainder: PartialChargebackRemainder::Hold, ..BankConfig::default() };
+        let bank = Bank::with_config(config);
+        apply(&bank, TxType::Deposit, 1, 1, Some(50000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+
+        apply(&bank, TxType::Chargeback, 1, 1, Some(20000)).unwrap();
+
+        assert_eq!(balances(&bank, 1), (0, 30000, true));
+    }
+
+    #[test]
+    fn test_partial_chargeback_refused_when_released_remainder_would_overflow() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 10, Some(10)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 10, None).unwrap();
+        apply(&bank, TxType::Deposit, 1, 11, Some(i64::MAX)).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Chargeback, 1, 10, Some(5)), Err(RejectionReason::Overflow));
+
+        assert_eq!(balances(&bank, 1), (i64::MAX, 10, false));
+        assert!(bank.transactions.lock().get(TxId(10)).unwrap().disputed);
+    }
+
+    #[test]
+    fn test_chargeback_without_amount_charges_back_in_full() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(50000)).unwrap();
+        apply(&bank, TxType::Deposit, 1, 2, Some(10000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+
+        apply(&bank, TxType::Chargeback, 1, 1, None).unwrap();
+
+        assert_eq!(balances(&bank, 1), (10000, 0, true));
+    }
+
+    #[test]
+    fn test_partial_chargeback_larger_than_disputed_amount_rejected() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(50000)).unwrap();
+        apply(&bank, TxType::Dispute, 1, 1, None).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Chargeback, 1, 1, Some(50001)), Err(RejectionReason::InvalidChargebackAmount));
+        assert_eq!(balances(&bank, 1), (0, 50000, false));
+    }
+
+    #[test]
+    fn test_verified_meta_amounts() {
+        let bank = Bank::with_config(BankConfig { verify_meta_amounts: true, ..BankConfig::default() });
+        apply(&bank, TxType::Deposit, 1, 1, Some(50000)).unwrap();
+        apply(&bank, TxType::Deposit, 1, 2, Some(20000)).unwrap();
+
+        assert_eq!(apply(&bank, TxType::Dispute, 1, 1, Some(40000)), Err(RejectionReason::AmountMismatch));
+        assert_eq!(balances(&bank, 1), (70000, 0, false));
+        apply(&bank, TxType::Dispute, 1, 1, Some(50000)).unwrap();
+        assert_eq!(apply(&bank, TxType::Resolve, 1, 1, Some(5000)), Err(RejectionReason::AmountMismatch));
+        assert_eq!(balances(&bank, 1), (20000, 50000, false));
+        apply(&bank, TxType::Resolve, 1, 1, Some(50000)).unwrap();
+
+        apply(&bank, TxType::Dispute, 1, 2, None).unwrap();
+        apply(&bank, TxType::Resolve, 1, 2, None).unwrap();
+        assert_eq!(balances(&bank, 1), (70000, 0, false));
+    }
+
+    #[test]
+    fn test_meta_amounts_ignored_by_default() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(50000)).unwrap();
+
+        apply(&bank, TxType::Dispute, 1, 1, Some(40000)).unwrap();
+
+        assert_eq!(balances(&bank, 1), (0, 50000, false));
+    }
+
+    const USD: CurrencyCode = CurrencyCode::USD;
+
+    fn amount(value: i64) -> Amount {
+        Amount { value }
+    }
+
+    fn account_with(available: i64, held: i64) -> Account {
+        let mut account = Account::new(ClientId(1));
+        account.available = amount(available);
+        account.held = amount(held);
+        account.update_peaks();
+        account
+    }
+
+    fn values(account: &Account) -> (i64, i64, bool) {
+        (account.available.value, account.held.value, account.locked)
+    }
+
+    #[test]
+    fn test_account_deposit_and_withdraw() {
+        let mut account = Account::new(ClientId(1));
+
+        account.deposit(amount(30000), USD, USD).unwrap();
+        account.withdraw(amount(10000), amount(500), USD, USD).unwrap();
+
+        assert_eq!(values(&account), (19500, 0, false));
+        assert_eq!(account.peak_available, 30000);
+    }
+
+    #[test]
+    fn test_account_deposit_overflow_leaves_balance() {
+        let mut account = account_with(i64::MAX - 1, 0);
+
+        assert_eq!(account.deposit(amount(2), USD, USD), Err(RejectionReason::Overflow));
+        assert_eq!(values(&account), (i64::MAX - 1, 0, false));
+    }
+
+    #[test]
+    fn test_account_withdraw_needs_amount_and_fee() {
+        let mut account = account_with(10000, 0);
+
+        assert_eq!(account.withdraw(amount(10000), amount(1), USD, USD), Err(RejectionReason::InsufficientFunds));
+        assert_eq!(account.withdraw(amount(i64::MIN), amount(0), USD, USD), Err(RejectionReason::InsufficientFunds));
+        assert_eq!(values(&account), (10000, 0, false));
+        account.withdraw(amount(9999), amount(1), USD, USD).unwrap();
+        assert_eq!(values(&account), (0, 0, false));
+    }
+
+    #[test]
+    fn test_locked_account_refuses_every_change() {
+        let mut account = account_with(10000, 5000);
+        account.locked = true;
+
+        assert_eq!(account.deposit(amount(1), USD, USD), Err(RejectionReason::AccountLocked));
+        assert_eq!(account.withdraw(amount(1), amount(0), USD, USD), Err(RejectionReason::AccountLocked));
+        assert_eq!(account.hold(amount(1), USD, USD), Err(RejectionReason::AccountLocked));
+        assert_eq!(account.release(amount(1), USD, USD), Err(RejectionReason::AccountLocked));
+        let outcome = account.charge_back(amount(1), amount(1), PartialChargebackRemainder::Hold, USD, USD);
+        assert_eq!(outcome, Err(RejectionReason::AccountLocked));
+        assert_eq!(values(&account), (10000, 5000, true));
+    }
+
+    #[test]
+    fn test_account_hold_can_take_available_negative() {
+        let mut account = account_with(1000, 0);
+
+        account.hold(amount(5000), USD, USD).unwrap();
+
+        assert_eq!(values(&account), (-4000, 5000, false));
+        assert_eq!((account.peak_available, account.peak_held), (1000, 5000));
+    }
+
+    #[test]
+    fn test_account_hold_overflow_leaves_balances() {
+        let mut account = account_with(0, i64::MAX);
+
+        assert_eq!(account.hold(amount(1), USD, USD), Err(RejectionReason::Overflow));
+        assert_eq!(values(&account), (0, i64::MAX, false));
+    }
+
+    #[test]
+    fn test_account_release_needs_held_amount() {
+        let mut account = account_with(0, 5000);
+
+        assert_eq!(account.release(amount(5001), USD, USD), Err(RejectionReason::InsufficientHeld));
+        account.release(amount(5000), USD, USD).unwrap();
+
+        assert_eq!(values(&account), (5000, 0, false));
+    }
+
+    #[test]
+    fn test_account_charge_back_whole_amount() {
+        let mut account = account_with(2000, 5000);
+
+        let shortfall = account.charge_back(amount(5000), amount(5000), PartialChargebackRemainder::Release, USD, USD);
+
+        assert_eq!(shortfall, Ok(0));
+        assert_eq!(values(&account), (2000, 0, true));
+    }
+
+    #[test]
+    fn test_account_charge_back_remainder() {
+        let mut released = account_with(0, 5000);
+        let mut kept = account_with(0, 5000);
+
+        released.charge_back(amount(5000), amount(2000), PartialChargebackRemainder::Release, USD, USD).unwrap();
+        kept.charge_back(amount(5000), amount(2000), PartialChargebackRemainder::Hold, USD, USD).unwrap();
+
+        assert_eq!(values(&released), (3000, 0, true));
+        assert_eq!(values(&kept), (0, 3000, true));
+    }
+
+    #[test]
+    fn test_account_charge_back_returns_shortfall() {
+        let mut account = account_with(-3000, 5000);
+
+        let shortfall = account.charge_back(amount(5000), amount(5000), PartialChargebackRemainder::Release, USD, USD);
+
+        assert_eq!(shortfall, Ok(3000));
+        assert_eq!(values(&account), (-3000, 0, true));
+    }
+
+    #[test]
+    fn test_account_charge_back_refusals_leave_account_unlocked() {
+        let mut account = account_with(0, 5000);
+
+        let mut charge_back = |disputed, charged| {
+            account.charge_back(amount(disputed), amount(charged), PartialChargebackRemainder::Release, USD, USD)
+        };
+        assert_eq!(charge_back(5000, -1), Err(RejectionReason::InvalidChargebackAmount));
+        assert_eq!(charge_back(5000, 5001), Err(RejectionReason::InvalidChargebackAmount));
+        assert_eq!(charge_back(6000, 6000), Err(RejectionReason::InsufficientHeld));
+        assert_eq!(values(&account), (0, 5000, false));
+    }
+
+    #[test]
+    fn test_account_methods_use_currency_balances() {
+        let eur: CurrencyCode = "EUR".parse().unwrap();
+        let mut account = account_with(10000, 0);
+
+        account.deposit(amount(7000), eur, USD).unwrap();
+        account.hold(amount(3000), eur, USD).unwrap();
+        assert_eq!(account.withdraw(amount(5000), amount(0), eur, USD), Err(RejectionReason::InsufficientFunds));
+        account.release(amount(1000), eur, USD).unwrap();
+
+        let balance = account.currencies.get(&eur).unwrap();
+        assert_eq!((balance.available.value, balance.held.value), (5000, 2000));
+        assert_eq!(values(&account), (10000, 0, false));
+    }
+}
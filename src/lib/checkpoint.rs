@@ -0,0 +1,267 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use crate::error::BankError;
+use crate::journal::Journal;
+
+/// Where processing a csv file can carry on from, the start of the row after the last one processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ResumePoint {
+    /// Offset into the file
+    pub(crate) byte: u64,
+    /// Line of the file the row starts on
+    pub(crate) line: u64,
+}
+
+/// The size and modification time of a csv file, to tell whether it changed since its checkpoint was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileIdentity {
+    len: u64,
+    /// Nanoseconds since the Unix epoch, zero where the platform doesn't keep it
+    modified: u128,
+}
+
+impl FileIdentity {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified().ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since_epoch| since_epoch.as_nanos());
+        Ok(Self { len: metadata.len(), modified })
+    }
+}
+
+/// Writes the [ResumePoint] of a csv file being processed to a file next to it every so many rows,
+/// see [crate::config::ProcessOptions::checkpoint_every]
+///
+/// The checkpoint file holds a single line of the byte offset and line followed by the size and
+/// modification time of the csv, e.g. `1024,37,52810,1718000000000000000`.
+/// If the bank has a journal, it is synced before each checkpoint is written, so the checkpoint
+/// never gets ahead of the transactions the journal holds.
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    every: usize,
+    /// Rows processed since the checkpoint was last written
+    rows: usize,
+    /// The csv as it was when processing started, None if its metadata couldn't be read
+    identity: Option<FileIdentity>,
+    journal: Option<Arc<Journal>>,
+}
+
+impl Checkpoint {
+    pub(crate) fn new(csv_path: &str, every: usize, journal: Option<Arc<Journal>>) -> Self {
+        let identity = FileIdentity::of(Path::new(csv_path)).ok();
+        Self { path: Self::path_of(csv_path), every: every.max(1), rows: 0, identity, journal }
+    }
+
+    /// The checkpoint file of the csv at the given path, `transactions.csv.checkpoint` for `transactions.csv`
+    fn path_of(csv_path: &str) -> PathBuf {
+        PathBuf::from(format!("{}.checkpoint", csv_path))
+    }
+
+    /// Reads the checkpoint of the csv at the given path, None if there is none
+    ///
+    /// Fails if the csv's size or modification time changed since the checkpoint was written,
+    /// as the checkpoint's offset may no longer be the start of a row.
+    pub(crate) fn read(csv_path: &str) -> Result<Option<ResumePoint>, BankError> {
+        let path = Self::path_of(csv_path);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let Some((point, identity)) = parse(&content) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid checkpoint in {}: {:?}", path.display(), content)).into());
+        };
+        if FileIdentity::of(Path::new(csv_path))? != identity {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} changed since its checkpoint was written", csv_path)).into());
+        }
+        Ok(Some(point))
+    }
+
+    /// Counts a processed row, writing where the next one starts every [Checkpoint::every] rows
+    pub(crate) fn row_processed(&mut self, next: ResumePoint) {
+        self.rows += 1;
+        if self.rows >= self.every {
+            self.write(next);
+        }
+    }
+
+    /// Writes where the next row starts, e.g. at the end of the file once all rows are processed
+    ///
+    /// The checkpoint is replaced in one go, so a crash while writing leaves the previous one.
+    /// Failing to write it is logged and processing carries on, it only costs rows processed again.
+    /// It is not written if the bank's journal can't be synced, or if the csv's metadata couldn't be read.
+    pub(crate) fn write(&mut self, next: ResumePoint) {
+        self.rows = 0;
+        let Some(identity) = self.identity else {
+            return;
+        };
+        if let Some(Err(e)) = self.journal.as_ref().map(|journal| journal.sync()) {
+            tracing::error!(path = %self.path.display(), error = %e, "not writing checkpoint, the journal could not be synced");
+            return;
+        }
+        let temporary = self.path.with_extension("checkpoint.tmp");
+        let written = fs::write(&temporary, format!("{},{},{},{}\n", next.byte, next.line, identity.len, identity.modified))
+            .and_then(|()| fs::rename(&temporary, &self.path));
+        if let Err(e) = written {
+            tracing::error!(path = %self.path.display(), error = %e, "failed to write checkpoint");
+        }
+    }
+}
+
+/// The resume point and file identity of the content of a checkpoint file
+fn parse(content: &str) -> Option<(ResumePoint, FileIdentity)> {
+    let fields: Vec<&str> = content.trim().split(',').collect();
+    let [byte, line, len, modified] = fields[..] else {
+        return None;
+    };
+    let point = ResumePoint { byte: byte.parse().ok()?, line: line.parse().ok()? };
+    Some((point, FileIdentity { len: len.parse().ok()?, modified: modified.parse().ok()? }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use crate::bank::Bank;
+    use crate::config::{BankConfig, DuplicateTxIdPolicy, ProcessOptions};
+    use crate::error::BankError;
+    use crate::journal::Journal;
+    use crate::report::{RejectionReason, RowError};
+    use crate::shared_types::ClientId;
+
+    /// Twenty rows of three clients, with a malformed row on line 19 and a dispute and resolve
+    fn transactions() -> String {
+        let mut data = "type, client, tx, amount\n".to_string();
+        for tx in 1..=16 {
+            let type_ = if tx % 4 == 0 { "withdrawal" } else { "deposit" };
+            data.push_str(&format!("{}, {}, {}, {}.5\n", type_, tx % 3 + 1, tx, tx));
+        }
+        data.push_str("dispute, 2, 1,\ndeposit, x, 17, 1.0\nresolve, 2, 1,\ndeposit, 1, 18, 2.0\n");
+        data
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn balances(bank: &Bank) -> Vec<Option<(i64, i64)>> {
+        (1..=3).map(|client| bank.account_snapshot(ClientId(client)).map(|account| (account.available, account.held))).collect()
+    }
+
+    #[test]
+    fn test_resume_after_crash_matches_uninterrupted_run() {
+        let dir = temp_dir("bank_lib_test_checkpoint_resume");
+        let csv_path = dir.join("transactions.csv");
+        // A byte order mark and an oversized row shift the csv reader's offsets against the file's
+        let oversized = format!("deposit,3,99,\"{}\"\n", "9\n".repeat(600));
+        fs::write(&csv_path, format!("\u{feff}{}", transactions()).replacen("dispute", &format!("{}dispute", oversized), 1)).unwrap();
+        let csv_path = csv_path.to_str().unwrap();
+        let journal = dir.join("bank.journal");
+        let config = || BankConfig { duplicate_tx_ids: DuplicateTxIdPolicy::Reject, ..BankConfig::default() };
+        let uninterrupted = Bank::with_config(config());
+        uninterrupted.process_transactions_from_csv_path_blocking(csv_path).unwrap();
+
+        let crashed = panic::catch_unwind(AssertUnwindSafe(|| {
+            let bank = Bank::with_config_and_journal(config(), &journal).unwrap();
+            let options = ProcessOptions {
+                checkpoint_every: Some(4),
+                progress_interval: 1,
+                on_progress: Some(std::sync::Arc::new(|update| assert!(update.rows < 10, "crash"))),
+                ..ProcessOptions::default()
+            };
+            bank.process_transactions_from_csv_path_with_options_blocking(csv_path, &options)
+        }));
+        assert!(crashed.is_err());
+        assert_eq!(fs::read_to_string(format!("{}.checkpoint", csv_path)).unwrap().split(',').nth(1), Some("10"));
+
+        let bank = Bank::recover_from_journal_with_config(config(), &journal).unwrap();
+        let options = ProcessOptions { checkpoint_every: Some(4), resume: true, ..ProcessOptions::default() };
+        let report = bank.process_transactions_from_csv_path_with_options_blocking(csv_path, &options).unwrap();
+
+        // Rows 9 and 10 were processed before the crash but after the checkpoint
+        assert_eq!((report.rows, report.rejected_for(RejectionReason::DuplicateTxId)), (13, 2));
+        assert_eq!((report.row_errors[1].line, &report.row_errors[1].error), (620, &RowError::InvalidField { column: "client".to_string(), value: "x".to_string() }));
+        assert_eq!(balances(&bank), balances(&uninterrupted));
+
+        let report = bank.process_transactions_from_csv_path_with_options_blocking(csv_path, &options).unwrap();
+        assert_eq!(report.rows, 0);
+        assert_eq!(balances(&bank), balances(&uninterrupted));
+    }
+
+    #[test]
+    fn test_pipelined_run_checkpoints_end_of_file() {
+        let dir = temp_dir("bank_lib_test_checkpoint_pipelined");
+        let csv_path = dir.join("transactions.csv");
+        let data = transactions();
+        fs::write(&csv_path, &data).unwrap();
+        let csv_path = csv_path.to_str().unwrap();
+        let options = ProcessOptions { pipeline: Some(1), checkpoint_every: Some(3), resume: true, ..ProcessOptions::default() };
+        let bank = Bank::new();
+
+        assert_eq!(bank.process_transactions_from_csv_path_with_options_blocking(csv_path, &options).unwrap().rows, 20);
+
+        assert!(fs::read_to_string(format!("{}.checkpoint", csv_path)).unwrap().starts_with(&format!("{},22,{},", data.len(), data.len())));
+        assert_eq!(bank.process_transactions_from_csv_path_with_options_blocking(csv_path, &options).unwrap().rows, 0);
+    }
+
+    #[test]
+    fn test_file_without_checkpoint_read_from_start() {
+        let dir = temp_dir("bank_lib_test_checkpoint_missing");
+        let csv_path = dir.join("transactions.csv");
+        fs::write(&csv_path, transactions()).unwrap();
+        let csv_path = csv_path.to_str().unwrap();
+        let options = ProcessOptions { resume: true, ..ProcessOptions::default() };
+
+        let report = Bank::new().process_transactions_from_csv_path_with_options_blocking(csv_path, &options).unwrap();
+        assert_eq!(report.rows, 20);
+
+        fs::write(format!("{}.checkpoint", csv_path), "not a checkpoint").unwrap();
+        let error = Bank::new().process_transactions_from_csv_path_with_options_blocking(csv_path, &options).unwrap_err();
+        assert!(error.to_string().contains("invalid checkpoint"), "{}", error);
+    }
+
+    #[test]
+    fn test_resume_refused_after_file_changed() {
+        let dir = temp_dir("bank_lib_test_checkpoint_changed");
+        let csv_path = dir.join("transactions.csv");
+        fs::write(&csv_path, transactions()).unwrap();
+        let csv_path = csv_path.to_str().unwrap();
+        let options = ProcessOptions { checkpoint_every: Some(4), resume: true, ..ProcessOptions::default() };
+        Bank::new().process_transactions_from_csv_path_with_options_blocking(csv_path, &options).unwrap();
+
+        fs::write(csv_path, format!("{}deposit, 1, 19, 1.0\n", transactions())).unwrap();
+
+        let error = Bank::new().process_transactions_from_csv_path_with_options_blocking(csv_path, &options).unwrap_err();
+        assert!(error.to_string().contains("changed since its checkpoint"), "{}", error);
+    }
+
+    #[test]
+    fn test_no_checkpoint_ahead_of_failed_journal() {
+        let dir = temp_dir("bank_lib_test_checkpoint_journal_failed");
+        let csv_path = dir.join("transactions.csv");
+        fs::write(&csv_path, transactions()).unwrap();
+        let csv_path = csv_path.to_str().unwrap();
+        let journal_path = dir.join("bank.journal");
+        fs::write(&journal_path, "").unwrap();
+        // Opened for reading only, so the first append fails
+        let journal = Journal::from_file(fs::File::open(&journal_path).unwrap(), 0);
+        let bank = Bank { journal: Some(Arc::new(journal)), ..Bank::new() };
+        let options = ProcessOptions { checkpoint_every: Some(1), ..ProcessOptions::default() };
+
+        let outcome = bank.process_transactions_from_csv_path_with_options_blocking(csv_path, &options);
+
+        assert!(matches!(outcome, Err(BankError::Journal(_))));
+        assert!(!PathBuf::from(format!("{}.checkpoint", csv_path)).exists());
+    }
+}
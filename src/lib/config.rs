@@ -0,0 +1,366 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::report::{ProgressUpdate, RejectionReason};
+use crate::shared_types::{Amount, AmountValue, ClientId, CurrencyCode};
+use crate::transaction::TxType;
+
+/// What to do with a deposit or withdrawal of exactly zero
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ZeroAmountPolicy {
+    /// The transaction is not processed and is counted as invalid
+    #[default]
+    Reject,
+    /// The transaction is processed like any other and stored on the transaction sheet
+    Accept,
+}
+
+/// What happens to the rest of a disputed amount when a chargeback only charges back part of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PartialChargebackRemainder {
+    /// The rest is moved back to the available balance
+    #[default]
+    Release,
+    /// The rest stays held
+    Hold,
+}
+
+/// How the amounts of the account output are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AmountOutputFormat {
+    /// As decimal numbers, e.g. `1.5`
+    #[default]
+    Decimal,
+    /// As integers scaled like the bank stores them, e.g. `15000` for `1.5` with the default four
+    /// [BankConfig::decimal_places], for lossless interchange with other systems keeping amounts as integers
+    ScaledInteger,
+}
+
+/// What to do with a deposit or withdrawal reusing the tx id of one already on the transaction sheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateTxIdPolicy {
+    /// A transaction of the other type is rejected, so disputes keep referencing the transaction
+    /// they were meant for. One of the same type replaces the stored transaction.
+    #[default]
+    RejectOtherType,
+    /// Every reuse of a tx id is rejected
+    Reject,
+    /// The new transaction replaces the stored one
+    Overwrite,
+}
+
+impl DuplicateTxIdPolicy {
+    /// Whether a transaction of type `new` reusing the tx id of a stored one of type `stored` is rejected
+    pub(crate) fn rejects(self, stored: TxType, new: TxType) -> bool {
+        match self {
+            DuplicateTxIdPolicy::RejectOtherType => stored != new,
+            DuplicateTxIdPolicy::Reject => true,
+            DuplicateTxIdPolicy::Overwrite => false,
+        }
+    }
+}
+
+/// The fee charged on each withdrawal, taken from the available balance on top of the amount withdrawn
+///
+/// Fees are in the bank's [internal integer representation](Amount) like amounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FeeSchedule {
+    #[default]
+    None,
+    /// The same fee on every withdrawal
+    Flat(AmountValue),
+    /// A share of the amount withdrawn in basis points, e.g. `25` is 0.25%, rounded half up to the
+    /// last decimal place the bank keeps. Fees below `minimum` are raised to it.
+    Percentage { basis_points: u32, minimum: AmountValue },
+}
+
+impl FeeSchedule {
+    /// The fee charged on a withdrawal of the given amount
+    pub fn fee(&self, amount: AmountValue) -> AmountValue {
+        match *self {
+            FeeSchedule::None => 0,
+            FeeSchedule::Flat(fee) => fee,
+            FeeSchedule::Percentage { basis_points, minimum } => {
+                let fee = (amount as i128 * basis_points as i128 + 5_000) / 10_000;
+                AmountValue::try_from(fee).unwrap_or(AmountValue::MAX).max(minimum)
+            },
+        }
+    }
+}
+
+/// Limits on the deposits of each client, for fraud prevention and regulatory caps, none by default
+///
+/// Amounts are in the bank's [internal integer representation](Amount). Deposits in every currency are limited, each against the same amounts
+/// in its own units without any conversion. Deposits to a locked account are rejected as
+/// [RejectionReason::AccountLocked] before the limits are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    /// The largest single deposit, larger ones are rejected as [RejectionReason::ExceedsSingleLimit]
+    pub max_single_deposit: Option<Amount>,
+    /// The most a client can deposit in a currency from a single file, withdrawals don't make room again.
+    /// Deposits not read from a file, e.g. ones given to [crate::bank::Bank::apply], count together over
+    /// the life of the account. A deposit that would go over it is rejected as [RejectionReason::ExceedsCumulativeLimit].
+    pub max_cumulative_deposit: Option<Amount>,
+    /// The highest available balance an account can have in each currency, e.g. for account types with a
+    /// regulatory cap. A deposit that would take it higher is rejected as [RejectionReason::ExceedsMaxBalance].
+    /// Only deposits are checked: a resolve returning disputed funds to the available balance can take it
+    /// over the cap, as the funds were within it when deposited.
+    pub max_balance: Option<Amount>,
+}
+
+impl Limits {
+    /// Checks a deposit of the given amount by a client who deposited `deposited` before from the same file
+    /// in the same currency and has `available` in it
+    pub(crate) fn check_deposit(&self, amount: AmountValue, deposited: AmountValue, available: AmountValue) -> Result<(), RejectionReason> {
+        if self.max_single_deposit.is_some_and(|max| amount > max.value) {
+            return Err(RejectionReason::ExceedsSingleLimit);
+        }
+        if self.max_cumulative_deposit.is_some_and(|max| deposited.saturating_add(amount) > max.value) {
+            return Err(RejectionReason::ExceedsCumulativeLimit);
+        }
+        if self.max_balance.is_some_and(|max| available.saturating_add(amount) > max.value) {
+            return Err(RejectionReason::ExceedsMaxBalance);
+        }
+        Ok(())
+    }
+}
+
+/// Configuration of how a [crate::bank::Bank] processes transactions
+///
+/// The default configuration is what the payments engine spec asks for.
+#[derive(Debug, Clone)]
+pub struct BankConfig {
+    pub zero_amount_policy: ZeroAmountPolicy,
+    /// The currency of transactions that don't specify one
+    pub base_currency: CurrencyCode,
+    /// How many disputes, resolves and chargebacks referencing a not yet seen transaction
+    /// are kept to be applied once it arrives. Zero rejects them straight away.
+    pub max_pending_disputes: usize,
+    /// Whether accounts must be opened with [crate::bank::Bank::open_account] before use.
+    /// When false, the first transaction of a client opens their account.
+    pub strict_accounts: bool,
+    /// Client ids that never get an account, e.g. `0` used by a feed for an unknown client. Their
+    /// transactions are rejected as [crate::report::RejectionReason::ReservedClient]. Empty by default.
+    pub reserved_clients: HashSet<ClientId>,
+    /// Whether negative totals are written out as zero. Balances themselves are not changed.
+    pub clamp_negative_totals: bool,
+    /// How many records are appended to the journal of a bank between syncs of it to disk,
+    /// see [crate::bank::Bank::with_journal]. Zero leaves syncing to the operating system.
+    pub journal_sync_every: usize,
+    /// Whether every change to an account is recorded for [crate::bank::Bank::write_statement].
+    /// Off by default as the records grow with every transaction.
+    pub record_statements: bool,
+    /// What happens to the rest of a disputed amount when a chargeback charges back only part of it.
+    /// [PartialChargebackRemainder::Release] by default, which returns it to the available balance,
+    /// while [PartialChargebackRemainder::Hold] keeps it held until the account is dealt with otherwise.
+    pub partial_chargeback_remainder: PartialChargebackRemainder,
+    /// A withdrawal is only made if the available balance covers both it and its fee.
+    /// Fees are added up in [crate::bank::Bank::collected_fees].
+    pub fee_schedule: FeeSchedule,
+    /// Caps on single deposits, on the deposits of a client per file and on balances, see [Limits].
+    /// None by default.
+    pub limits: Limits,
+    /// Whether the account output has an `open_disputes` column after `locked`,
+    /// the number of the client's transactions currently disputed
+    pub extended_output: bool,
+    /// Whether accounts being created and locked and disputes being opened and closed are recorded
+    /// for [crate::bank::Bank::write_activity_log_csv]. Off by default as the log grows with every dispute.
+    pub record_activity: bool,
+    /// Whether a resolve or chargeback in a csv of a transaction that is not disputed, or not known,
+    /// is counted as a row error of a bad feed rather than as a rejected transaction
+    pub strict_disputes: bool,
+    /// Whether a chargeback of a transaction that is not disputed is applied as if a dispute came first,
+    /// holding the disputed amount before charging it back and locking the account. For upstreams that
+    /// send chargebacks without a dispute, off by default so such chargebacks are rejected as not disputed.
+    pub allow_direct_chargeback: bool,
+    /// How many deposits and withdrawals may be stored after a transaction for it to still be disputed.
+    /// A later dispute, or direct chargeback, is rejected as [crate::report::RejectionReason::DisputeWindowExpired].
+    /// None lets every transaction on the sheet be disputed.
+    pub dispute_window: Option<u64>,
+    /// What to do with a deposit or withdrawal reusing the tx id of a stored one.
+    /// [DuplicateTxIdPolicy::RejectOtherType] by default, which rejects a reuse by the other type but lets
+    /// a reuse by the same type replace the stored transaction, so `duplicate_window` only ever lets through
+    /// reuses by the other type. Set [DuplicateTxIdPolicy::Reject] for `duplicate_window` to decide every reuse.
+    /// [DuplicateTxIdPolicy::Overwrite] accepts every reuse, which makes `duplicate_window` inert.
+    pub duplicate_tx_ids: DuplicateTxIdPolicy,
+    /// How many of the most recently stored deposits and withdrawals `duplicate_tx_ids` applies to.
+    /// A transaction reusing an older tx id is accepted and replaces the stored one, for feeds that
+    /// reuse ids after a while. None applies it to every stored transaction.
+    pub duplicate_window: Option<usize>,
+    /// How the amounts of the account output are written. [AmountOutputFormat::Decimal] by default, e.g. `1.5`,
+    /// while [AmountOutputFormat::ScaledInteger] writes the integers the bank stores, e.g. `15000`.
+    pub amount_output_format: AmountOutputFormat,
+    /// Whether a dispute or resolve carrying an amount is rejected if the amount differs from the one
+    /// of the transaction it references. Meta-transactions without an amount are not checked, and
+    /// the amount of a chargeback is always the part of the disputed amount to charge back.
+    pub verify_meta_amounts: bool,
+    /// Whether the account a transaction touched is checked afterwards for available plus held adding up
+    /// to its deposits less its withdrawals, fees and chargebacks, logging an error for each currency that
+    /// doesn't and counting it in [crate::bank::BankStats::invariant_violations]. For debugging, off by default.
+    pub debug_invariants: bool,
+    /// Number of decimal places amounts are kept with, at most [Amount::MAX_DECIMAL_PLACES].
+    /// Amounts are stored scaled by 10 to this power, see [Amount], and written with this many places at most;
+    /// inputs with more are handled according to [ProcessOptions::excess_precision].
+    /// Amounts given to the bank directly, e.g. in [crate::transaction::TxRequest], are scaled the same way.
+    pub decimal_places: u32,
+    /// Number of decimal places balances in a currency are written with in the account output and
+    /// transaction sheet, e.g. 0 for JPY or 8 for BTC, rounded half away from zero if the bank keeps more.
+    /// Currencies not in the map are written with as many places as needed. Amounts written as
+    /// [AmountOutputFormat::ScaledInteger] are not affected. At most [Amount::MAX_DECIMAL_PLACES] each.
+    pub currency_decimal_places: HashMap<CurrencyCode, u32>,
+}
+
+impl Default for BankConfig {
+    fn default() -> Self {
+        Self {
+            zero_amount_policy: ZeroAmountPolicy::default(),
+            base_currency: CurrencyCode::USD,
+            max_pending_disputes: 0,
+            strict_accounts: false,
+            reserved_clients: HashSet::new(),
+            clamp_negative_totals: false,
+            journal_sync_every: 1,
+            record_statements: false,
+            partial_chargeback_remainder: PartialChargebackRemainder::default(),
+            fee_schedule: FeeSchedule::default(),
+            limits: Limits::default(),
+            extended_output: false,
+            record_activity: false,
+            strict_disputes: false,
+            allow_direct_chargeback: false,
+            dispute_window: None,
+            duplicate_tx_ids: DuplicateTxIdPolicy::default(),
+            duplicate_window: None,
+            amount_output_format: AmountOutputFormat::default(),
+            verify_meta_amounts: false,
+            debug_invariants: false,
+            decimal_places: Amount::DECIMAL_PLACES,
+            currency_decimal_places: HashMap::new(),
+        }
+    }
+}
+
+/// Whether a csv file starts with a header row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CsvHeaders {
+    /// The first row is the header row
+    #[default]
+    Present,
+    /// There is no header row, columns are in the order `type, client, tx, amount, currency, timestamp`.
+    /// Used for continuation parts of a file that was split up.
+    Absent,
+    /// The first row is a header row unless it parses as a transaction
+    Detect,
+}
+
+/// The order in which the transactions of a csv file are applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TxOrdering {
+    /// Each row is applied as soon as it is read
+    #[default]
+    FileOrder,
+    /// All rows are read first and applied sorted by their `timestamp` column, ties broken by tx id.
+    /// Rows without a timestamp are applied first in tx id order,
+    /// so files without timestamps are applied in tx id order.
+    Timestamp,
+}
+
+/// What to do with an amount that has more decimal places than the bank keeps, see [BankConfig::decimal_places]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExcessPrecision {
+    /// The row is skipped and counted in [crate::report::ProcessReport::precision_errors].
+    /// What a strict deployment should use.
+    Reject,
+    /// The amount is rounded to the nearest value, halves away from zero, e.g. `1.00005` becomes `1.0001` with four places
+    #[default]
+    RoundHalfUp,
+    /// The extra decimal places are dropped, e.g. `2.999999` becomes `2.9999` with four places
+    Truncate,
+}
+
+/// Called with the progress of processing a source, see [ProcessOptions::on_progress]
+pub type ProgressCallback = Arc<dyn Fn(ProgressUpdate) + Send + Sync>;
+
+/// Options for reading a single source of transactions
+#[derive(Clone)]
+pub struct ProcessOptions {
+    pub headers: CsvHeaders,
+    pub ordering: TxOrdering,
+    pub excess_precision: ExcessPrecision,
+    /// Whether amounts in scientific notation such as `2.5E2` are accepted.
+    /// Rows with such amounts are skipped when not.
+    pub allow_scientific: bool,
+    /// Records longer than this many bytes are skipped without being read into memory in full
+    pub max_record_bytes: usize,
+    /// Records with more fields than this are skipped
+    pub max_fields: usize,
+    /// Lines starting with this byte are comments and skipped, `#` by default. None reads them as records.
+    /// Lines of only whitespace are always skipped.
+    pub comment: Option<u8>,
+    /// Renames columns of the header row before the records are read, from the name used in the file
+    /// to the expected one, e.g. `txn_type` to `type`. Columns not in the mapping keep their name.
+    pub column_names: HashMap<String, String>,
+    /// Called every [ProcessOptions::progress_interval] rows read, e.g. to show how far a large file got.
+    /// Never called while the bank is locked, so it may read the bank or take its time.
+    pub on_progress: Option<ProgressCallback>,
+    /// How many rows are read between calls of [ProcessOptions::on_progress]
+    pub progress_interval: usize,
+    /// Whether a file is parsed on a thread of its own, sending batches of parsed rows over a channel
+    /// with room for this many to the thread applying them, so reading and applying overlap. Parsing waits
+    /// while the channel is full. None parses and applies each row in turn on the same thread.
+    /// Only used by [crate::bank::Bank::process_transactions_from_csv_path_with_options] and its blocking variant.
+    pub pipeline: Option<usize>,
+    /// Every this many rows of a file processed from a path, and once it is done, where the next row starts
+    /// is written to a checkpoint file next to it, e.g. `transactions.csv.checkpoint` for `transactions.csv`,
+    /// for [ProcessOptions::resume] to carry on from. None writes no checkpoint. Ignored with
+    /// [TxOrdering::Timestamp], which only applies rows once all are read.
+    pub checkpoint_every: Option<usize>,
+    /// Whether a file processed from a path is read from where its checkpoint says, skipping the rows before,
+    /// see [ProcessOptions::checkpoint_every]. The bank must hold what those rows did, e.g. as recovered with
+    /// [crate::bank::Bank::recover_from_journal]. A file without a checkpoint is read from the start.
+    ///
+    /// The checkpoint can be up to `checkpoint_every - 1` rows behind the last row processed before a crash,
+    /// those rows are processed again. [DuplicateTxIdPolicy::Reject] rejects their deposits and withdrawals as duplicates.
+    pub resume: bool,
+}
+
+impl fmt::Debug for ProcessOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProcessOptions")
+            .field("headers", &self.headers)
+            .field("ordering", &self.ordering)
+            .field("excess_precision", &self.excess_precision)
+            .field("allow_scientific", &self.allow_scientific)
+            .field("max_record_bytes", &self.max_record_bytes)
+            .field("max_fields", &self.max_fields)
+            .field("comment", &self.comment.map(char::from))
+            .field("column_names", &self.column_names)
+            .field("on_progress", &self.on_progress.as_ref().map(|_| "Fn(ProgressUpdate)"))
+            .field("progress_interval", &self.progress_interval)
+            .field("pipeline", &self.pipeline)
+            .field("checkpoint_every", &self.checkpoint_every)
+            .field("resume", &self.resume)
+            .finish()
+    }
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            headers: CsvHeaders::default(),
+            ordering: TxOrdering::default(),
+            excess_precision: ExcessPrecision::default(),
+            allow_scientific: false,
+            max_record_bytes: 1024,
+            max_fields: 16,
+            comment: Some(b'#'),
+            column_names: HashMap::new(),
+            on_progress: None,
+            progress_interval: 100_000,
+            pipeline: None,
+            checkpoint_every: None,
+            resume: false,
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+use std::io;
+
+use crate::shared_types::ClientId;
+
+/// Errors returned by the bank's public API
+#[derive(Debug)]
+pub enum BankError {
+    /// Reading or writing a file or stream failed
+    Io(io::Error),
+    /// Reading or writing csv data failed
+    Csv(csv::Error),
+    /// Writing Parquet data failed
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::errors::ParquetError),
+    /// Opening balances could not be imported, see [crate::bank::Bank::import_opening_balances]
+    OpeningBalances(OpeningBalancesError),
+    /// Appending to the bank's journal failed, see [crate::bank::Bank::is_poisoned]
+    Journal(io::Error),
+}
+
+impl fmt::Display for BankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BankError::Io(e) => write!(f, "io error: {}", e),
+            BankError::Csv(e) => write!(f, "csv error: {}", e),
+            #[cfg(feature = "parquet")]
+            BankError::Parquet(e) => write!(f, "parquet error: {}", e),
+            BankError::OpeningBalances(e) => write!(f, "opening balances error: {}", e),
+            BankError::Journal(e) => write!(f, "journal error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BankError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BankError::Io(e) => Some(e),
+            BankError::Csv(e) => Some(e),
+            #[cfg(feature = "parquet")]
+            BankError::Parquet(e) => Some(e),
+            BankError::OpeningBalances(e) => Some(e),
+            BankError::Journal(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for BankError {
+    fn from(e: io::Error) -> Self {
+        BankError::Io(e)
+    }
+}
+
+impl From<csv::Error> for BankError {
+    fn from(e: csv::Error) -> Self {
+        BankError::Csv(e)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for BankError {
+    fn from(e: parquet::errors::ParquetError) -> Self {
+        BankError::Parquet(e)
+    }
+}
+
+impl From<OpeningBalancesError> for BankError {
+    fn from(e: OpeningBalancesError) -> Self {
+        BankError::OpeningBalances(e)
+    }
+}
+
+/// Why opening balances could not be imported, nothing is imported if any row is refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpeningBalancesError {
+    /// The bank has processed transactions already
+    AfterProcessing,
+    /// The client already has an account, or a row of the same client came earlier
+    DuplicateClient { line: u64, client: ClientId },
+    /// The available or held balance is negative
+    NegativeBalance { line: u64, client: ClientId },
+    /// The client is one of [crate::config::BankConfig::reserved_clients], which never get an account
+    ReservedClient { line: u64, client: ClientId },
+    /// The available or held balance is not a decimal amount with at most the bank's decimal places
+    InvalidAmount { line: u64, value: String },
+}
+
+impl fmt::Display for OpeningBalancesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpeningBalancesError::AfterProcessing => f.write_str("transactions were processed already"),
+            OpeningBalancesError::DuplicateClient { line, client } => write!(f, "line {}: client {} already has an account", line, client.0),
+            OpeningBalancesError::NegativeBalance { line, client } => write!(f, "line {}: negative balance for client {}", line, client.0),
+            OpeningBalancesError::ReservedClient { line, client } => write!(f, "line {}: client {} is reserved", line, client.0),
+            OpeningBalancesError::InvalidAmount { line, value } => write!(f, "line {}: invalid amount {:?}", line, value),
+        }
+    }
+}
+
+impl std::error::Error for OpeningBalancesError {}
+
+/// Why an account could not be closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseAccountError {
+    /// The account holds disputed funds in some currency
+    HeldFunds,
+    /// One of the client's transactions is disputed
+    OpenDisputes,
+}
+
+impl fmt::Display for CloseAccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseAccountError::HeldFunds => f.write_str("account has held funds"),
+            CloseAccountError::OpenDisputes => f.write_str("account has open disputes"),
+        }
+    }
+}
+
+impl std::error::Error for CloseAccountError {}
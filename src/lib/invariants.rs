@@ -0,0 +1,86 @@
+use std::sync::atomic::Ordering;
+
+use crate::bank::{Account, Accounts, Bank};
+use crate::shared_types::{ClientId, CurrencyCode};
+
+impl Bank {
+    /// Checks the balances of a client's account against the money that went in and out of it,
+    /// logging an error and counting a violation for each currency that doesn't add up.
+    /// Only called with [crate::config::BankConfig::debug_invariants] on.
+    pub(crate) fn check_invariants(&self, accounts: &Accounts, client: ClientId) {
+        let Some(account) = accounts.get(&client) else {
+            return;
+        };
+        for (currency, balances, net_flow) in account.invariant_violations(self.config.base_currency) {
+            tracing::error!(client = client.0, currency = currency.as_str(), balances, net_flow, "account balances don't add up to its deposits and withdrawals");
+            self.invariant_violations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Account {
+    /// Adds money that went in, or with a negative change out, of the account in the given currency
+    pub(crate) fn record_net_flow(&mut self, currency: CurrencyCode, change: i128) {
+        *self.net_flows.entry(currency).or_insert(0) += change;
+    }
+
+    /// The currencies whose available plus held balance differs from the recorded net flow,
+    /// with the two sums
+    fn invariant_violations(&self, base_currency: CurrencyCode) -> Vec<(CurrencyCode, i128, i128)> {
+        let balances = std::iter::once((base_currency, self.available.value, self.held.value))
+            .chain(self.currencies.iter().map(|(currency, balance)| (*currency, balance.available.value, balance.held.value)));
+        balances
+            .map(|(currency, available, held)| {
+                let net_flow = self.net_flows.get(&currency).copied().unwrap_or(0);
+                (currency, available as i128 + held as i128, net_flow)
+            })
+            .filter(|(_, balances, net_flow)| balances != net_flow)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::Bank;
+    use crate::config::BankConfig;
+    use crate::shared_types::{ClientId, CurrencyCode, TxId};
+    use crate::transaction::{TxRequest, TxType};
+
+    fn checked_bank() -> Bank {
+        Bank::with_config(BankConfig { debug_invariants: true, ..BankConfig::default() })
+    }
+
+    #[test]
+    fn test_normal_sequence_has_no_violations() {
+        let bank = checked_bank();
+        let apply = |type_, client, tx, amount, currency: Option<&str>| bank.apply(TxRequest {
+            type_, client: ClientId(client), tx: TxId(tx), amount, currency: currency.map(|c| c.parse().unwrap()),
+        });
+
+        apply(TxType::Deposit, 1, 1, Some(50000), None).unwrap();
+        apply(TxType::Withdrawal, 1, 2, Some(20000), None).unwrap();
+        apply(TxType::Withdrawal, 1, 3, Some(90000), None).unwrap_err();
+        apply(TxType::Deposit, 1, 4, Some(30000), Some("EUR")).unwrap();
+        apply(TxType::Dispute, 1, 4, None, None).unwrap();
+        apply(TxType::Resolve, 1, 4, None, None).unwrap();
+        apply(TxType::Deposit, 2, 5, Some(10000), None).unwrap();
+        apply(TxType::Withdrawal, 2, 6, Some(8000), None).unwrap();
+        apply(TxType::Dispute, 2, 5, None, None).unwrap();
+        apply(TxType::Chargeback, 2, 5, Some(4000), None).unwrap();
+
+        assert_eq!(bank.stats().invariant_violations, 0);
+        assert_eq!(bank.account_snapshot(ClientId(2)).unwrap().total, -2000);
+    }
+
+    #[test]
+    fn test_balance_changed_outside_transactions_is_reported() {
+        let bank = checked_bank();
+        bank.apply(TxRequest { type_: TxType::Deposit, client: ClientId(1), tx: TxId(1), amount: Some(10000), currency: None }).unwrap();
+
+        bank.accounts.lock().get_mut(&ClientId(1)).unwrap().held.value += 1;
+        bank.check_invariants(&bank.accounts.lock(), ClientId(1));
+
+        assert_eq!(bank.stats().invariant_violations, 1);
+        assert!(bank.accounts.lock()[&ClientId(1)].invariant_violations(CurrencyCode::USD).len() == 1);
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bank::Bank;
+use crate::config::BankConfig;
+use crate::error::BankError;
+use crate::merge::JournaledMerge;
+use crate::opening::OpeningBalance;
+use crate::shared_types::ClientId;
+use crate::transaction::{Tx, TxRequest};
+
+impl Bank {
+    /// Creates an empty bank that appends every change of its state to a journal
+    /// at the given path, which [Bank::recover_from_journal] can rebuild the bank from after a crash
+    ///
+    /// Any existing file at the path is replaced.
+    pub fn with_journal<P: AsRef<Path>>(path: P) -> Result<Self, BankError> {
+        Bank::with_config_and_journal(BankConfig::default(), path)
+    }
+
+    /// Creates an empty bank with the given config that journals its changes, see [Bank::with_journal]
+    pub fn with_config_and_journal<P: AsRef<Path>>(config: BankConfig, path: P) -> Result<Self, BankError> {
+        let journal = Journal::create(path.as_ref(), config.journal_sync_every)?;
+        Ok(Bank { journal: Some(Arc::new(journal)), ..Bank::with_config(config) })
+    }
+
+    /// Rebuilds a bank by replaying the journal at the given path, see [Bank::with_journal]
+    ///
+    /// A final entry cut short by a crash is dropped from the journal, the change
+    /// it records is treated as never made. The returned bank keeps appending to the journal.
+    pub fn recover_from_journal<P: AsRef<Path>>(path: P) -> Result<Self, BankError> {
+        Bank::recover_from_journal_with_config(BankConfig::default(), path)
+    }
+
+    /// Rebuilds a bank with the given config from a journal, which should be the config it was written with
+    pub fn recover_from_journal_with_config<P: AsRef<Path>>(config: BankConfig, path: P) -> Result<Self, BankError> {
+        let path = path.as_ref();
+        let records = read_complete_records(path)?;
+        let mut bank = Bank::with_config(config);
+        let mut entries = 0;
+        for (index, line) in records.split(|&b| b == b'\n').filter(|line| !line.is_empty()).enumerate() {
+            let entry: JournalEntry = serde_json::from_slice(line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("journal entry {}: {}", index + 1, e)))?;
+            bank.replay(entry)?;
+            entries += 1;
+        }
+        tracing::info!(path = %path.display(), entries, "recovered bank from journal");
+        bank.journal = Some(Arc::new(Journal::open(path, bank.config.journal_sync_every)?));
+        Ok(bank)
+    }
+
+    /// Whether appending to the bank's journal failed
+    ///
+    /// The change that failed to be journaled was made in memory, so the bank no longer matches its journal.
+    /// From then on transactions are rejected as [crate::report::RejectionReason::JournalFailed], opening balances
+    /// are refused and files stop being processed, their methods returning [BankError::Journal].
+    /// Rebuild the bank with [Bank::recover_from_journal] once the journal's file can be written again.
+    pub fn is_poisoned(&self) -> bool {
+        self.journal.as_ref().is_some_and(|journal| journal.failure().is_some())
+    }
+
+    /// The error that poisoned the bank if it is poisoned, see [Bank::is_poisoned]
+    pub(crate) fn check_journal(&self) -> Result<(), BankError> {
+        match self.journal.as_ref().and_then(|journal| journal.failure()) {
+            Some(e) => Err(BankError::Journal(e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Appends an entry to the bank's journal if it has one, poisoning the bank if that fails
+    pub(crate) fn append_to_journal(&self, entry: &JournalEntry) -> Result<(), BankError> {
+        let Some(journal) = &self.journal else {
+            return Ok(());
+        };
+        journal.append(entry).map_err(|e| {
+            tracing::error!(error = %e, "failed to journal change, the bank is poisoned");
+            BankError::Journal(e)
+        })
+    }
+
+    /// Makes the change recorded by a journal entry again
+    fn replay(&self, entry: JournalEntry) -> Result<(), BankError> {
+        match entry {
+            // Rejected while being recovered only if the config differs from the one it was journaled with
+            JournalEntry::Transaction { request, source } => {
+                let source = source.map(|path| self.register_source(&path));
+                let _ = Tx { source, ..Tx::from(request) }.process(self);
+            },
+            JournalEntry::OpenAccounts { clients } => {
+                self.ensure_clients(&clients);
+            },
+            JournalEntry::OpeningBalances { accounts } => {
+                self.insert_opening_balances(accounts.into_iter().map(|balance| (0, balance)).collect())?;
+            },
+            JournalEntry::RemoveAccount { client } => {
+                self.remove_account(client);
+            },
+            JournalEntry::Merge(merged) => {
+                self.merge_state(merged.into());
+            },
+        }
+        Ok(())
+    }
+}
+
+/// A change of a bank's state as appended to its journal, a line of JSON each
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+pub(crate) enum JournalEntry {
+    /// A transaction that changed the bank's state, see [Tx::process], with the path of the file it was read from
+    Transaction {
+        #[serde(flatten)]
+        request: TxRequest,
+        source: Option<String>,
+    },
+    /// Empty accounts opened by [Bank::open_account] or [Bank::ensure_clients]
+    OpenAccounts { clients: Vec<ClientId> },
+    /// Accounts created by [Bank::import_opening_balances]
+    OpeningBalances { accounts: Vec<OpeningBalance> },
+    /// An account removed by [Bank::remove_account] or [Bank::close_account]
+    RemoveAccount { client: ClientId },
+    /// Another bank folded in by [Bank::merge]
+    Merge(JournaledMerge),
+}
+
+/// Reads the journal at the given path up to its last complete entry,
+/// truncating the file there so later entries are not appended to a partial one
+fn read_complete_records(path: &Path) -> io::Result<Vec<u8>> {
+    let mut records = std::fs::read(path)?;
+    let complete_len = records.iter().rposition(|&b| b == b'\n').map_or(0, |index| index + 1);
+    if complete_len < records.len() {
+        tracing::warn!(path = %path.display(), dropped_bytes = records.len() - complete_len, "dropping incomplete final journal entry");
+        records.truncate(complete_len);
+        OpenOptions::new().write(true).open(path)?.set_len(complete_len as u64)?;
+    }
+    Ok(records)
+}
+
+/// An append-only file of the changes of a bank's state, a line of JSON each in the order they were made
+///
+/// These are the applied transactions, including disputes parked until the transaction they reference
+/// arrives, and withdrawals rejected for insufficient funds, which are still stored on the transaction sheet,
+/// as well as the accounts opened, imported, merged in or removed directly on the bank.
+/// Statements of accounts merged in from another bank are not journaled.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    file: Mutex<JournalFile>,
+    /// Entries written between syncs of the file to disk, zero leaves syncing to the operating system
+    sync_every: usize,
+}
+
+#[derive(Debug)]
+struct JournalFile {
+    file: File,
+    /// Entries written since the file was last synced
+    unsynced: usize,
+    /// Why writing to the file failed, after which nothing more is written to it
+    failed: Option<(io::ErrorKind, String)>,
+}
+
+impl Journal {
+    /// Creates an empty journal, replacing any existing file
+    fn create(path: &Path, sync_every: usize) -> io::Result<Self> {
+        let file = File::create(path)?;
+        file.sync_data()?;
+        Ok(Self::from_file(file, sync_every))
+    }
+
+    /// Opens an existing journal for appending
+    fn open(path: &Path, sync_every: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Self::from_file(file, sync_every))
+    }
+
+    pub(crate) fn from_file(file: File, sync_every: usize) -> Self {
+        Self { file: Mutex::new(JournalFile { file, unsynced: 0, failed: None }), sync_every }
+    }
+
+    /// Appends an entry, syncing the file if enough entries were written since the last sync
+    ///
+    /// Fails without writing anything once an earlier append failed.
+    pub(crate) fn append(&self, entry: &JournalEntry) -> io::Result<()> {
+        let mut journal = self.file.lock().unwrap();
+        if let Some((kind, message)) = &journal.failed {
+            return Err(io::Error::new(*kind, message.clone()));
+        }
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let written = journal.file.write_all(&line).and_then(|()| {
+            journal.unsynced += 1;
+            if self.sync_every > 0 && journal.unsynced >= self.sync_every {
+                journal.file.sync_data()?;
+                journal.unsynced = 0;
+            }
+            Ok(())
+        });
+        if let Err(e) = &written {
+            journal.failed = Some((e.kind(), e.to_string()));
+        }
+        written
+    }
+
+    /// Syncs the entries written so far to disk
+    ///
+    /// Fails once an earlier append failed, as the journal is missing an entry.
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        let mut journal = self.file.lock().unwrap();
+        if let Some((kind, message)) = &journal.failed {
+            return Err(io::Error::new(*kind, message.clone()));
+        }
+        if journal.unsynced == 0 {
+            return Ok(());
+        }
+        let synced = journal.file.sync_data();
+        match &synced {
+            Ok(()) => journal.unsynced = 0,
+            Err(e) => journal.failed = Some((e.kind(), e.to_string())),
+        }
+        synced
+    }
+
+    /// Why an earlier append failed, if one did
+    fn failure(&self) -> Option<io::Error> {
+        let journal = self.file.lock().unwrap();
+        journal.failed.as_ref().map(|(kind, message)| io::Error::new(*kind, message.clone()))
+    }
+
+    /// Drops all entries, used when the bank is cleared
+    pub(crate) fn clear(&self) -> io::Result<()> {
+        let mut journal = self.file.lock().unwrap();
+        let cleared = journal.file.set_len(0).and_then(|()| journal.file.sync_data());
+        match &cleared {
+            Ok(()) => journal.unsynced = 0,
+            Err(e) => journal.failed = Some((e.kind(), e.to_string())),
+        }
+        cleared
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        if let Ok(journal) = self.file.get_mut() {
+            let _ = journal.file.sync_data();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use crate::bank::Bank;
+    use crate::error::BankError;
+    use crate::journal::Journal;
+    use crate::report::RejectionReason;
+    use crate::shared_types::{ClientId, TxId};
+    use crate::transaction::{TxRequest, TxType};
+
+    const FIXTURES: [&str; 3] = [
+        "tests/fixtures/deposits_and_withdrawals.csv",
+        "tests/fixtures/dispute_chargeback.csv",
+        "tests/fixtures/ignored_rows.csv",
+    ];
+
+    fn journal_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bank_lib_test_journal_{}.jsonl", name))
+    }
+
+    fn sorted_output(bank: &Bank) -> String {
+        let mut clients: Vec<ClientId> = bank.accounts.lock().keys().copied().collect();
+        clients.sort();
+        let mut output = Vec::new();
+        bank.write_accounts_filtered(&mut output, &clients).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn process_fixtures(bank: &Bank) {
+        for fixture in FIXTURES {
+            bank.process_transactions_from_csv_path_blocking(fixture).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_recovered_bank_equals_straight_through_run() {
+        let path = journal_path("replay");
+        let bank = Bank::new();
+        process_fixtures(&bank);
+        let journaled = Bank::with_journal(&path).unwrap();
+        process_fixtures(&journaled);
+        drop(journaled);
+
+        let recovered = Bank::recover_from_journal(&path).unwrap();
+
+        assert_eq!(sorted_output(&recovered), sorted_output(&bank));
+        assert_eq!(recovered.stats(), bank.stats());
+        assert_eq!(recovered.chargeback_losses(), bank.chargeback_losses());
+    }
+
+    #[test]
+    fn test_truncated_final_record_is_dropped() {
+        let path = journal_path("truncated");
+        let bank = Bank::new();
+        process_fixtures(&bank);
+        let journaled = Bank::with_journal(&path).unwrap();
+        process_fixtures(&journaled);
+        drop(journaled);
+        let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"entry":"transaction","type":"deposit","client":3,"#).unwrap();
+        drop(file);
+
+        let recovered = Bank::recover_from_journal(&path).unwrap();
+        assert_eq!(sorted_output(&recovered), sorted_output(&bank));
+
+        let deposit = TxRequest { type_: TxType::Deposit, client: ClientId(3), tx: TxId(101), amount: Some(10000), currency: None };
+        recovered.apply(deposit).unwrap();
+        drop(recovered);
+        let recovered = Bank::recover_from_journal(&path).unwrap();
+        assert_eq!(recovered.account_snapshot(ClientId(3)).unwrap().available, 10000);
+    }
+
+    #[test]
+    fn test_direct_changes_are_recovered() {
+        let path = journal_path("direct");
+        let deposit = |client: u16, tx: u32| TxRequest { type_: TxType::Deposit, client: ClientId(client), tx: TxId(tx), amount: Some(10000), currency: None };
+        let journaled = Bank::with_journal(&path).unwrap();
+        journaled.import_opening_balances("client,available,held,locked\n1,2.5,0,false\n".as_bytes()).unwrap();
+        journaled.open_account(ClientId(2));
+        journaled.ensure_clients(&[ClientId(2), ClientId(3), ClientId(4)]);
+        journaled.apply(deposit(3, 1)).unwrap();
+        journaled.remove_account(ClientId(4));
+        let shard = Bank::new();
+        shard.apply(deposit(5, 2)).unwrap();
+        shard.apply(TxRequest { type_: TxType::Dispute, client: ClientId(5), tx: TxId(2), amount: None, currency: None }).unwrap();
+        journaled.merge(&shard);
+        let expected = (sorted_output(&journaled), journaled.stats());
+        drop(journaled);
+
+        let recovered = Bank::recover_from_journal(&path).unwrap();
+
+        assert_eq!((sorted_output(&recovered), recovered.stats()), expected);
+        let resolve = TxRequest { type_: TxType::Resolve, client: ClientId(5), tx: TxId(2), amount: None, currency: None };
+        recovered.apply(resolve).unwrap();
+        assert_eq!(recovered.account_snapshot(ClientId(5)).unwrap().available, 10000);
+    }
+
+    #[test]
+    fn test_failed_append_poisons_bank() {
+        let path = journal_path("read_only");
+        std::fs::write(&path, "").unwrap();
+        // Opened for reading only, so every write fails
+        let journal = Journal::from_file(std::fs::File::open(&path).unwrap(), 1);
+        let bank = Bank { journal: Some(Arc::new(journal)), ..Bank::new() };
+        let deposit = |tx: u32| TxRequest { type_: TxType::Deposit, client: ClientId(1), tx: TxId(tx), amount: Some(10000), currency: None };
+
+        assert_eq!(bank.apply(deposit(1)), Err(RejectionReason::JournalFailed));
+        assert!(bank.is_poisoned());
+        assert_eq!(bank.apply(deposit(2)), Err(RejectionReason::JournalFailed));
+        let outcome = bank.process_transactions_from_csv_path_blocking(FIXTURES[0]);
+        assert!(matches!(outcome, Err(BankError::Journal(_))));
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 10000);
+        assert_eq!(bank.stats().transactions, 1);
+    }
+}
@@ -1,18 +1,53 @@
 //!
 //! This library is designed to be used with the [crate::async_bank_runner] runner,
-//! however you can use it standalone.
+//! however you can use it standalone. Files can be processed without an async runtime
+//! with the blocking variants of the processing methods. The async variants are behind the
+//! default `async` feature, without it the library doesn't depend on tokio.
 //!
 //! # Examples
 //!
 //! ```
 //! use bank_lib::bank::Bank;
+//! use bank_lib::shared_types::ClientId;
 //!
 //! let bank = Bank::new();
-//! Bank::process_transactions_from_csv_path("transactions.csv", bank);
+//! let report = bank.process_transactions_from_csv_path_blocking("tests/fixtures/dispute_resolve.csv").unwrap();
+//! assert_eq!((report.rows, report.applied), (6, 5));
 //!
-//! bank.write_accounts();
+//! let account = bank.account_snapshot(ClientId(1)).unwrap();
+//! assert_eq!((account.available, account.held), (40000, 0));
+//! bank.write_accounts().unwrap();
 //! ```
 
+mod activity;
 pub mod bank;
+mod checkpoint;
+pub mod config;
+pub mod error;
+mod invariants;
+mod journal;
+mod lock;
+mod merge;
+pub mod metrics;
+mod opening;
+#[cfg(feature = "async")]
+mod parallel;
+mod pipeline;
+#[cfg(feature = "parquet")]
+mod parquet_output;
+mod pending;
+mod profile;
+mod reader;
+mod recent;
+mod record_limit;
+mod registry;
+mod simulate;
+pub mod report;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod shared_types;
+pub mod store;
+#[cfg(feature = "async")]
+mod stream;
 pub mod transaction;
+mod validate;
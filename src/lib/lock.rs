@@ -0,0 +1,238 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+#[cfg(debug_assertions)]
+use std::thread::{self, Thread};
+#[cfg(debug_assertions)]
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::profile::Profiler;
+
+/// How long a lock is waited for in debug builds before giving up, see [OrderedLock::lock]
+#[cfg(debug_assertions)]
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The parts of a bank behind their own lock, in the only order they may be locked in
+///
+/// A thread holding one of them may only lock parts that come later, so two threads can
+/// never wait on each other. Parts of the same rank of different banks, e.g. the accounts of
+/// a bank and of one of its shards, may be held together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum LockRank {
+    Accounts,
+    Transactions,
+    PendingDisputes,
+    Losses,
+    Fees,
+}
+
+impl LockRank {
+    /// Every rank, in lock order
+    pub(crate) const ALL: [LockRank; 5] = [LockRank::Accounts, LockRank::Transactions, LockRank::PendingDisputes, LockRank::Losses, LockRank::Fees];
+
+    /// The name of the part of the bank behind the lock, e.g. in a [crate::report::ProfileReport]
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            LockRank::Accounts => "accounts",
+            LockRank::Transactions => "transactions",
+            LockRank::PendingDisputes => "pending_disputes",
+            LockRank::Losses => "losses",
+            LockRank::Fees => "fees",
+        }
+    }
+}
+
+thread_local! {
+    /// Ranks of the locks the current thread holds, in the order they were taken
+    static HELD: RefCell<Vec<LockRank>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A mutex of one part of a bank that checks it is locked in [LockRank] order
+///
+/// Locking out of order panics instead of risking a deadlock. In debug builds, locking also
+/// panics when the lock can't be taken within five seconds, naming the thread holding it.
+///
+/// With a [Profiler] that is turned on, the time spent waiting for and holding the lock is measured.
+pub(crate) struct OrderedLock<T> {
+    rank: LockRank,
+    mutex: Mutex<T>,
+    profiler: Option<Arc<Profiler>>,
+    /// The thread holding the lock, to name it when another thread times out waiting for it
+    #[cfg(debug_assertions)]
+    holder: Mutex<Option<Thread>>,
+}
+
+impl<T> OrderedLock<T> {
+    pub(crate) fn new(rank: LockRank, value: T) -> Self {
+        Self {
+            rank,
+            mutex: Mutex::new(value),
+            profiler: None,
+            #[cfg(debug_assertions)]
+            holder: Mutex::new(None),
+        }
+    }
+
+    /// Measures waiting for and holding the lock with the given profiler while it is turned on
+    pub(crate) fn profiled(self, profiler: &Arc<Profiler>) -> Self {
+        Self { profiler: Some(profiler.clone()), ..self }
+    }
+
+    /// Locks the mutex, panicking if it is poisoned
+    pub(crate) fn lock(&self) -> OrderedGuard<'_, T> {
+        HELD.with(|held| {
+            if let Some(&last) = held.borrow().iter().max() {
+                assert!(last <= self.rank, "lock order violation: locking {:?} while holding {:?}", self.rank, last);
+            }
+        });
+        let profiler = self.profiler.as_deref().filter(|profiler| profiler.is_enabled());
+        let waiting_since = profiler.map(|_| Instant::now());
+        let guard = self.acquire();
+        let acquired = waiting_since.zip(profiler).map(|(waiting_since, profiler)| {
+            let acquired = Instant::now();
+            profiler.record_wait(self.rank, acquired - waiting_since);
+            acquired
+        });
+        HELD.with(|held| held.borrow_mut().push(self.rank));
+        OrderedGuard { guard, lock: self, acquired }
+    }
+
+    /// Locks the mutex if no one holds it, without waiting, e.g. to describe the bank in debug output
+    ///
+    /// As it never waits it can't deadlock, so it may be called whatever locks the thread holds.
+    /// Returns None if the mutex is held, by another thread or this one, and panics if it is poisoned.
+    pub(crate) fn try_lock(&self) -> Option<OrderedGuard<'_, T>> {
+        let guard = match self.mutex.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => return None,
+            Err(TryLockError::Poisoned(e)) => panic!("{:?} lock poisoned: {}", self.rank, e),
+        };
+        #[cfg(debug_assertions)]
+        {
+            *self.holder.lock().unwrap() = Some(thread::current());
+        }
+        HELD.with(|held| held.borrow_mut().push(self.rank));
+        Some(OrderedGuard { guard, lock: self, acquired: None })
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn acquire(&self) -> MutexGuard<'_, T> {
+        self.mutex.lock().unwrap()
+    }
+
+    #[cfg(debug_assertions)]
+    fn acquire(&self) -> MutexGuard<'_, T> {
+        let start = Instant::now();
+        let guard = loop {
+            match self.mutex.try_lock() {
+                Ok(guard) => break guard,
+                Err(TryLockError::Poisoned(e)) => panic!("{:?} lock poisoned: {}", self.rank, e),
+                Err(TryLockError::WouldBlock) if start.elapsed() >= LOCK_TIMEOUT => {
+                    let holder = self.holder.lock().unwrap().as_ref()
+                        .map_or("an unknown thread".to_string(), describe_thread);
+                    panic!(
+                        "could not lock {:?} within {:?}, it is held by {}, waiting thread is {}",
+                        self.rank, LOCK_TIMEOUT, holder, describe_thread(&thread::current())
+                    );
+                },
+                Err(TryLockError::WouldBlock) => thread::sleep(Duration::from_micros(50)),
+            }
+        };
+        *self.holder.lock().unwrap() = Some(thread::current());
+        guard
+    }
+}
+
+#[cfg(debug_assertions)]
+fn describe_thread(thread: &Thread) -> String {
+    format!("thread {:?} ({})", thread.id(), thread.name().unwrap_or("unnamed"))
+}
+
+impl<T: fmt::Debug> fmt::Debug for OrderedLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OrderedLock").field("rank", &self.rank).field("mutex", &self.mutex).finish()
+    }
+}
+
+/// Access to the value of a locked [OrderedLock], released when dropped
+pub(crate) struct OrderedGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    lock: &'a OrderedLock<T>,
+    /// When the lock was taken, if it is being profiled
+    acquired: Option<Instant>,
+}
+
+impl<T> Deref for OrderedGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for OrderedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for OrderedGuard<'_, T> {
+    fn drop(&mut self) {
+        if let (Some(acquired), Some(profiler)) = (self.acquired, &self.lock.profiler) {
+            profiler.record_hold(self.lock.rank, acquired.elapsed());
+        }
+        #[cfg(debug_assertions)]
+        {
+            *self.lock.holder.lock().unwrap() = None;
+        }
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(index) = held.iter().rposition(|&rank| rank == self.lock.rank) {
+                held.remove(index);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lock::{LockRank, OrderedLock};
+
+    #[test]
+    #[should_panic(expected = "lock order violation: locking Accounts while holding Losses")]
+    fn test_out_of_order_locking_panics() {
+        let accounts = OrderedLock::new(LockRank::Accounts, ());
+        let losses = OrderedLock::new(LockRank::Losses, ());
+
+        let _losses = losses.lock();
+        let _accounts = accounts.lock();
+    }
+
+    #[test]
+    fn test_in_order_and_same_rank_locking() {
+        let accounts = OrderedLock::new(LockRank::Accounts, 1);
+        let shard_accounts = OrderedLock::new(LockRank::Accounts, 2);
+        let transactions = OrderedLock::new(LockRank::Transactions, 3);
+
+        let accounts = accounts.lock();
+        let shard_accounts = shard_accounts.lock();
+        let transactions = transactions.lock();
+
+        assert_eq!(*accounts + *shard_accounts + *transactions, 6);
+    }
+
+    #[test]
+    fn test_try_lock_does_not_wait() {
+        let accounts = OrderedLock::new(LockRank::Accounts, 1);
+        let losses = OrderedLock::new(LockRank::Losses, 2);
+
+        let held = accounts.lock();
+        assert!(accounts.try_lock().is_none());
+        drop(held);
+
+        let _losses = losses.lock();
+        assert_eq!(accounts.try_lock().map(|accounts| *accounts), Some(1));
+    }
+}
@@ -0,0 +1,309 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bank::{Account, Bank, CurrencyBalance};
+use crate::journal::JournalEntry;
+use crate::report::MergeReport;
+use crate::shared_types::{Amount, AmountValue, ClientId, CurrencyCode, SourceId, TxId};
+use crate::store::StoredTx;
+use crate::transaction::{Tx, TxRequest};
+
+impl Bank {
+    /// Folds the accounts, transactions, parked disputes, chargeback losses and collected fees
+    /// of another bank into this one, leaving the other bank as it was
+    ///
+    /// Meant for combining banks that processed separate shards of the input. The balances of a client
+    /// with an account in both banks are summed, saturating at the largest amount, and the account is
+    /// locked if it is locked in either bank. Accounts only in `other` are numbered after the ones
+    /// created in this bank, see [crate::bank::AccountSnapshot::created_seq].
+    ///
+    /// A deposit or withdrawal whose tx id is already on this bank's transaction sheet is not copied over
+    /// and is reported as a conflict instead, although its amount is still part of the merged balances.
+    /// Transactions keep the file they were read from, see [Tx::source].
+    /// Both banks should keep the same [crate::config::BankConfig::decimal_places], amounts are not rescaled.
+    /// Merging a bank with itself or with one of its handles from [Bank::new_for_tokio] does nothing.
+    /// The merge is journaled, without the statements of the other bank's accounts, see [Bank::is_poisoned]
+    /// if that fails.
+    pub fn merge(&self, other: &Bank) -> MergeReport {
+        if Arc::ptr_eq(&self.accounts, &other.accounts) {
+            return MergeReport::default();
+        }
+        // Copy the other bank's state first so this bank's locks are never taken while holding its locks
+        let merged = MergedState {
+            accounts: other.accounts.lock().values().cloned().collect(),
+            transactions: other.transactions.lock().iter().collect(),
+            parked: other.pending_disputes.lock().clone_all(),
+            losses: other.chargeback_losses().into_iter().collect(),
+            fees: other.collected_fees().into_iter().collect(),
+            sources: other.sources.lock().unwrap().clone(),
+            tx_seq: other.tx_seq(),
+            max_seen_tx_id: other.max_seen_tx_id(),
+        };
+        let entry = self.journal.as_ref().map(|_| JournalEntry::Merge(JournaledMerge::from(&merged)));
+        let report = self.merge_state(merged);
+        if let Some(entry) = entry {
+            let _ = self.append_to_journal(&entry);
+        }
+        report
+    }
+
+    /// Folds the copied state of another bank into this one, see [Bank::merge]
+    pub(crate) fn merge_state(&self, merged: MergedState) -> MergeReport {
+        let mut report = MergeReport::default();
+        let sources: Vec<SourceId> = merged.sources.iter().map(|path| self.register_source(path)).collect();
+        let source_in_self = |source: Option<SourceId>| source.map(|source| sources[source.0 as usize]);
+
+        self.with_accounts_and_sheet(|accounts, transactions| {
+            for mut account in merged.accounts {
+                for entry in &mut account.statement {
+                    entry.source = source_in_self(entry.source);
+                }
+                account.deposited = std::mem::take(&mut account.deposited).into_iter()
+                    .map(|((source, currency), amount)| ((source_in_self(source), currency), amount))
+                    .collect();
+                match accounts.get_mut(&account.client) {
+                    Some(existing) => existing.absorb(account),
+                    None => {
+                        let merged = Account { created_seq: self.new_account(account.client).created_seq, ..account };
+                        accounts.insert(merged.client, merged);
+                    },
+                }
+                report.accounts += 1;
+            }
+            for tx in merged.transactions {
+                let tx = StoredTx { source: source_in_self(tx.source), ..tx };
+                if transactions.contains(tx.tx) {
+                    report.conflicts.push(tx.tx);
+                } else {
+                    transactions.insert(tx);
+                    report.transactions += 1;
+                }
+            }
+        });
+        let mut pending_disputes = self.pending_disputes.lock();
+        for (id, waiting) in merged.parked {
+            let waiting = waiting.into_iter().map(|tx| Tx { source: source_in_self(tx.source), ..tx }).collect();
+            pending_disputes.restore(id, waiting);
+        }
+        drop(pending_disputes);
+        let mut losses = self.losses.lock();
+        for (client, loss) in merged.losses {
+            let total = losses.entry(client).or_default();
+            total.value = total.value.saturating_add(loss);
+        }
+        drop(losses);
+        let mut fees = self.fees.lock();
+        for (currency, fee) in merged.fees {
+            let total = fees.entry(currency).or_insert(0);
+            *total = total.saturating_add(fee);
+        }
+        self.tx_seq.fetch_max(merged.tx_seq, Ordering::Relaxed);
+        if let Some(max_seen) = merged.max_seen_tx_id {
+            self.record_seen_tx_id(max_seen);
+        }
+        report.conflicts.sort();
+        report
+    }
+}
+
+/// The state of another bank folded in by [Bank::merge]
+pub(crate) struct MergedState {
+    accounts: Vec<Account>,
+    transactions: Vec<StoredTx>,
+    parked: Vec<(TxId, Vec<Tx>)>,
+    losses: Vec<(ClientId, AmountValue)>,
+    fees: Vec<(CurrencyCode, AmountValue)>,
+    /// Paths of the other bank's sources, indexed by its source ids
+    sources: Vec<String>,
+    tx_seq: u64,
+    max_seen_tx_id: Option<TxId>,
+}
+
+/// A [MergedState] as it is journaled, without the statements and net flows of the accounts
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct JournaledMerge {
+    accounts: Vec<JournaledAccount>,
+    transactions: Vec<StoredTx>,
+    parked: Vec<(TxId, Vec<TxRequest>)>,
+    losses: Vec<(ClientId, AmountValue)>,
+    fees: Vec<(CurrencyCode, AmountValue)>,
+    sources: Vec<String>,
+    tx_seq: u64,
+    max_seen_tx_id: Option<TxId>,
+}
+
+/// The balances and counters of a merged account, see [JournaledMerge]
+#[derive(Debug, Serialize, Deserialize)]
+struct JournaledAccount {
+    client: ClientId,
+    available: AmountValue,
+    held: AmountValue,
+    locked: bool,
+    /// Available and held balance by currency other than the base currency
+    currencies: Vec<(CurrencyCode, AmountValue, AmountValue)>,
+    peak_available: AmountValue,
+    peak_held: AmountValue,
+    open_disputes: u32,
+    /// Deposits by file and currency, see [crate::config::Limits::max_cumulative_deposit]
+    deposited: Vec<(Option<SourceId>, CurrencyCode, AmountValue)>,
+}
+
+impl From<&MergedState> for JournaledMerge {
+    fn from(merged: &MergedState) -> Self {
+        let accounts = merged.accounts.iter().map(|account| JournaledAccount {
+            client: account.client,
+            available: account.available.value,
+            held: account.held.value,
+            locked: account.locked,
+            currencies: account.currencies.iter()
+                .map(|(currency, balance)| (*currency, balance.available.value, balance.held.value))
+                .collect(),
+            peak_available: account.peak_available,
+            peak_held: account.peak_held,
+            open_disputes: account.open_disputes,
+            deposited: account.deposited.iter().map(|((source, currency), amount)| (*source, *currency, *amount)).collect(),
+        }).collect();
+        JournaledMerge {
+            accounts,
+            transactions: merged.transactions.clone(),
+            parked: merged.parked.iter().map(|(id, waiting)| (*id, waiting.iter().map(Tx::request).collect())).collect(),
+            losses: merged.losses.clone(),
+            fees: merged.fees.clone(),
+            sources: merged.sources.clone(),
+            tx_seq: merged.tx_seq,
+            max_seen_tx_id: merged.max_seen_tx_id,
+        }
+    }
+}
+
+impl From<JournaledMerge> for MergedState {
+    fn from(merged: JournaledMerge) -> Self {
+        let accounts = merged.accounts.into_iter().map(|journaled| {
+            let currencies = journaled.currencies.into_iter()
+                .map(|(currency, available, held)| (currency, CurrencyBalance { available: Amount { value: available }, held: Amount { value: held } }))
+                .collect();
+            let mut account = Account {
+                available: Amount { value: journaled.available },
+                held: Amount { value: journaled.held },
+                locked: journaled.locked,
+                currencies,
+                peak_available: journaled.peak_available,
+                peak_held: journaled.peak_held,
+                open_disputes: journaled.open_disputes,
+                deposited: journaled.deposited.into_iter().map(|(source, currency, amount)| ((source, currency), amount)).collect(),
+                ..Account::new(journaled.client)
+            };
+            account.calculate_total();
+            account
+        }).collect();
+        MergedState {
+            accounts,
+            transactions: merged.transactions,
+            parked: merged.parked.into_iter().map(|(id, waiting)| (id, waiting.into_iter().map(Tx::from).collect())).collect(),
+            losses: merged.losses,
+            fees: merged.fees,
+            sources: merged.sources,
+            tx_seq: merged.tx_seq,
+            max_seen_tx_id: merged.max_seen_tx_id,
+        }
+    }
+}
+
+impl Account {
+    /// Adds the balances and history of the same client's account in another bank to this one
+    fn absorb(&mut self, other: Account) {
+        self.available.value = self.available.value.saturating_add(other.available.value);
+        self.held.value = self.held.value.saturating_add(other.held.value);
+        for (currency, balance) in other.currencies {
+            let merged = self.currencies.entry(currency).or_default();
+            merged.available.value = merged.available.value.saturating_add(balance.available.value);
+            merged.held.value = merged.held.value.saturating_add(balance.held.value);
+        }
+        self.locked |= other.locked;
+        self.open_disputes += other.open_disputes;
+        for (key, amount) in other.deposited {
+            let deposited = self.deposited.entry(key).or_insert(0);
+            *deposited = deposited.saturating_add(amount);
+        }
+        self.peak_available = self.peak_available.max(other.peak_available);
+        self.peak_held = self.peak_held.max(other.peak_held);
+        self.statement.extend(other.statement);
+        for (currency, net_flow) in other.net_flows {
+            *self.net_flows.entry(currency).or_insert(0) += net_flow;
+        }
+        self.update_peaks();
+        self.calculate_total();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::Bank;
+    use crate::shared_types::{ClientId, TxId};
+    use crate::transaction::{TxRequest, TxType};
+
+    fn apply(bank: &Bank, type_: TxType, client: u16, tx: u32, amount: Option<i64>) {
+        bank.apply(TxRequest { type_, client: ClientId(client), tx: TxId(tx), amount, currency: None }).unwrap();
+    }
+
+    #[test]
+    fn test_merge_disjoint_and_overlapping_clients() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000));
+        apply(&bank, TxType::Deposit, 2, 2, Some(20000));
+        let other = Bank::new();
+        apply(&other, TxType::Deposit, 2, 3, Some(5000));
+        apply(&other, TxType::Deposit, 3, 4, Some(30000));
+        apply(&other, TxType::Dispute, 3, 4, None);
+        apply(&other, TxType::Chargeback, 3, 4, None);
+        apply(&other, TxType::Deposit, 2, 5, Some(7000));
+        apply(&other, TxType::Dispute, 2, 5, None);
+
+        let report = bank.merge(&other);
+
+        assert_eq!((report.accounts, report.transactions), (2, 3));
+        assert!(report.conflicts.is_empty());
+        let client_2 = bank.account_snapshot(ClientId(2)).unwrap();
+        assert_eq!((client_2.available, client_2.held, client_2.total), (25000, 7000, 32000));
+        assert_eq!(client_2.open_disputes, 1);
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 10000);
+        let client_3 = bank.account_snapshot(ClientId(3)).unwrap();
+        assert!(client_3.locked);
+        assert_eq!(client_3.created_seq, 3);
+        assert_eq!(bank.stats().transactions, 5);
+
+        // Merged transactions can be disputed in the merged bank
+        apply(&bank, TxType::Resolve, 2, 5, None);
+        assert_eq!(bank.account_snapshot(ClientId(2)).unwrap().available, 32000);
+        assert_eq!(other.account_snapshot(ClientId(2)).unwrap().held, 7000);
+    }
+
+    #[test]
+    fn test_merge_flags_conflicting_tx_ids() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000));
+        let other = Bank::new();
+        apply(&other, TxType::Deposit, 2, 1, Some(20000));
+        apply(&other, TxType::Deposit, 2, 2, Some(30000));
+
+        let report = bank.merge(&other);
+
+        assert_eq!(report.conflicts, vec![TxId(1)]);
+        assert_eq!(report.transactions, 1);
+        assert_eq!(bank.stats().transactions, 2);
+        assert_eq!(bank.account_snapshot(ClientId(2)).unwrap().available, 50000);
+    }
+
+    #[test]
+    fn test_merge_with_handle_does_nothing() {
+        let bank = Bank::new();
+        apply(&bank, TxType::Deposit, 1, 1, Some(10000));
+
+        let report = bank.merge(&Bank::new_for_tokio(&bank));
+
+        assert_eq!(report.accounts, 0);
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 10000);
+    }
+}
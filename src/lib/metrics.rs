@@ -0,0 +1,142 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::report::{RejectionReason, RowError};
+use crate::transaction::TxType;
+
+/// Receives events as the bank processes transactions, e.g. to export them as live metrics
+///
+/// Every method does nothing by default, so an implementation only needs to handle the
+/// events it is interested in. Methods are called while the bank's accounts are locked,
+/// so they should return quickly.
+pub trait BankMetrics {
+    /// A transaction was applied, including disputes parked until the transaction they reference arrives
+    fn transaction_applied(&self, _tx_type: TxType) {}
+
+    /// A transaction was rejected
+    fn transaction_rejected(&self, _tx_type: TxType, _reason: RejectionReason) {}
+
+    /// A row of a csv could not be parsed into a transaction
+    fn row_skipped(&self, _error: &RowError) {}
+
+    /// A dispute moved funds from available to held
+    fn dispute_opened(&self) {}
+
+    /// A resolve or chargeback ended a dispute
+    fn dispute_closed(&self) {}
+
+    /// A chargeback locked an account
+    fn account_locked(&self) {}
+}
+
+/// Metrics that ignore every event, used until [crate::bank::Bank::set_metrics] is called
+#[derive(Debug, Default)]
+pub struct NoopMetrics;
+
+impl BankMetrics for NoopMetrics {}
+
+/// The metrics of a bank, shared by all its handles so setting them on one handle affects all
+pub(crate) struct SharedMetrics(RwLock<Arc<dyn BankMetrics + Send + Sync>>);
+
+impl SharedMetrics {
+    pub(crate) fn new() -> Self {
+        Self(RwLock::new(Arc::new(NoopMetrics)))
+    }
+
+    pub(crate) fn get(&self) -> Arc<dyn BankMetrics + Send + Sync> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub(crate) fn set(&self, metrics: Arc<dyn BankMetrics + Send + Sync>) {
+        *self.0.write().unwrap() = metrics;
+    }
+}
+
+impl fmt::Debug for SharedMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedMetrics")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    use crate::bank::Bank;
+    use crate::metrics::BankMetrics;
+    use crate::report::{RejectionReason, RowError};
+    use crate::transaction::TxType;
+
+    /// Counts every event by name
+    #[derive(Default)]
+    struct CountingMetrics(Mutex<BTreeMap<String, usize>>);
+
+    impl CountingMetrics {
+        fn count(&self, event: String) {
+            *self.0.lock().unwrap().entry(event).or_insert(0) += 1;
+        }
+
+        fn counts(&self) -> Vec<(String, usize)> {
+            self.0.lock().unwrap().clone().into_iter().collect()
+        }
+    }
+
+    impl BankMetrics for CountingMetrics {
+        fn transaction_applied(&self, tx_type: TxType) {
+            self.count(format!("applied {}", tx_type.as_str()));
+        }
+
+        fn transaction_rejected(&self, tx_type: TxType, reason: RejectionReason) {
+            self.count(format!("rejected {} {:?}", tx_type.as_str(), reason));
+        }
+
+        fn row_skipped(&self, _error: &RowError) {
+            self.count("row skipped".to_string());
+        }
+
+        fn dispute_opened(&self) {
+            self.count("dispute opened".to_string());
+        }
+
+        fn dispute_closed(&self) {
+            self.count("dispute closed".to_string());
+        }
+
+        fn account_locked(&self) {
+            self.count("account locked".to_string());
+        }
+    }
+
+    #[test]
+    fn test_metrics_counts_for_fixture() {
+        let metrics = Arc::new(CountingMetrics::default());
+        for fixture in ["tests/fixtures/dispute_chargeback.csv", "tests/fixtures/ignored_rows.csv"] {
+            let bank = Bank::new();
+            bank.set_metrics(metrics.clone());
+            bank.process_transactions_from_csv_path_blocking(fixture).unwrap();
+        }
+
+        let expected = [
+            ("account locked", 2),
+            ("applied chargeback", 2),
+            ("applied deposit", 5),
+            ("applied dispute", 2),
+            ("applied withdrawal", 1),
+            ("dispute closed", 2),
+            ("dispute opened", 2),
+            ("rejected chargeback NotDisputed", 1),
+            ("rejected deposit AccountLocked", 1),
+            ("rejected deposit ZeroAmount", 1),
+            ("rejected dispute ClientMismatch", 1),
+            ("rejected dispute UnknownTx", 1),
+            ("rejected resolve NotDisputed", 2),
+            ("rejected withdrawal AccountLocked", 1),
+            ("row skipped", 4),
+        ];
+        let expected: Vec<(String, usize)> = expected.iter()
+            .map(|(event, count)| (event.to_string(), *count))
+            .collect();
+        assert_eq!(metrics.counts(), expected);
+    }
+}
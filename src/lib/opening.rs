@@ -0,0 +1,225 @@
+use std::io;
+
+use csv::{ReaderBuilder, StringRecord, Trim};
+use serde::{Deserialize, Serialize};
+
+use crate::activity::ActivityKind;
+use crate::bank::{Account, Bank};
+use crate::config::ExcessPrecision;
+use crate::error::{BankError, OpeningBalancesError};
+use crate::journal::JournalEntry;
+use crate::shared_types::{Amount, AmountValue, ClientId};
+
+/// A row of opening balances, see [Bank::import_opening_balances]
+#[derive(Deserialize)]
+struct OpeningBalanceRow {
+    client: ClientId,
+    available: String,
+    held: String,
+    locked: bool,
+}
+
+/// The balances an account is created with by [Bank::import_opening_balances], as they are journaled
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct OpeningBalance {
+    client: ClientId,
+    available: AmountValue,
+    held: AmountValue,
+    locked: bool,
+}
+
+impl Bank {
+    /// Creates accounts with the balances of a prior system before any transactions are processed,
+    /// returning the number of accounts created
+    ///
+    /// The csv has a header row and the columns `client, available, held, locked`, with amounts in
+    /// the base currency as decimals of at most [crate::config::BankConfig::decimal_places] places,
+    /// e.g. `1,10.5,0.0,false`. Held funds are kept held, no transaction references them so they can't be
+    /// resolved or charged back. Fails without creating any account if the bank has processed
+    /// transactions, a client already has an account, appears twice or is one of
+    /// [crate::config::BankConfig::reserved_clients], or a balance is negative.
+    pub fn import_opening_balances<R: io::Read>(&self, reader: R) -> Result<usize, BankError> {
+        if self.max_seen_tx_id().is_some() {
+            return Err(OpeningBalancesError::AfterProcessing.into());
+        }
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let mut record = StringRecord::new();
+        let mut balances: Vec<(u64, OpeningBalance)> = Vec::new();
+        while reader.read_record(&mut record)? {
+            let line = record.position().map_or(0, |position| position.line());
+            let row: OpeningBalanceRow = record.deserialize(Some(&headers))?;
+            let parse = |value: &str| Amount::parse_with(value, self.config.decimal_places, ExcessPrecision::Reject, false)
+                .map_err(|_| OpeningBalancesError::InvalidAmount { line, value: value.to_string() });
+            let (available, held) = (parse(&row.available)?, parse(&row.held)?);
+            if available.value < 0 || held.value < 0 {
+                return Err(OpeningBalancesError::NegativeBalance { line, client: row.client }.into());
+            }
+            if balances.iter().any(|(_, balance)| balance.client == row.client) {
+                return Err(OpeningBalancesError::DuplicateClient { line, client: row.client }.into());
+            }
+            balances.push((line, OpeningBalance { client: row.client, available: available.value, held: held.value, locked: row.locked }));
+        }
+        self.insert_opening_balances(balances)
+    }
+
+    /// Creates the accounts of opening balances read from the given lines and journals them,
+    /// or none if a client already has an account or is reserved
+    pub(crate) fn insert_opening_balances(&self, balances: Vec<(u64, OpeningBalance)>) -> Result<usize, BankError> {
+        self.check_journal()?;
+        if let Some((line, balance)) = balances.iter().find(|(_, balance)| self.config.reserved_clients.contains(&balance.client)) {
+            return Err(OpeningBalancesError::ReservedClient { line: *line, client: balance.client }.into());
+        }
+        let mut existing = self.accounts.lock();
+        if let Some((line, balance)) = balances.iter().find(|(_, balance)| existing.contains_key(&balance.client)) {
+            return Err(OpeningBalancesError::DuplicateClient { line: *line, client: balance.client }.into());
+        }
+        let imported = balances.len();
+        let balances: Vec<OpeningBalance> = balances.into_iter().map(|(_, balance)| balance).collect();
+        for balance in &balances {
+            let client = balance.client;
+            let mut account = Account {
+                available: Amount { value: balance.available },
+                held: Amount { value: balance.held },
+                locked: balance.locked,
+                ..self.new_account(client)
+            };
+            account.calculate_total();
+            account.update_peaks();
+            if self.config.debug_invariants {
+                account.record_net_flow(self.config.base_currency, balance.available as i128 + balance.held as i128);
+            }
+            self.log_activity(ActivityKind::AccountCreated, client, None, None);
+            if account.locked {
+                self.log_activity(ActivityKind::AccountLocked, client, None, None);
+            }
+            existing.insert(client, account);
+        }
+        self.append_to_journal(&JournalEntry::OpeningBalances { accounts: balances })?;
+        Ok(imported)
+    }
+
+    /// Writes the accounts in the format read by [Bank::import_opening_balances], sorted by client,
+    /// returning the number of accounts written
+    ///
+    /// Only balances in the base currency are written. Negative balances, e.g. after a chargeback
+    /// of funds already withdrawn, are written as they are and refused on import.
+    pub fn export_opening_balances<W: io::Write>(&self, writer: W) -> Result<usize, BankError> {
+        let mut accounts: Vec<(ClientId, Amount, Amount, bool)> = self.accounts.lock().values()
+            .map(|account| (account.client, account.available, account.held, account.locked))
+            .collect();
+        accounts.sort_by_key(|(client, ..)| *client);
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["client", "available", "held", "locked"])?;
+        let decimal_places = self.config.decimal_places;
+        for (client, available, held, locked) in &accounts {
+            writer.write_record([
+                client.0.to_string(),
+                available.to_decimal_string_with(decimal_places),
+                held.to_decimal_string_with(decimal_places),
+                locked.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(accounts.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::bank::Bank;
+    use crate::config::BankConfig;
+    use crate::error::{BankError, OpeningBalancesError};
+    use crate::report::RejectionReason;
+    use crate::shared_types::{ClientId, TxId};
+    use crate::transaction::{TxRequest, TxType};
+
+    const OPENING_BALANCES: &str = "client, available, held, locked\n2, 10.5, 0, false\n1, 3, 1.25, false\n7, 0.0001, 0, true\n";
+
+    fn request(type_: TxType, client: u16, tx: u32, amount: Option<i64>) -> TxRequest {
+        TxRequest { type_, client: ClientId(client), tx: TxId(tx), amount, currency: None }
+    }
+
+    #[test]
+    fn test_withdrawal_against_imported_balance() {
+        let bank = Bank::new();
+
+        assert_eq!(bank.import_opening_balances(OPENING_BALANCES.as_bytes()).unwrap(), 3);
+
+        bank.apply(request(TxType::Withdrawal, 2, 1, Some(100000))).unwrap();
+        assert_eq!(bank.apply(request(TxType::Withdrawal, 1, 2, Some(40000))), Err(RejectionReason::InsufficientFunds));
+        assert_eq!(bank.apply(request(TxType::Deposit, 7, 3, Some(10000))), Err(RejectionReason::AccountLocked));
+        let client_2 = bank.account_snapshot(ClientId(2)).unwrap();
+        assert_eq!((client_2.available, client_2.held, client_2.total), (5000, 0, 5000));
+        let client_1 = bank.account_snapshot(ClientId(1)).unwrap();
+        assert_eq!((client_1.available, client_1.held, client_1.total), (30000, 12500, 42500));
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let bank = Bank::new();
+        bank.import_opening_balances(OPENING_BALANCES.as_bytes()).unwrap();
+        bank.apply(request(TxType::Deposit, 3, 1, Some(25000))).unwrap();
+        let mut exported = Vec::new();
+
+        assert_eq!(bank.export_opening_balances(&mut exported).unwrap(), 4);
+
+        let exported = String::from_utf8(exported).unwrap();
+        assert_eq!(exported, "client,available,held,locked\n1,3.0000,1.2500,false\n2,10.5000,0.0000,false\n3,2.5000,0.0000,false\n7,0.0001,0.0000,true\n");
+        let migrated = Bank::new();
+        assert_eq!(migrated.import_opening_balances(exported.as_bytes()).unwrap(), 4);
+        for client in [1, 2, 3, 7] {
+            assert_eq!(migrated.account_snapshot(ClientId(client)).map(|account| (account.available, account.held, account.locked)),
+                bank.account_snapshot(ClientId(client)).map(|account| (account.available, account.held, account.locked)));
+        }
+    }
+
+    #[test]
+    fn test_import_refused_after_processing() {
+        let bank = Bank::new();
+        bank.apply(request(TxType::Deposit, 1, 1, Some(10000))).unwrap();
+
+        let error = bank.import_opening_balances(OPENING_BALANCES.as_bytes()).unwrap_err();
+
+        assert!(matches!(error, BankError::OpeningBalances(OpeningBalancesError::AfterProcessing)));
+        assert!(bank.account_snapshot(ClientId(2)).is_none());
+    }
+
+    #[test]
+    fn test_import_refuses_duplicates_and_negative_balances() {
+        let import = |data: &str| {
+            let bank = Bank::new();
+            let outcome = bank.import_opening_balances(data.as_bytes());
+            (outcome, bank.account_snapshot(ClientId(1)))
+        };
+
+        let (outcome, account) = import("client, available, held, locked\n1, 1.0, 0, false\n1, 2.0, 0, false\n");
+        assert!(matches!(outcome, Err(BankError::OpeningBalances(OpeningBalancesError::DuplicateClient { line: 3, client: ClientId(1) }))));
+        assert!(account.is_none());
+
+        let (outcome, account) = import("client, available, held, locked\n1, 1.0, 0, false\n2, -1.0, 0, false\n");
+        assert!(matches!(outcome, Err(BankError::OpeningBalances(OpeningBalancesError::NegativeBalance { line: 3, client: ClientId(2) }))));
+        assert!(account.is_none());
+
+        let (outcome, _) = import("client, available, held, locked\n1, 1.00001, 0, false\n");
+        assert_eq!(outcome.unwrap_err().to_string(), "opening balances error: line 2: invalid amount \"1.00001\"");
+
+        let bank = Bank::new();
+        bank.open_account(ClientId(2));
+        let outcome = bank.import_opening_balances("client, available, held, locked\n1, 1.0, 0, false\n2, 1.0, 0, false\n".as_bytes());
+        assert!(matches!(outcome, Err(BankError::OpeningBalances(OpeningBalancesError::DuplicateClient { line: 3, client: ClientId(2) }))));
+        assert!(bank.account_snapshot(ClientId(1)).is_none());
+    }
+
+    #[test]
+    fn test_import_refuses_reserved_client() {
+        let bank = Bank::with_config(BankConfig { reserved_clients: HashSet::from([ClientId(0)]), ..BankConfig::default() });
+
+        let outcome = bank.import_opening_balances("client, available, held, locked\n1, 1.0, 0, false\n0, 1.0, 0, false\n".as_bytes());
+
+        assert!(matches!(outcome, Err(BankError::OpeningBalances(OpeningBalancesError::ReservedClient { line: 3, client: ClientId(0) }))));
+        assert_eq!(bank.stats().accounts, 0);
+    }
+}
@@ -0,0 +1,230 @@
+use tokio::sync::mpsc;
+
+use crate::bank::Bank;
+use crate::config::ProcessOptions;
+use crate::error::BankError;
+use crate::reader::TxReader;
+use crate::report::ProcessReport;
+use crate::shared_types::ClientId;
+use crate::transaction::Tx;
+
+/// Number of batches that can be queued for a worker before readers wait
+const WORKER_QUEUE_SIZE: usize = 64;
+/// Number of transactions a reader collects for a worker before sending them
+const BATCH_SIZE: usize = 256;
+
+impl Bank {
+    /// Processes all transactions in the csvs at the given paths using a pool of worker tasks
+    ///
+    /// Each worker owns a shard of the bank, a [Bank] of its own that no other task touches,
+    /// so applying a transaction never waits on a lock held by another task.
+    /// Rows are routed in batches to the worker for `client % workers`, which keeps the
+    /// order of each client's transactions within a file.
+    /// The bank's current accounts, transactions and parked disputes are moved into the shards before
+    /// processing starts and merged back once every file has been processed.
+    ///
+    /// A worker only sees the transactions of its own clients, so a dispute, resolve or chargeback
+    /// naming another client than the transaction's owner is treated as referencing an unknown
+    /// transaction rather than rejected as a client mismatch like [Bank::process_transactions_from_csv_path] does.
+    ///
+    /// Fails if one of the files can't be opened, after the others were processed and the shards merged back.
+    /// A panic of a reader or worker task is resumed on the calling task.
+    ///
+    /// # Arguments
+    ///
+    /// `csv_paths` - The csv files to process, each is read by its own task
+    /// `workers` - The number of worker tasks, at least one is used
+    pub async fn process_files_parallel(&self, csv_paths: &[String], workers: usize) -> Result<ProcessReport, BankError> {
+        let workers = workers.max(1);
+        let shards = self.split_into_shards(workers);
+
+        let mut senders = Vec::with_capacity(workers);
+        let mut worker_handles = Vec::with_capacity(workers);
+        for shard in shards {
+            let (sender, mut receiver) = mpsc::channel::<Vec<(u64, Tx)>>(WORKER_QUEUE_SIZE);
+            senders.push(sender);
+            worker_handles.push(tokio::spawn(async move {
+                let mut report = ProcessReport::default();
+                while let Some(batch) = receiver.recv().await {
+                    for (line, tx) in batch {
+                        shard.process_record(&mut report, line, tx);
+                    }
+                }
+                (shard, report)
+            }));
+        }
+
+        let reader_handles: Vec<_> = csv_paths.iter().cloned().map(|csv_path| {
+            let senders = senders.clone();
+            let source = self.register_source(&csv_path);
+            let decimal_places = self.config.decimal_places;
+            tokio::spawn(async move {
+                let mut file_reader = TxReader::from_path(&csv_path, &ProcessOptions::default())?
+                    .with_source(source)
+                    .with_decimal_places(decimal_places);
+                let mut report = ProcessReport::default();
+                let mut batches: Vec<Vec<(u64, Tx)>> = senders.iter().map(|_| Vec::with_capacity(BATCH_SIZE)).collect();
+                while let Some(record) = file_reader.next() {
+                    report.rows += 1;
+                    match record {
+                        Ok(record) => {
+                            let worker = record.client.0 as usize % senders.len();
+                            batches[worker].push((file_reader.line(), record));
+                            if batches[worker].len() == BATCH_SIZE {
+                                let batch = std::mem::replace(&mut batches[worker], Vec::with_capacity(BATCH_SIZE));
+                                // Workers only stop early if they panicked, which is resumed once they are joined
+                                if senders[worker].send(batch).await.is_err() {
+                                    return Ok(report);
+                                }
+                            }
+                        },
+                        Err(e) => report.record_row_error(e),
+                    }
+                }
+                report.bytes = file_reader.bytes();
+                for (worker, batch) in batches.into_iter().enumerate() {
+                    if !batch.is_empty() && senders[worker].send(batch).await.is_err() {
+                        break;
+                    }
+                }
+                Ok::<_, BankError>(report)
+            })
+        }).collect();
+        drop(senders);
+
+        let mut report = ProcessReport::default();
+        let mut failure = None;
+        for reader in reader_handles {
+            match reader.await {
+                Ok(Ok(reader_report)) => report.merge(reader_report),
+                Ok(Err(e)) => failure = failure.or(Some(e)),
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        }
+        for worker in worker_handles {
+            let (shard, worker_report) = match worker.await {
+                Ok(finished) => finished,
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            };
+            report.merge(worker_report);
+            self.merge_shard(shard);
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(report),
+        }
+    }
+
+    /// Moves this bank's accounts, transactions, parked disputes and chargeback losses into `count` new banks,
+    /// routed by client id
+    fn split_into_shards(&self, count: usize) -> Vec<Bank> {
+        let shards: Vec<Bank> = (0..count)
+            .map(|_| Bank { metrics: self.metrics.clone(), journal: self.journal.clone(), activity: self.activity.clone(), sources: self.sources.clone(), fees: self.fees.clone(), invariant_violations: self.invariant_violations.clone(), max_tx_id: self.max_tx_id.clone(), tx_seq: self.tx_seq.clone(), accounts_created: self.accounts_created.clone(), ..Bank::with_config_and_profiler((*self.config).clone(), self.profiler.clone()) })
+            .collect();
+        let shard_for = |client: ClientId| &shards[client.0 as usize % count];
+        for (client, account) in self.accounts.lock().drain() {
+            shard_for(client).accounts.lock().insert(client, account);
+        }
+        for tx in self.transactions.lock().drain() {
+            shard_for(tx.client).transactions.lock().insert(tx);
+        }
+        for (id, waiting) in self.pending_disputes.lock().take_all() {
+            shard_for(waiting[0].client).pending_disputes.lock().restore(id, waiting);
+        }
+        for (client, loss) in self.losses.lock().drain() {
+            shard_for(client).losses.lock().insert(client, loss);
+        }
+        shards
+    }
+
+    /// Moves the accounts, transactions, parked disputes and chargeback losses of a shard back into this bank
+    fn merge_shard(&self, shard: Bank) {
+        self.accounts.lock().extend(shard.accounts.lock().drain());
+        let mut transactions = self.transactions.lock();
+        for tx in shard.transactions.lock().drain() {
+            transactions.insert(tx);
+        }
+        let mut pending_disputes = self.pending_disputes.lock();
+        for (id, waiting) in shard.pending_disputes.lock().take_all() {
+            pending_disputes.restore(id, waiting);
+        }
+        self.losses.lock().extend(shard.losses.lock().drain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::bank::Bank;
+    use crate::error::BankError;
+    use crate::shared_types::ClientId;
+
+    const FIXTURE: &str = "transactions-provided-100k.csv";
+
+    fn sorted_output(bank: &Bank) -> Vec<String> {
+        let mut output = Vec::new();
+        bank.write_accounts_to(&mut output).unwrap();
+        let mut lines: Vec<String> = String::from_utf8(output).unwrap().lines().map(String::from).collect();
+        lines.sort();
+        lines
+    }
+
+    #[tokio::test]
+    async fn test_parallel_output_matches_inline_engine() {
+        let bank = Bank::new();
+        let inline_report = bank.process_transactions_from_csv_path(FIXTURE).await.unwrap();
+
+        let sharded_bank = Bank::new();
+        let sharded_report = sharded_bank.process_files_parallel(&[FIXTURE.to_string()], 4).await.unwrap();
+
+        assert_eq!(inline_report, sharded_report);
+        assert_eq!(sorted_output(&bank), sorted_output(&sharded_bank));
+        assert_eq!(bank.transactions.lock().len(), sharded_bank.transactions.lock().len());
+    }
+
+    #[tokio::test]
+    async fn test_parallel_keeps_existing_state() {
+        let bank = Bank::new();
+        bank.process_files_parallel(&["transactions.csv".to_string()], 3).await.unwrap();
+        bank.process_files_parallel(&["transactions.csv".to_string()], 2).await.unwrap();
+
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 30000);
+        assert_eq!(bank.account_snapshot(ClientId(2)).unwrap().available, 10000);
+    }
+
+    #[tokio::test]
+    async fn test_missing_file_fails_after_merging_others() {
+        let bank = Bank::new();
+        let paths = ["transactions.csv".to_string(), "missing.csv".to_string()];
+
+        let outcome = bank.process_files_parallel(&paths, 2).await;
+
+        assert!(matches!(outcome, Err(BankError::Io(_))));
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 15000);
+    }
+
+    /// Compares the throughput of both engines, run with
+    /// `cargo test --release -- --ignored --nocapture bench_parallel`
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn bench_parallel_against_inline_engine() {
+        let paths = vec![FIXTURE.to_string(); 10];
+
+        let bank = Bank::new();
+        let start = Instant::now();
+        let handles: Vec<_> = paths.iter().cloned().map(|path| {
+            let tokio_bank = Bank::new_for_tokio(&bank);
+            tokio::spawn(async move { tokio_bank.process_transactions_from_csv_path(&path).await })
+        }).collect();
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+        eprintln!("inline engine: {:?}", start.elapsed());
+
+        let bank = Bank::new();
+        let start = Instant::now();
+        bank.process_files_parallel(&paths, 4).await.unwrap();
+        eprintln!("parallel engine, 4 workers: {:?}", start.elapsed());
+    }
+}
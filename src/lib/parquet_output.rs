@@ -0,0 +1,167 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, BooleanArray, Int64Array, RecordBatch, StringArray, UInt16Array};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+
+use crate::bank::Bank;
+use crate::error::BankError;
+use crate::shared_types::{AmountValue, ClientId, CurrencyCode};
+
+/// A row of [Bank::write_accounts_parquet]
+struct ParquetRow {
+    client: ClientId,
+    currency: CurrencyCode,
+    available: AmountValue,
+    held: AmountValue,
+    total: AmountValue,
+    locked: bool,
+}
+
+impl Bank {
+    /// Writes the bank's accounts to a Parquet file at the given path, one row per client and currency
+    /// sorted by client id, with the same rows as the csv output of a bank that has seen more than one
+    /// currency, see [Bank::write_accounts_to]
+    ///
+    /// The columns are `client: u16, currency: utf8, available: i64, held: i64, total: i64, locked: bool`.
+    /// Amounts are in the bank's [internal integer representation](crate::shared_types::Amount),
+    /// e.g. `15000` for `1.5` with the default four decimal places, whatever [crate::config::BankConfig::amount_output_format] says. Unlike a `decimal(18,4)` column
+    /// this holds every balance the bank can hold. Negative totals are written as zero with
+    /// [crate::config::BankConfig::clamp_negative_totals]. An existing file is overwritten.
+    pub fn write_accounts_parquet(&self, path: &Path) -> Result<(), BankError> {
+        let mut rows = Vec::new();
+        for account in self.accounts.lock().values() {
+            for (currency, available, held) in account.balances(self.config.base_currency) {
+                let total = available.value.saturating_add(held.value);
+                rows.push(ParquetRow {
+                    client: account.client,
+                    currency,
+                    available: available.value,
+                    held: held.value,
+                    total: if self.config.clamp_negative_totals { total.max(0) } else { total },
+                    locked: account.locked,
+                });
+            }
+        }
+        // Stable, so the rows of a client keep the base currency first
+        rows.sort_by_key(|row| row.client);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(rows.iter().map(|row| row.client.0).collect::<UInt16Array>()),
+            Arc::new(rows.iter().map(|row| Some(row.currency.as_str())).collect::<StringArray>()),
+            Arc::new(rows.iter().map(|row| row.available).collect::<Int64Array>()),
+            Arc::new(rows.iter().map(|row| row.held).collect::<Int64Array>()),
+            Arc::new(rows.iter().map(|row| row.total).collect::<Int64Array>()),
+            Arc::new(rows.iter().map(|row| Some(row.locked)).collect::<BooleanArray>()),
+        ];
+        let batch = RecordBatch::try_new(account_schema(), columns).map_err(parquet::errors::ParquetError::from)?;
+        let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+/// The columns of [Bank::write_accounts_parquet]
+fn account_schema() -> Arc<Schema> {
+    Arc::new(Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("currency", DataType::Utf8, false),
+        Field::new("available", DataType::Int64, false),
+        Field::new("held", DataType::Int64, false),
+        Field::new("total", DataType::Int64, false),
+        Field::new("locked", DataType::Boolean, false),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
+
+    use arrow_array::{BooleanArray, Int64Array, StringArray, UInt16Array};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use crate::bank::Bank;
+    use crate::shared_types::{ClientId, TxId};
+    use crate::transaction::{TxRequest, TxType};
+
+    /// Reads the rows of a file written by [Bank::write_accounts_parquet]
+    fn read_rows(path: &Path) -> Vec<(u16, String, i64, i64, i64, bool)> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap().build().unwrap();
+        let mut rows = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let column = |name| batch.column_by_name(name).unwrap();
+            let clients = column("client").as_any().downcast_ref::<UInt16Array>().unwrap();
+            let currencies = column("currency").as_any().downcast_ref::<StringArray>().unwrap();
+            let available = column("available").as_any().downcast_ref::<Int64Array>().unwrap();
+            let held = column("held").as_any().downcast_ref::<Int64Array>().unwrap();
+            let total = column("total").as_any().downcast_ref::<Int64Array>().unwrap();
+            let locked = column("locked").as_any().downcast_ref::<BooleanArray>().unwrap();
+            for i in 0..batch.num_rows() {
+                rows.push((clients.value(i), currencies.value(i).to_string(), available.value(i), held.value(i), total.value(i), locked.value(i)));
+            }
+        }
+        rows
+    }
+
+    /// A directory of its own for this test run, so concurrent runs don't share files
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bank_lib_test_parquet_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_accounts_read_back_match_snapshots() {
+        let bank = Bank::new();
+        let apply = |type_, client, tx, amount| bank.apply(TxRequest { type_, client: ClientId(client), tx: TxId(tx), amount, currency: None });
+        apply(TxType::Deposit, 2, 1, Some(15000)).unwrap();
+        apply(TxType::Deposit, 1, 2, Some(10000)).unwrap();
+        apply(TxType::Deposit, 1, 3, Some(5000)).unwrap();
+        apply(TxType::Dispute, 1, 3, None).unwrap();
+        apply(TxType::Deposit, 3, 4, Some(20000)).unwrap();
+        apply(TxType::Withdrawal, 3, 5, Some(5000)).unwrap();
+        apply(TxType::Dispute, 3, 4, None).unwrap();
+        apply(TxType::Chargeback, 3, 4, None).unwrap();
+        let path = temp_dir("snapshots").join("accounts.parquet");
+
+        bank.write_accounts_parquet(&path).unwrap();
+
+        let expected: Vec<_> = [1, 2, 3].into_iter().map(|client| {
+            let snapshot = bank.account_snapshot(ClientId(client)).unwrap();
+            (client, "USD".to_string(), snapshot.available, snapshot.held, snapshot.total, snapshot.locked)
+        }).collect();
+        let rows = read_rows(&path);
+        assert_eq!(rows, expected);
+        assert_eq!(rows[0], (1, "USD".to_string(), 10000, 5000, 15000, false));
+        assert_eq!(rows[2], (3, "USD".to_string(), -5000, 0, -5000, true));
+    }
+
+    #[test]
+    fn test_one_row_per_client_and_currency() {
+        let bank = Bank::new();
+        let apply = |client, tx, amount, currency: Option<&str>| bank.apply(TxRequest {
+            type_: TxType::Deposit,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Some(amount),
+            currency: currency.map(|currency| currency.parse().unwrap()),
+        });
+        apply(1, 1, 10000, None).unwrap();
+        apply(1, 2, 20000, Some("EUR")).unwrap();
+        apply(2, 3, 30000, Some("EUR")).unwrap();
+        let path = temp_dir("currencies").join("accounts.parquet");
+
+        bank.write_accounts_parquet(&path).unwrap();
+
+        assert_eq!(read_rows(&path), vec![
+            (1, "USD".to_string(), 10000, 0, 10000, false),
+            (1, "EUR".to_string(), 20000, 0, 20000, false),
+            (2, "EUR".to_string(), 30000, 0, 30000, false),
+        ]);
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use crate::report::RejectionReason;
+use crate::shared_types::{ClientId, TxId};
+use crate::transaction::{Tx, TxType};
+
+/// Meta-transactions referencing transactions that are not on the transaction sheet yet
+///
+/// When files are processed concurrently a dispute can be read before the deposit it
+/// disputes. Instead of dropping it, the dispute is parked here and applied once the
+/// transaction it references is recorded, together with any resolve or chargeback
+/// that arrived for it in the meantime.
+///
+/// A parked meta-transaction keeps the client that sent it and is applied with the same checks
+/// as any other, so one from a client that turns out not to own the transaction is rejected then.
+#[derive(Debug, Default)]
+pub(crate) struct PendingDisputes {
+    by_tx: HashMap<TxId, Vec<Tx>>,
+    len: usize,
+    capacity: usize,
+}
+
+impl PendingDisputes {
+    /// Creates a queue holding at most `capacity` meta-transactions, zero disables parking
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { by_tx: HashMap::new(), len: 0, capacity }
+    }
+
+    /// Parks a meta-transaction whose referenced transaction is unknown
+    ///
+    /// Disputes and reversals start a new entry, resolves and chargebacks are only parked
+    /// behind a dispute that is already waiting for the same transaction.
+    /// Fails with [RejectionReason::UnknownTx] if the transaction can't be parked.
+    pub(crate) fn park(&mut self, tx: Tx) -> Result<(), RejectionReason> {
+        if self.len >= self.capacity {
+            return Err(RejectionReason::UnknownTx);
+        }
+        match tx.type_ {
+            TxType::Dispute | TxType::Reversal => self.by_tx.entry(tx.tx).or_default().push(tx),
+            TxType::Resolve | TxType::Chargeback => match self.by_tx.get_mut(&tx.tx) {
+                Some(waiting) => waiting.push(tx),
+                None => return Err(RejectionReason::UnknownTx),
+            },
+            TxType::Deposit | TxType::Withdrawal => return Err(RejectionReason::UnknownTx),
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes and returns the meta-transactions waiting for the given transaction, in arrival order
+    pub(crate) fn take(&mut self, tx: TxId) -> Vec<Tx> {
+        let waiting = self.by_tx.remove(&tx).unwrap_or_default();
+        self.len -= waiting.len();
+        waiting
+    }
+
+    /// Number of parked meta-transactions
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Drops every parked meta-transaction sent by the given client
+    pub(crate) fn remove_client(&mut self, client: ClientId) {
+        for waiting in self.by_tx.values_mut() {
+            let before = waiting.len();
+            waiting.retain(|tx| tx.client != client);
+            self.len -= before - waiting.len();
+        }
+        self.by_tx.retain(|_, waiting| !waiting.is_empty());
+    }
+
+    /// Drops everything that is parked
+    pub(crate) fn clear(&mut self) {
+        self.by_tx.clear();
+        self.len = 0;
+    }
+
+    /// Removes and returns everything that is parked, grouped by the referenced transaction
+    #[cfg(feature = "async")]
+    pub(crate) fn take_all(&mut self) -> Vec<(TxId, Vec<Tx>)> {
+        self.len = 0;
+        self.by_tx.drain().collect()
+    }
+
+    /// Copies of everything that is parked, grouped by the referenced transaction
+    pub(crate) fn clone_all(&self) -> Vec<(TxId, Vec<Tx>)> {
+        self.by_tx.iter().map(|(id, waiting)| (*id, waiting.clone())).collect()
+    }
+
+    /// Copies of the meta-transactions waiting for the given transaction, in arrival order
+    pub(crate) fn clone_waiting(&self, tx: TxId) -> Vec<Tx> {
+        self.by_tx.get(&tx).cloned().unwrap_or_default()
+    }
+
+    /// Parks meta-transactions previously removed with [PendingDisputes::take_all], ignoring the capacity
+    pub(crate) fn restore(&mut self, tx: TxId, mut waiting: Vec<Tx>) {
+        self.len += waiting.len();
+        self.by_tx.entry(tx).or_default().append(&mut waiting);
+    }
+}
@@ -0,0 +1,147 @@
+use std::io;
+use std::sync::mpsc;
+use std::time::Instant;
+
+use crate::bank::{report_progress, Bank};
+use crate::checkpoint::{Checkpoint, ResumePoint};
+use crate::config::ProcessOptions;
+use crate::reader::TxReader;
+use crate::report::{LineError, ProcessReport, ProgressUpdate};
+use crate::transaction::Tx;
+
+/// Number of rows the parsing thread collects before sending them to be applied
+const BATCH_SIZE: usize = 256;
+
+/// A row parsed from a csv with where it was found
+struct ParsedRow {
+    line: u64,
+    /// Bytes of the csv read up to the end of the row
+    bytes: u64,
+    /// Where the next row starts, for the checkpoint
+    next: ResumePoint,
+    record: Result<Tx, LineError>,
+}
+
+impl Bank {
+    /// Processes the transactions read by a [TxReader] with parsing and applying on separate threads
+    ///
+    /// The csv is parsed on a thread of its own that sends batches of rows over a channel with room
+    /// for `capacity` batches, at least one, while the calling thread applies them in the order they were read.
+    /// Parsing waits while the channel is full, so no more of the file is held in memory than that.
+    /// The outcome is the same as [Bank::process_records], including for [crate::config::TxOrdering::Timestamp].
+    pub(crate) fn process_records_pipelined<R: io::Read + Send>(
+        &self,
+        tx_reader: TxReader<R>,
+        options: &ProcessOptions,
+        capacity: usize,
+        mut checkpoint: Option<Checkpoint>,
+    ) -> ProcessReport {
+        let mut tx_reader = tx_reader.with_decimal_places(self.config.decimal_places);
+        let (sender, receiver) = mpsc::sync_channel::<Vec<ParsedRow>>(capacity.max(1));
+        std::thread::scope(|scope| {
+            let parsing = scope.spawn(move || {
+                let mut batch = Vec::with_capacity(BATCH_SIZE);
+                while let Some(record) = tx_reader.next() {
+                    batch.push(ParsedRow { line: tx_reader.line(), bytes: tx_reader.bytes(), next: tx_reader.resume_point(), record });
+                    if batch.len() == BATCH_SIZE {
+                        let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                        // Only fails if the applying thread is gone, then there is no one to read for
+                        if sender.send(full).is_err() {
+                            break;
+                        }
+                    }
+                }
+                if !batch.is_empty() {
+                    let _ = sender.send(batch);
+                }
+                (tx_reader.bytes(), tx_reader.resume_point())
+            });
+
+            let mut report = ProcessReport::default();
+            let mut collected = Vec::new();
+            let started = Instant::now();
+            'batches: for batch in receiver {
+                for row in batch {
+                    if self.is_poisoned() {
+                        break 'batches;
+                    }
+                    self.process_read_record(&mut report, &mut collected, options, row.line, row.record);
+                    if let Some(checkpoint) = &mut checkpoint {
+                        checkpoint.row_processed(row.next);
+                    }
+                    report_progress(options, ProgressUpdate { rows: report.rows, bytes: row.bytes, elapsed: started.elapsed() });
+                }
+            }
+            let (bytes, end) = match parsing.join() {
+                Ok(read) => read,
+                Err(panic) => std::panic::resume_unwind(panic),
+            };
+            report.bytes = bytes;
+            self.process_collected(&mut report, collected);
+            if let Some(checkpoint) = &mut checkpoint {
+                checkpoint.write(end);
+            }
+            report
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::bank::Bank;
+    use crate::config::{ProcessOptions, TxOrdering};
+    use crate::report::ProcessReport;
+
+    /// Processes a csv into a new bank, returning the report, the account output sorted by line
+    /// and the transaction sheet
+    fn run(csv_path: &str, options: &ProcessOptions) -> (ProcessReport, Vec<String>, String) {
+        let bank = Bank::new();
+        let report = bank.process_transactions_from_csv_path_with_options_blocking(csv_path, options).unwrap();
+        let (mut accounts, mut transactions) = (Vec::new(), Vec::new());
+        bank.write_accounts_to(&mut accounts).unwrap();
+        bank.write_transactions(&mut transactions).unwrap();
+        let mut accounts: Vec<String> = String::from_utf8(accounts).unwrap().lines().map(String::from).collect();
+        accounts.sort();
+        (report, accounts, String::from_utf8(transactions).unwrap())
+    }
+
+    #[test]
+    fn test_pipeline_output_matches_inline_path() {
+        let csv_paths = [
+            "transactions-provided-100k.csv",
+            "tests/fixtures/ignored_rows.csv",
+            "tests/fixtures/dispute_chargeback.csv",
+            "tests/fixtures/multi_currency.csv",
+            "tests/fixtures/empty.csv",
+        ];
+        for csv_path in csv_paths {
+            for ordering in [TxOrdering::FileOrder, TxOrdering::Timestamp] {
+                let inline = ProcessOptions { ordering, ..ProcessOptions::default() };
+                let pipelined = ProcessOptions { ordering, pipeline: Some(2), ..ProcessOptions::default() };
+
+                assert_eq!(run(csv_path, &inline), run(csv_path, &pipelined), "{} in {:?}", csv_path, ordering);
+            }
+        }
+    }
+
+    /// Compares the throughput of the pipeline and the inline path, run with
+    /// `cargo test --release -- --ignored --nocapture bench_pipeline`
+    #[test]
+    #[ignore]
+    fn bench_pipeline_against_inline_path() {
+        for (name, options) in [
+            ("inline path", ProcessOptions::default()),
+            ("pipeline, 16 batches", ProcessOptions { pipeline: Some(16), ..ProcessOptions::default() }),
+        ] {
+            let bank = Bank::new();
+            let start = Instant::now();
+            for _ in 0..10 {
+                bank.clear();
+                bank.process_transactions_from_csv_path_with_options_blocking("transactions-provided-100k.csv", &options).unwrap();
+            }
+            println!("{}: {:?}", name, start.elapsed());
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::bank::Bank;
+use crate::lock::LockRank;
+use crate::report::{LockProfile, ProfileReport};
+
+/// Times of one lock, in nanoseconds, see [Profiler]
+#[derive(Default)]
+struct LockTimes {
+    acquisitions: AtomicU64,
+    wait_nanos: AtomicU64,
+    hold_nanos: AtomicU64,
+}
+
+/// When profiling was turned on, and how long it was on for once turned off
+#[derive(Default)]
+struct ProfileClock {
+    started: Option<Instant>,
+    elapsed: Duration,
+}
+
+/// Measures the locks and throughput of a bank while turned on, see [Bank::set_profiling]
+///
+/// While turned off the locks only read a flag, no time is taken.
+#[derive(Default)]
+pub(crate) struct Profiler {
+    enabled: AtomicBool,
+    clock: Mutex<ProfileClock>,
+    records: AtomicU64,
+    /// Indexed by [LockRank]
+    locks: [LockTimes; LockRank::ALL.len()],
+}
+
+impl Profiler {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Turns profiling on, starting over from zero, or off, keeping what was measured
+    fn set_enabled(&self, enabled: bool) {
+        let mut clock = self.clock.lock().unwrap();
+        if enabled {
+            self.records.store(0, Ordering::Relaxed);
+            for times in &self.locks {
+                times.acquisitions.store(0, Ordering::Relaxed);
+                times.wait_nanos.store(0, Ordering::Relaxed);
+                times.hold_nanos.store(0, Ordering::Relaxed);
+            }
+            *clock = ProfileClock { started: Some(Instant::now()), elapsed: Duration::ZERO };
+        } else if let Some(started) = clock.started.take() {
+            clock.elapsed = started.elapsed();
+        }
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_wait(&self, rank: LockRank, wait: Duration) {
+        let times = &self.locks[rank as usize];
+        times.acquisitions.fetch_add(1, Ordering::Relaxed);
+        times.wait_nanos.fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_hold(&self, rank: LockRank, hold: Duration) {
+        self.locks[rank as usize].hold_nanos.fetch_add(hold.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a processed transaction, if turned on
+    pub(crate) fn record_processed(&self) {
+        if self.is_enabled() {
+            self.records.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn report(&self) -> ProfileReport {
+        let clock = self.clock.lock().unwrap();
+        ProfileReport {
+            records: self.records.load(Ordering::Relaxed),
+            elapsed: clock.started.map_or(clock.elapsed, |started| started.elapsed()),
+            locks: LockRank::ALL.iter().map(|&rank| {
+                let times = &self.locks[rank as usize];
+                LockProfile {
+                    lock: rank.name(),
+                    acquisitions: times.acquisitions.load(Ordering::Relaxed),
+                    wait: Duration::from_nanos(times.wait_nanos.load(Ordering::Relaxed)),
+                    hold: Duration::from_nanos(times.hold_nanos.load(Ordering::Relaxed)),
+                }
+            }).collect(),
+        }
+    }
+}
+
+impl Bank {
+    /// Creates an empty bank that measures where its time goes if `enabled`, see [Bank::set_profiling]
+    pub fn with_profiling(enabled: bool) -> Self {
+        let bank = Bank::new();
+        bank.set_profiling(enabled);
+        bank
+    }
+
+    /// Turns measuring the time spent waiting for and holding each lock of the bank, and the number
+    /// of transactions processed, on or off for this bank and all other handles to it
+    ///
+    /// Turning it on starts the [Bank::profile_report] over. While off no time is taken, so it costs
+    /// next to nothing.
+    pub fn set_profiling(&self, enabled: bool) {
+        self.profiler.set_enabled(enabled);
+    }
+
+    /// What was measured since profiling was last turned on, see [Bank::set_profiling]
+    pub fn profile_report(&self) -> ProfileReport {
+        self.profiler.report()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::bank::Bank;
+
+    const FIXTURE: &str = "tests/fixtures/dispute_resolve.csv";
+
+    #[test]
+    fn test_profile_of_processed_fixture() {
+        let bank = Bank::with_profiling(true);
+
+        let report = bank.process_transactions_from_csv_path_blocking(FIXTURE).unwrap();
+        let mut output = Vec::new();
+        bank.write_accounts_to(&mut output).unwrap();
+
+        let profile = bank.profile_report();
+        assert_eq!(profile.records, report.rows as u64);
+        assert!(profile.elapsed > Duration::ZERO);
+        assert!(profile.records_per_second() > 0.0);
+        let locks: Vec<_> = profile.locks.iter().map(|lock| lock.lock).collect();
+        assert_eq!(locks, ["accounts", "transactions", "pending_disputes", "losses", "fees"]);
+        for lock in &profile.locks[..2] {
+            assert!(lock.acquisitions >= report.rows as u64, "{:?}", lock);
+            assert!(lock.wait > Duration::ZERO && lock.hold > Duration::ZERO, "{:?}", lock);
+        }
+    }
+
+    #[test]
+    fn test_nothing_measured_while_off() {
+        let bank = Bank::with_profiling(true);
+        bank.set_profiling(false);
+        let elapsed = bank.profile_report().elapsed;
+
+        bank.process_transactions_from_csv_path_blocking(FIXTURE).unwrap();
+
+        let profile = Bank::new_for_tokio(&bank).profile_report();
+        assert_eq!((profile.records, profile.elapsed), (0, elapsed));
+        assert!(profile.locks.iter().all(|lock| lock.acquisitions == 0 && lock.hold == Duration::ZERO));
+    }
+}
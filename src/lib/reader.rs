@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use csv::{Reader, ReaderBuilder, StringRecord};
+
+use crate::checkpoint::ResumePoint;
+use crate::config::{CsvHeaders, ExcessPrecision, ProcessOptions};
+use crate::error::BankError;
+use crate::record_limit::{RecordLimit, OVERSIZED_MARKER};
+use crate::report::{LineError, RowError};
+use crate::shared_types::{Amount, SourceId};
+use crate::transaction::{RawTxRecord, Tx};
+
+/// Column names used for files without a header row
+const DEFAULT_HEADERS: [&str; 6] = ["type", "client", "tx", "amount", "currency", "timestamp"];
+
+/// Reads transactions from csv data record by record
+///
+/// Headers are handled here instead of by the csv crate so that files without a
+/// header row can be processed, see [CsvHeaders].
+pub(crate) struct TxReader<R: io::Read> {
+    reader: Reader<RecordLimit<R>>,
+    headers: StringRecord,
+    /// A record read while detecting headers that still needs to be returned
+    pending: Option<StringRecord>,
+    excess_precision: ExcessPrecision,
+    allow_scientific: bool,
+    /// Decimal places amounts are parsed with, see [crate::config::BankConfig::decimal_places]
+    decimal_places: u32,
+    max_record_bytes: usize,
+    max_fields: usize,
+    /// Line of the record last returned
+    line: u64,
+    /// Lines of oversized records that were dropped before reaching the csv reader,
+    /// added to the line numbers it reports
+    skipped_lines: u64,
+    /// The file the records are read from, set on every transaction returned
+    source: Option<SourceId>,
+    /// Offset into the file the csv data starts at, see [TxReader::resumed]
+    start_byte: u64,
+}
+
+impl TxReader<File> {
+    pub(crate) fn from_path(csv_path: &str, options: &ProcessOptions) -> Result<Self, BankError> {
+        Ok(Self::from_reader(File::open(csv_path)?, options)?)
+    }
+}
+
+impl<R: io::Read> TxReader<R> {
+    pub(crate) fn from_reader(reader: R, options: &ProcessOptions) -> Result<Self, csv::Error> {
+        Self::new(csv_reader_builder(options).from_reader(record_limit(reader, options)), options)
+    }
+
+    fn new(mut reader: Reader<RecordLimit<R>>, options: &ProcessOptions) -> Result<Self, csv::Error> {
+        let (headers, pending) = Self::read_headers(&mut reader, options)?;
+        Ok(Self::with_headers(reader, options, headers, pending))
+    }
+
+    /// Takes the header row from the first record, if it is one, returning the record otherwise
+    fn read_headers(reader: &mut Reader<RecordLimit<R>>, options: &ProcessOptions) -> Result<(StringRecord, Option<StringRecord>), csv::Error> {
+        let default_headers = StringRecord::from(DEFAULT_HEADERS.to_vec());
+        let mut first = StringRecord::new();
+        let (headers, pending) = if !read_non_blank_record(reader, &mut first)? {
+            (default_headers, None)
+        } else {
+            let first_is_header = match options.headers {
+                _ if is_oversized(&first) => false,
+                CsvHeaders::Present => true,
+                CsvHeaders::Absent => false,
+                CsvHeaders::Detect => first.deserialize::<RawTxRecord>(Some(&default_headers)).is_err(),
+            };
+            if first_is_header {
+                (rename_columns(&first, &options.column_names), None)
+            } else {
+                (default_headers, Some(first))
+            }
+        };
+        Ok((headers, pending))
+    }
+
+    fn with_headers(reader: Reader<RecordLimit<R>>, options: &ProcessOptions, headers: StringRecord, pending: Option<StringRecord>) -> Self {
+        Self {
+            reader,
+            headers,
+            pending,
+            excess_precision: options.excess_precision,
+            allow_scientific: options.allow_scientific,
+            decimal_places: Amount::DECIMAL_PLACES,
+            max_record_bytes: options.max_record_bytes,
+            max_fields: options.max_fields,
+            line: 0,
+            skipped_lines: 0,
+            source: None,
+            start_byte: 0,
+        }
+    }
+}
+
+impl<R: io::Read + io::Seek> TxReader<R> {
+    /// Reads csv data from the given point on, taking the header row from the start of the data
+    /// as usual, see [crate::config::ProcessOptions::resume]
+    pub(crate) fn resumed(mut reader: R, options: &ProcessOptions, point: ResumePoint) -> Result<Self, BankError> {
+        let (headers, _) = TxReader::read_headers(&mut csv_reader_builder(options).from_reader(record_limit(&mut reader, options)), options)?;
+        reader.seek(io::SeekFrom::Start(point.byte))?;
+        let limited = record_limit(reader, options).continuing();
+        Ok(Self {
+            start_byte: point.byte,
+            // So lines are counted from the line the point is on
+            skipped_lines: point.line.saturating_sub(1),
+            ..Self::with_headers(csv_reader_builder(options).from_reader(limited), options, headers, None)
+        })
+    }
+}
+
+impl<R: io::Read> TxReader<R> {
+    /// Marks every transaction read as coming from the given file, see [Tx::source]
+    pub(crate) fn with_source(mut self, source: SourceId) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Parses amounts with the given number of decimal places instead of the default four
+    pub(crate) fn with_decimal_places(mut self, decimal_places: u32) -> Self {
+        self.decimal_places = decimal_places;
+        self
+    }
+
+    /// The number of bytes of csv data read so far
+    ///
+    /// Records over [ProcessOptions::max_record_bytes] count as the few bytes standing in for them.
+    pub(crate) fn bytes(&self) -> u64 {
+        self.reader.position().byte()
+    }
+
+    /// The line of the input the record last returned starts on
+    pub(crate) fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// Where the record after the one last returned starts in the input
+    pub(crate) fn resume_point(&self) -> ResumePoint {
+        let position = self.reader.position();
+        ResumePoint {
+            byte: self.start_byte + self.reader.get_ref().input_offset(position.byte()),
+            line: position.line() + self.skipped_lines,
+        }
+    }
+
+    /// Checks the fields of a record that serde would only give an opaque error for
+    fn validate(&self, record: &StringRecord) -> Result<(), RowError> {
+        let field = |name: &str| self.headers.iter().position(|header| header == name).and_then(|index| record.get(index));
+        for column in ["client", "tx"] {
+            if let Some(value) = field(column).filter(|value| !is_unsigned_integer(value)) {
+                return Err(RowError::InvalidField { column: column.to_string(), value: value.to_string() });
+            }
+        }
+        if let Some(client) = field("client").filter(|client| is_larger_than(client, u16::MAX as u64)) {
+            return Err(RowError::ClientIdOutOfRange { value: client.to_string() });
+        }
+        if let Some(tx) = field("tx").filter(|tx| is_larger_than(tx, u32::MAX as u64)) {
+            return Err(RowError::TxIdOutOfRange { value: tx.to_string() });
+        }
+        Ok(())
+    }
+}
+
+impl<R: io::Read> Iterator for TxReader<R> {
+    type Item = Result<Tx, LineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.pending.take() {
+            Some(record) => record,
+            None => {
+                let mut record = StringRecord::new();
+                match read_non_blank_record(&mut self.reader, &mut record) {
+                    Ok(true) => record,
+                    Ok(false) => return None,
+                    Err(e) => {
+                        let line = e.position().map_or(0, |position| position.line() + self.skipped_lines);
+                        return Some(Err(LineError { line, error: RowError::Malformed(e.to_string()) }));
+                    },
+                }
+            }
+        };
+        let line = record.position().map_or(0, |position| position.line() + self.skipped_lines);
+        self.line = line;
+        if is_oversized(&record) {
+            self.skipped_lines += record.get(1).and_then(|lines| lines.parse().ok()).unwrap_or(0);
+            return Some(Err(LineError { line, error: RowError::RecordTooLarge { max: self.max_record_bytes } }));
+        }
+        if record.len() > self.max_fields {
+            return Some(Err(LineError { line, error: RowError::TooManyFields { max: self.max_fields } }));
+        }
+        let parsed = self.validate(&record).and_then(|()| {
+            let raw: RawTxRecord = record.deserialize(Some(&self.headers))
+                .map_err(|e| RowError::Malformed(e.to_string()))?;
+            let timestamp = raw.timestamp;
+            let request = raw.into_request_with(self.decimal_places, self.excess_precision, self.allow_scientific)?;
+            Ok(Tx { timestamp, source: self.source, ..Tx::from(request) })
+        });
+        Some(parsed.map_err(|error| LineError { line, error }))
+    }
+}
+
+/// Whether a record stands in for one that was over the size limit, see [RecordLimit]
+fn is_oversized(record: &StringRecord) -> bool {
+    record.get(0) == Some(OVERSIZED_MARKER)
+}
+
+/// Whether a field is a non-negative integer of any size, optionally with a leading `+`
+fn is_unsigned_integer(field: &str) -> bool {
+    let digits = field.strip_prefix('+').unwrap_or(field);
+    !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// Whether a non-negative integer field is larger than the given largest value of an id
+fn is_larger_than(field: &str, max: u64) -> bool {
+    // Too many digits for a u64 is larger too
+    field.parse::<u64>().map_or(true, |value| value > max)
+}
+
+/// Renames the columns of a header row according to [ProcessOptions::column_names]
+fn rename_columns(headers: &StringRecord, column_names: &HashMap<String, String>) -> StringRecord {
+    headers.iter()
+        .map(|header| column_names.get(header).map_or(header, String::as_str))
+        .collect()
+}
+
+/// Reads the next record that isn't a line of only whitespace, see [ProcessOptions::comment]
+///
+/// The csv reader skips empty lines itself, a line of whitespace is a record of one empty field once trimmed.
+fn read_non_blank_record<R: io::Read>(reader: &mut Reader<R>, record: &mut StringRecord) -> Result<bool, csv::Error> {
+    while reader.read_record(record)? {
+        if !(record.len() == 1 && record[0].is_empty()) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn record_limit<R: io::Read>(reader: R, options: &ProcessOptions) -> RecordLimit<R> {
+    RecordLimit::new(reader, options.max_record_bytes).with_comment(options.comment)
+}
+
+fn csv_reader_builder(options: &ProcessOptions) -> ReaderBuilder {
+    let mut builder = ReaderBuilder::new();
+    builder
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .comment(options.comment);
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::Bank;
+    use crate::config::{CsvHeaders, ProcessOptions};
+    use crate::reader::TxReader;
+    use crate::report::RowError;
+    use crate::shared_types::ClientId;
+
+    fn read_tx_ids(data: &str, headers: CsvHeaders) -> Vec<u32> {
+        let options = ProcessOptions { headers, ..ProcessOptions::default() };
+        TxReader::from_reader(data.as_bytes(), &options).unwrap()
+            .map(|tx| tx.unwrap().tx.0)
+            .collect()
+    }
+
+    #[test]
+    fn test_headerless_file_keeps_first_row() {
+        let data = "deposit, 1, 7, 1.0\nwithdrawal, 1, 8, 0.5\n";
+
+        assert_eq!(read_tx_ids(data, CsvHeaders::Absent), vec![7, 8]);
+        assert_eq!(read_tx_ids(data, CsvHeaders::Detect), vec![7, 8]);
+    }
+
+    #[test]
+    fn test_detect_headers() {
+        let data = "type, client, tx, amount\ndeposit, 1, 7, 1.0\n";
+
+        assert_eq!(read_tx_ids(data, CsvHeaders::Detect), vec![7]);
+        assert_eq!(read_tx_ids(data, CsvHeaders::Present), vec![7]);
+    }
+
+
+    #[test]
+    fn test_oversized_record_reported_as_row_error() {
+        let data = format!("type, client, tx, amount\ndeposit,1,7,\"{}\"\ndeposit, 1, 8, 1.0\n", "9\n".repeat(2000));
+        let options = ProcessOptions { max_record_bytes: 1024, ..ProcessOptions::default() };
+
+        let records: Vec<_> = TxReader::from_reader(data.as_bytes(), &options).unwrap().collect();
+
+        assert_eq!(records.len(), 2);
+        let error = records[0].as_ref().unwrap_err();
+        assert_eq!((error.line, &error.error), (2, &RowError::RecordTooLarge { max: 1024 }));
+        let tx = records[1].as_ref().unwrap();
+        assert_eq!(tx.tx.0, 8);
+        assert_eq!(tx.amount_value(), 10000);
+    }
+
+    #[test]
+    fn test_oversized_line_numbers_of_later_errors() {
+        let data = format!("type, client, tx, amount\ndeposit,1,7,\"{}\"\ndeposit, x, 8, 1.0\n", "9\n".repeat(2000));
+        let options = ProcessOptions { max_record_bytes: 1024, ..ProcessOptions::default() };
+
+        let records: Vec<_> = TxReader::from_reader(data.as_bytes(), &options).unwrap().collect();
+
+        assert_eq!(records[1].as_ref().unwrap_err().line, 2003);
+    }
+
+    #[test]
+    fn test_huge_field_rejected_by_default_limit() {
+        let data = format!("type, client, tx, amount\ndeposit,1,7,{}\ndeposit, 1, 8, 1.0\n", "9".repeat(16 * 1024 * 1024));
+
+        let report = Bank::new().process_transactions_from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap();
+
+        assert_eq!((report.rows, report.applied, report.parse_errors), (2, 1, 1));
+        assert_eq!(report.row_errors[0].error, RowError::RecordTooLarge { max: 1024 });
+    }
+
+    #[test]
+    fn test_too_many_fields_rejected() {
+        let data = format!("type, client, tx, amount\ndeposit, 1, 7, 1.0{}\ndeposit, 1, 8, 1.0\n", ",".repeat(16));
+
+        let records: Vec<_> = TxReader::from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap().collect();
+
+        let error = records[0].as_ref().unwrap_err();
+        assert_eq!((error.line, &error.error), (2, &RowError::TooManyFields { max: 16 }));
+        assert_eq!(records[1].as_ref().unwrap().tx.0, 8);
+    }
+
+    #[test]
+    fn test_negative_client_and_non_numeric_tx_are_invalid_fields() {
+        let data = "type, client, tx, amount\ndeposit, -1, 1, 1.0\ndeposit, 1, abc, 1.0\ndeposit, +2, 3, 1.0\n";
+
+        let records: Vec<_> = TxReader::from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap().collect();
+
+        let error = records[0].as_ref().unwrap_err();
+        assert_eq!((error.line, &error.error), (2, &RowError::InvalidField { column: "client".to_string(), value: "-1".to_string() }));
+        assert_eq!(error.to_string(), "line 2: client \"-1\" is not a non-negative integer");
+        let error = records[1].as_ref().unwrap_err();
+        assert_eq!((error.line, &error.error), (3, &RowError::InvalidField { column: "tx".to_string(), value: "abc".to_string() }));
+        assert_eq!(error.to_string(), "line 3: tx \"abc\" is not a non-negative integer");
+        assert_eq!(records[2].as_ref().unwrap().client.0, 2);
+    }
+
+    #[test]
+    fn test_byte_order_mark_processed_like_plain_file() {
+        let data = "type, client, tx, amount\ndeposit, 1, 1, 2.0\nwithdrawal, 1, 2, 0.5\ndeposit, 2, 3, 1.0\n";
+        let process = |data: &str, headers| {
+            let bank = Bank::new();
+            let options = ProcessOptions { headers, ..ProcessOptions::default() };
+            let report = bank.process_transactions_from_reader(data.as_bytes(), &options).unwrap();
+            let mut output = Vec::new();
+            bank.write_accounts_filtered(&mut output, &[ClientId(1), ClientId(2)]).unwrap();
+            ((report.rows, report.applied, report.parse_errors), String::from_utf8(output).unwrap())
+        };
+
+        for headers in [CsvHeaders::Present, CsvHeaders::Detect] {
+            let plain = process(data, headers);
+            assert_eq!(plain.0, (3, 3, 0));
+            assert_eq!(process(&format!("\u{feff}{}", data), headers), plain);
+        }
+    }
+
+    #[test]
+    fn test_blank_and_comment_lines_skipped() {
+        let data = "# exported by the feed\n\ntype, client, tx, amount\n   \n# deposits\ndeposit, 1, 1, 2.0\n\t\r\n\n\
+            # a comment, \"with a quote\ndeposit, 2, 2, 1.0\n  \nwithdrawal, 1, 3, 0.5\ndeposit, x, 4, 1.0\n# trailing\n";
+
+        for headers in [CsvHeaders::Present, CsvHeaders::Detect] {
+            let options = ProcessOptions { headers, ..ProcessOptions::default() };
+            let bank = Bank::new();
+            let report = bank.process_transactions_from_reader(data.as_bytes(), &options).unwrap();
+
+            assert_eq!((report.rows, report.applied, report.parse_errors), (4, 3, 1));
+            assert_eq!(report.row_errors[0].line, 13);
+            let mut output = Vec::new();
+            bank.write_accounts_filtered(&mut output, &[ClientId(1), ClientId(2)]).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n2,1.0,0.0,1.0,false\n");
+        }
+    }
+
+    #[test]
+    fn test_comment_byte_configurable() {
+        let data = "; exported by the feed\ndeposit, 1, 7, 1.0\n# not a comment\n";
+
+        let options = ProcessOptions { comment: Some(b';'), headers: CsvHeaders::Absent, ..ProcessOptions::default() };
+        let records: Vec<_> = TxReader::from_reader(data.as_bytes(), &options).unwrap().collect();
+        assert_eq!(records[0].as_ref().unwrap().tx.0, 7);
+        assert_eq!(records[1].as_ref().unwrap_err().line, 3);
+
+        let options = ProcessOptions { comment: None, headers: CsvHeaders::Absent, ..ProcessOptions::default() };
+        let records: Vec<_> = TxReader::from_reader("# exported\ndeposit, 1, 7, 1.0\n".as_bytes(), &options).unwrap().collect();
+        assert_eq!(records[0].as_ref().unwrap_err().line, 1);
+        assert_eq!(records[1].as_ref().unwrap().tx.0, 7);
+    }
+
+    #[test]
+    fn test_scientific_amounts_behind_option() {
+        let data = "type, client, tx, amount\ndeposit, 1, 7, 2.5E2\n";
+
+        let records: Vec<_> = TxReader::from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap().collect();
+        assert!(matches!(&records[0].as_ref().unwrap_err().error, RowError::Malformed(message) if message.contains("scientific notation")));
+
+        let options = ProcessOptions { allow_scientific: true, ..ProcessOptions::default() };
+        let records: Vec<_> = TxReader::from_reader(data.as_bytes(), &options).unwrap().collect();
+        assert_eq!(records[0].as_ref().unwrap().amount_value(), 2_500_000);
+    }
+
+    #[test]
+    fn test_scientific_amounts_with_fraction_and_negative_exponent() {
+        let data = "type, client, tx, amount\ndeposit, 1, 7, 1.5e3\ndeposit, 1, 8, 1E-4\n";
+
+        let records: Vec<_> = TxReader::from_reader(data.as_bytes(), &ProcessOptions::default()).unwrap().collect();
+        for record in &records {
+            assert!(matches!(&record.as_ref().unwrap_err().error, RowError::Malformed(message) if message.ends_with("not allowed unless allow_scientific is set")));
+        }
+
+        let options = ProcessOptions { allow_scientific: true, ..ProcessOptions::default() };
+        let amounts: Vec<_> = TxReader::from_reader(data.as_bytes(), &options).unwrap()
+            .map(|record| record.unwrap().amount_value())
+            .collect();
+        assert_eq!(amounts, vec![15_000_000, 1]);
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::bank::Bank;
+use crate::shared_types::TxId;
+
+impl Bank {
+    /// Whether a stored transaction with the given id is recent enough for
+    /// [crate::config::BankConfig::duplicate_tx_ids] to apply, always true without a window
+    pub(crate) fn is_recent_tx_id(&self, id: TxId) -> bool {
+        self.recent_tx_ids.as_ref().is_none_or(|recent| recent.lock().unwrap().contains(id))
+    }
+
+    /// Records that a deposit or withdrawal was stored on the transaction sheet, if the bank has a window
+    ///
+    /// Called while the transaction sheet is locked, so the window follows the order transactions were stored in.
+    pub(crate) fn record_recent_tx_id(&self, id: TxId) {
+        if let Some(recent) = &self.recent_tx_ids {
+            recent.lock().unwrap().push(id);
+        }
+    }
+}
+
+/// The tx ids of the most recently stored deposits and withdrawals, oldest first,
+/// see [crate::config::BankConfig::duplicate_window]
+#[derive(Debug, Clone)]
+pub(crate) struct RecentTxIds {
+    capacity: usize,
+    order: VecDeque<TxId>,
+    /// How many times each id is in `order`, as an id reused within the window is in it more than once
+    counts: HashMap<TxId, usize>,
+}
+
+impl RecentTxIds {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { capacity, order: VecDeque::with_capacity(capacity), counts: HashMap::new() }
+    }
+
+    /// Whether the id is among the last `capacity` stored ones
+    pub(crate) fn contains(&self, id: TxId) -> bool {
+        self.counts.contains_key(&id)
+    }
+
+    /// Records that a transaction with the given id was stored, forgetting the oldest id if the window is full
+    pub(crate) fn push(&mut self, id: TxId) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                if let Some(count) = self.counts.get_mut(&oldest) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.counts.remove(&oldest);
+                    }
+                }
+            }
+        }
+        self.order.push_back(id);
+        *self.counts.entry(id).or_insert(0) += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recent::RecentTxIds;
+    use crate::shared_types::TxId;
+
+    #[test]
+    fn test_oldest_ids_leave_the_window() {
+        let mut recent = RecentTxIds::new(2);
+
+        recent.push(TxId(1));
+        recent.push(TxId(2));
+        recent.push(TxId(1));
+        assert!(recent.contains(TxId(1)));
+        assert!(recent.contains(TxId(2)));
+
+        recent.push(TxId(3));
+        assert!(recent.contains(TxId(1)));
+        assert!(!recent.contains(TxId(2)));
+
+        recent.push(TxId(4));
+        assert!(!recent.contains(TxId(1)));
+        assert!(recent.contains(TxId(3)));
+    }
+}
@@ -0,0 +1,273 @@
+use std::io;
+
+/// First field of the record that stands in for a record over the size limit
+pub(crate) const OVERSIZED_MARKER: &str = "\0record too large";
+
+/// Size of the chunks read from the inner reader
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// The UTF-8 byte order mark some Windows tools start files with
+const UTF8_BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+
+/// Wraps csv data so that no record longer than a limit reaches the csv reader
+///
+/// The csv reader buffers a whole record before returning it, so an enormous field
+/// would otherwise be read into memory in full. Records are collected here up to the
+/// limit instead. A record over the limit is dropped and replaced by a record of two
+/// fields, [OVERSIZED_MARKER] and the number of lines it spanned beyond its first, so the
+/// reader can report it and keep line numbers of later records right.
+///
+/// Records end at a newline outside of a quoted field, following the quoting rules of the
+/// csv crate: a quote only opens a quoted field at the start of the field.
+///
+/// A UTF-8 byte order mark at the start of the data is dropped, as it would otherwise end up in
+/// the first header name or field.
+///
+/// A line starting with the comment byte, see [RecordLimit::with_comment], is a record of its own
+/// up to the next newline, with no quoting, like the csv reader treats comments.
+pub(crate) struct RecordLimit<R: io::Read> {
+    inner: R,
+    max_record_bytes: usize,
+    /// The bytes of the current record, unless it is over the limit
+    record: Vec<u8>,
+    /// Bytes of complete records waiting to be read
+    ready: Vec<u8>,
+    ready_pos: usize,
+    oversized: bool,
+    /// Newlines within the current record
+    newlines: usize,
+    in_quotes: bool,
+    field_start: bool,
+    /// Whether the previous byte closed a quoted field, a quote right after it is an escaped quote
+    quote_closed: bool,
+    eof: bool,
+    comment: Option<u8>,
+    /// Whether the current record is a comment line
+    in_comment: bool,
+    /// Bytes of a leading [UTF8_BOM] matched so far, until it is found or ruled out
+    bom_matched: Option<usize>,
+    /// Bytes read from the inner reader up to the end of the current record
+    consumed: u64,
+    /// Bytes of complete records passed on, including those still waiting to be read
+    emitted: u64,
+    /// Where the bytes passed on stopped lining up with the ones read, as the bytes passed on
+    /// and read at the end of a record, see [RecordLimit::input_offset]
+    shifts: Vec<(u64, u64)>,
+}
+
+impl<R: io::Read> RecordLimit<R> {
+    pub(crate) fn new(inner: R, max_record_bytes: usize) -> Self {
+        Self {
+            inner,
+            max_record_bytes,
+            record: Vec::new(),
+            ready: Vec::new(),
+            ready_pos: 0,
+            oversized: false,
+            newlines: 0,
+            in_quotes: false,
+            field_start: true,
+            quote_closed: false,
+            eof: false,
+            comment: None,
+            in_comment: false,
+            bom_matched: Some(0),
+            consumed: 0,
+            emitted: 0,
+            shifts: Vec::new(),
+        }
+    }
+
+    /// Doesn't look for a byte order mark, for data that continues a file from the start of a record
+    pub(crate) fn continuing(self) -> Self {
+        Self { bom_matched: None, ..self }
+    }
+
+    /// Treats lines starting with the given byte as comments, which may hold quotes that don't open a quoted field
+    pub(crate) fn with_comment(self, comment: Option<u8>) -> Self {
+        Self { comment, ..self }
+    }
+
+    /// The offset into the inner reader's data of the given offset into the data read from this,
+    /// which must be at the end of a record
+    pub(crate) fn input_offset(&self, output_offset: u64) -> u64 {
+        let shift = self.shifts.partition_point(|&(emitted, _)| emitted <= output_offset);
+        match shift.checked_sub(1).map(|index| self.shifts[index]) {
+            Some((emitted, consumed)) => consumed + (output_offset - emitted),
+            None => output_offset,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.consumed += 1;
+        if let Some(matched) = self.bom_matched {
+            if byte == UTF8_BOM[matched] {
+                self.bom_matched = (matched + 1 < UTF8_BOM.len()).then_some(matched + 1);
+                return;
+            }
+            self.end_bom();
+        }
+        self.push_data(byte);
+    }
+
+    /// Adds a byte that is not part of a byte order mark to the current record
+    fn push_data(&mut self, byte: u8) {
+        if self.record.is_empty() && !self.oversized && self.comment == Some(byte) {
+            self.in_comment = true;
+        }
+        if !self.in_comment {
+            let closing_quote = byte == b'"' && self.in_quotes;
+            if closing_quote {
+                self.in_quotes = false;
+            } else if byte == b'"' && (self.field_start || self.quote_closed) {
+                self.in_quotes = true;
+            }
+            self.quote_closed = closing_quote;
+            self.field_start = !self.in_quotes && (byte == b',' || byte == b'\n');
+        }
+
+        if !self.oversized {
+            self.record.push(byte);
+            if self.record.len() > self.max_record_bytes {
+                self.oversized = true;
+                self.record = Vec::new();
+            }
+        }
+        if byte == b'\n' {
+            self.newlines += 1;
+            if !self.in_quotes {
+                self.end_record();
+            }
+        }
+    }
+
+    /// Stops looking for a byte order mark, passing on the bytes of a partial one as data
+    fn end_bom(&mut self) {
+        if let Some(matched) = self.bom_matched.take() {
+            for &byte in &UTF8_BOM[..matched] {
+                self.push_data(byte);
+            }
+        }
+    }
+
+    /// Moves the current record to the bytes ready to be read
+    fn end_record(&mut self) {
+        if self.oversized {
+            let skipped_lines = self.newlines.saturating_sub(1);
+            let marker = format!("{},{}\n", OVERSIZED_MARKER, skipped_lines);
+            self.emitted += marker.len() as u64;
+            self.ready.extend_from_slice(marker.as_bytes());
+        } else {
+            self.emitted += self.record.len() as u64;
+            self.ready.append(&mut self.record);
+        }
+        // Wrapping, as the record standing in for an oversized one may be longer than it
+        let shifted = self.shifts.last().map_or(0, |&(emitted, consumed)| consumed.wrapping_sub(emitted));
+        if self.consumed.wrapping_sub(self.emitted) != shifted {
+            self.shifts.push((self.emitted, self.consumed));
+        }
+        self.oversized = false;
+        self.newlines = 0;
+        self.in_quotes = false;
+        self.field_start = true;
+        self.quote_closed = false;
+        self.in_comment = false;
+    }
+}
+
+impl<R: io::Read> io::Read for RecordLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.ready_pos == self.ready.len() && !self.eof {
+            self.ready.clear();
+            self.ready_pos = 0;
+            let mut chunk = [0; CHUNK_SIZE];
+            let read = self.inner.read(&mut chunk)?;
+            if read == 0 {
+                self.eof = true;
+                self.end_bom();
+                if self.oversized || !self.record.is_empty() {
+                    self.end_record();
+                }
+            }
+            for &byte in &chunk[..read] {
+                self.push(byte);
+            }
+        }
+        let count = buf.len().min(self.ready.len() - self.ready_pos);
+        buf[..count].copy_from_slice(&self.ready[self.ready_pos..self.ready_pos + count]);
+        self.ready_pos += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use crate::record_limit::RecordLimit;
+
+    fn limit(data: &str, max_record_bytes: usize) -> String {
+        let mut output = String::new();
+        RecordLimit::new(data.as_bytes(), max_record_bytes).read_to_string(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_records_within_limit_unchanged() {
+        let data = "type,client,tx,amount\r\ndeposit,1,1,\"1.0\"\n\"with\nnewline\",\"a\"\"b\"\nlast";
+
+        assert_eq!(limit(data, 64), data);
+    }
+
+    #[test]
+    fn test_oversized_record_replaced() {
+        let data = format!("deposit,1,1,1.0\ndeposit,1,2,{}\ndeposit,1,3,1.0\n", "9".repeat(100));
+
+        assert_eq!(limit(&data, 32), "deposit,1,1,1.0\n\0record too large,0\ndeposit,1,3,1.0\n");
+    }
+
+    #[test]
+    fn test_oversized_quoted_record_counts_lines() {
+        let data = format!("deposit,1,1,\"{}\"\ndeposit,1,3,1.0", "9\n".repeat(50));
+
+        assert_eq!(limit(&data, 32), "\0record too large,50\ndeposit,1,3,1.0");
+    }
+
+    #[test]
+    fn test_leading_byte_order_mark_dropped() {
+        assert_eq!(limit("\u{feff}\"type\",client\n\u{feff}deposit", 64), "\"type\",client\n\u{feff}deposit");
+        assert_eq!(limit("\u{feff}", 64), "");
+        let mut output = Vec::new();
+        RecordLimit::new(&[0xEF, 0xBB, b'a', b'\n', 0xEF][..], 64).read_to_end(&mut output).unwrap();
+        assert_eq!(output, [0xEF, 0xBB, b'a', b'\n', 0xEF]);
+    }
+
+    #[test]
+    fn test_input_offsets_of_record_ends() {
+        let data = format!("\u{feff}deposit,1,1,1.0\ndeposit,1,2,{}\ndeposit,1,3,1.0\n", "9".repeat(100));
+        let mut limited = RecordLimit::new(data.as_bytes(), 32);
+        let mut output = String::new();
+        limited.read_to_string(&mut output).unwrap();
+
+        let ends: Vec<u64> = output.match_indices('\n').map(|(index, _)| index as u64 + 1).collect();
+        let offsets: Vec<u64> = ends.iter().map(|&end| limited.input_offset(end)).collect();
+        let expected: Vec<u64> = data.match_indices('\n').map(|(index, _)| index as u64 + 1).collect();
+        assert_eq!(offsets, expected);
+    }
+
+    #[test]
+    fn test_quote_in_comment_line_does_not_open_quoting() {
+        let data = format!("# note,\"unbalanced\n{}", "deposit,1,2,1.0\n".repeat(10));
+        let mut output = String::new();
+        RecordLimit::new(data.as_bytes(), 32).with_comment(Some(b'#')).read_to_string(&mut output).unwrap();
+
+        assert_eq!(output, data);
+        assert_eq!(limit(&data, 32), "\0record too large,10\n");
+    }
+
+    #[test]
+    fn test_quote_inside_field_does_not_open_quoting() {
+        let data = format!("deposit,1,1,1\"0\n{}", "deposit,1,2,1.0\n".repeat(10));
+
+        assert_eq!(limit(&data, 32), data);
+    }
+}
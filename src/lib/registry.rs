@@ -0,0 +1,155 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::bank::Bank;
+use crate::error::BankError;
+use crate::report::ProcessReport;
+
+impl Bank {
+    /// Creates an empty bank that skips csv files it has processed before, even in an earlier run
+    ///
+    /// Files are recognized by a hash of their content, so a file processed under another name is
+    /// skipped too while a changed file is processed again. The hashes of processed files are kept
+    /// in a csv at the given path along with a summary of their reports, it is created if missing.
+    pub fn with_processed_registry<P: AsRef<Path>>(path: P) -> Result<Self, BankError> {
+        let registry = ProcessedRegistry::load(path.as_ref())?;
+        Ok(Bank { registry: Some(Arc::new(Mutex::new(registry))), ..Bank::new() })
+    }
+}
+
+/// A row of the registry file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RegistryEntry {
+    /// Hex encoded SHA-256 hash of the file's content
+    hash: String,
+    /// Path the file was processed from, for readers of the registry
+    path: String,
+    rows: usize,
+    applied: usize,
+    rejected: usize,
+    parse_errors: usize,
+    precision_errors: usize,
+}
+
+/// The files a bank has processed, by the hash of their content
+#[derive(Debug)]
+pub(crate) struct ProcessedRegistry {
+    path: PathBuf,
+    processed: HashMap<String, RegistryEntry>,
+    /// Hashes of files being processed right now, so a copy processed at the same time is skipped
+    in_progress: HashSet<String>,
+}
+
+impl ProcessedRegistry {
+    fn load(path: &Path) -> Result<Self, BankError> {
+        let mut processed = HashMap::new();
+        match File::open(path) {
+            Ok(file) => {
+                for entry in csv::Reader::from_reader(file).deserialize() {
+                    let entry: RegistryEntry = entry?;
+                    processed.insert(entry.hash.clone(), entry);
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Self { path: path.to_path_buf(), processed, in_progress: HashSet::new() })
+    }
+
+    /// Claims the file with the given hash for processing,
+    /// returning false if it was processed before or is being processed
+    pub(crate) fn claim(&mut self, hash: &str) -> bool {
+        !self.processed.contains_key(hash) && self.in_progress.insert(hash.to_string())
+    }
+
+    /// Gives up a claim after processing the file failed, so it is processed again next time
+    pub(crate) fn release(&mut self, hash: &str) {
+        self.in_progress.remove(hash);
+    }
+
+    /// Records a claimed file as processed and appends it to the registry file
+    pub(crate) fn record(&mut self, hash: String, path: &str, report: &ProcessReport) -> Result<(), BankError> {
+        self.in_progress.remove(&hash);
+        let entry = RegistryEntry {
+            hash: hash.clone(),
+            path: path.to_string(),
+            rows: report.rows,
+            applied: report.applied,
+            rejected: report.rejected(),
+            parse_errors: report.parse_errors,
+            precision_errors: report.precision_errors,
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut writer = csv::WriterBuilder::new().has_headers(is_new).from_writer(file);
+        writer.serialize(&entry)?;
+        writer.flush()?;
+        self.processed.insert(hash, entry);
+        Ok(())
+    }
+}
+
+/// Hex encoded SHA-256 hash of everything the reader reads, hashed as it is read
+pub(crate) fn hex_sha256<R: io::Read>(mut reader: R) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::bank::Bank;
+    use crate::shared_types::ClientId;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bank_lib_test_registry_{}.csv", name))
+    }
+
+    #[test]
+    fn test_same_file_processed_once() {
+        let registry = temp_path("same_file");
+        let _ = std::fs::remove_file(&registry);
+        let fixture = "tests/fixtures/dispute_resolve.csv";
+        let single_run = Bank::new();
+        single_run.process_transactions_from_csv_path_blocking(fixture).unwrap();
+
+        let bank = Bank::with_processed_registry(&registry).unwrap();
+        bank.process_transactions_from_csv_path_blocking(fixture).unwrap();
+        let report = bank.process_transactions_from_csv_path_blocking(fixture).unwrap();
+        assert_eq!(report.rows, 0);
+        assert_eq!(bank.account_snapshot(ClientId(1)), single_run.account_snapshot(ClientId(1)));
+
+        // A later run loads the registry from the file
+        let bank = Bank::with_processed_registry(&registry).unwrap();
+        bank.process_transactions_from_csv_path_blocking(fixture).unwrap();
+        assert!(bank.account_snapshot(ClientId(1)).is_none());
+    }
+
+    #[test]
+    fn test_modified_file_processed_again() {
+        let registry = temp_path("modified_file");
+        let _ = std::fs::remove_file(&registry);
+        let input = temp_path("modified_file_input");
+        let input_path = input.to_str().unwrap();
+        let bank = Bank::with_processed_registry(&registry).unwrap();
+
+        std::fs::write(&input, "type, client, tx, amount\ndeposit, 1, 1, 1.0\n").unwrap();
+        bank.process_transactions_from_csv_path_blocking(input_path).unwrap();
+        std::fs::write(&input, "type, client, tx, amount\ndeposit, 1, 2, 2.0\n").unwrap();
+        let report = bank.process_transactions_from_csv_path_blocking(input_path).unwrap();
+
+        assert_eq!(report.applied, 1);
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 30000);
+        let registry = std::fs::read_to_string(&registry).unwrap();
+        assert_eq!(registry.lines().count(), 3);
+        assert!(registry.starts_with("hash,path,rows,applied,rejected,parse_errors,precision_errors\n"));
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::bank::AccountSnapshot;
+use crate::shared_types::{ClientId, TxId};
+use crate::transaction::TxType;
+
+/// How many row errors a report keeps the details of, later ones are only counted
+pub const MAX_REPORTED_ROW_ERRORS: usize = 100;
+
+/// Why a transaction was not applied to the bank
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RejectionReason {
+    /// A deposit or withdrawal of zero, see [crate::config::ZeroAmountPolicy]
+    ZeroAmount,
+    /// A deposit or withdrawal without an amount
+    MissingAmount,
+    /// A deposit or withdrawal of a negative amount, which would move funds the other way
+    /// around the deposit limits and withdrawal fees
+    NegativeAmount,
+    /// The client's account is locked
+    AccountLocked,
+    /// A withdrawal for more than the available balance
+    InsufficientFunds,
+    /// A dispute, resolve, chargeback or reversal referencing a transaction not on the transaction sheet
+    UnknownTx,
+    /// A resolve or chargeback referencing a transaction that is not disputed
+    NotDisputed,
+    /// A dispute, resolve, chargeback or reversal referencing a transaction of another client
+    ClientMismatch,
+    /// A resolve or chargeback for more than the account's held balance, or a dispute of a negative
+    /// amount, which would take the held balance below zero. The bank's state is inconsistent for the first two.
+    /// The account is left as it was, unlocked.
+    InsufficientHeld,
+    /// A balance would go beyond the range of amounts the bank can store
+    Overflow,
+    /// A transaction for a client without an account, see [crate::config::BankConfig::strict_accounts]
+    UnknownClient,
+    /// A chargeback with a negative amount or one larger than the amount of the disputed transaction
+    InvalidChargebackAmount,
+    /// A deposit or withdrawal reusing the tx id of a stored transaction, see [crate::config::DuplicateTxIdPolicy]
+    DuplicateTxId,
+    /// A dispute or resolve with an amount other than the one of the transaction it references,
+    /// see [crate::config::BankConfig::verify_meta_amounts]
+    AmountMismatch,
+    /// A reversal of a withdrawal or of a deposit that is disputed
+    NotReversible,
+    /// A dispute or reversal of a deposit that was already reversed
+    Reversed,
+    /// A dispute, resolve, chargeback or reversal of a withdrawal that was rejected for insufficient
+    /// funds, which is stored but never moved any funds
+    FailedTx,
+    /// A dispute of a transaction stored too long ago, see [crate::config::BankConfig::dispute_window]
+    DisputeWindowExpired,
+    /// A transaction of a client whose id is reserved, see [crate::config::BankConfig::reserved_clients]
+    ReservedClient,
+    /// A deposit larger than [crate::config::Limits::max_single_deposit]
+    ExceedsSingleLimit,
+    /// A deposit that would take the client's deposits over [crate::config::Limits::max_cumulative_deposit]
+    ExceedsCumulativeLimit,
+    /// A deposit that would take the available balance over [crate::config::Limits::max_balance]
+    ExceedsMaxBalance,
+    /// Any transaction once the bank failed to append to its journal, see [crate::bank::Bank::is_poisoned]
+    JournalFailed,
+}
+
+/// Why a row could not be parsed into a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RowError {
+    /// The client or tx column holds something other than a non-negative integer, e.g. `-1` or `abc`
+    InvalidField { column: String, value: String },
+    /// The client column holds a number larger than the largest client id
+    ClientIdOutOfRange { value: String },
+    /// The tx column holds a number larger than the largest tx id
+    TxIdOutOfRange { value: String },
+    /// The amount has more decimal places than the bank keeps, see [crate::config::ExcessPrecision]
+    ExcessPrecision { value: String, decimal_places: u32 },
+    /// The record is longer than [crate::config::ProcessOptions::max_record_bytes]
+    RecordTooLarge { max: usize },
+    /// The record has more fields than [crate::config::ProcessOptions::max_fields]
+    TooManyFields { max: usize },
+    /// A resolve or chargeback of a transaction that is not disputed or not known,
+    /// see [crate::config::BankConfig::strict_disputes]
+    NotDisputed { type_: TxType, tx: TxId },
+    /// A deposit or withdrawal with the tx id of an earlier one, found by [crate::bank::Bank::validate_csv_path]
+    DuplicateTx { tx: TxId },
+    /// A dispute, resolve or chargeback of a transaction that is nowhere to be found,
+    /// found by [crate::bank::Bank::validate_csv_path]
+    UnknownTx { type_: TxType, tx: TxId },
+    /// A deposit or withdrawal without an amount
+    MissingAmount { type_: TxType },
+    /// The row is not valid csv or doesn't describe a transaction
+    Malformed(String),
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RowError::InvalidField { column, value } => write!(f, "{} {:?} is not a non-negative integer", column, value),
+            RowError::ClientIdOutOfRange { .. } => write!(f, "client id out of range (max {})", u16::MAX),
+            RowError::TxIdOutOfRange { .. } => write!(f, "tx id out of range (max {})", u32::MAX),
+            RowError::RecordTooLarge { max } => write!(f, "record larger than {} bytes", max),
+            RowError::TooManyFields { max } => write!(f, "record with more than {} fields", max),
+            RowError::NotDisputed { type_, tx } => write!(f, "{} of tx {} which is not disputed", type_.as_str(), tx),
+            RowError::DuplicateTx { tx } => write!(f, "tx {} is already used by another transaction", tx),
+            RowError::UnknownTx { type_, tx } => write!(f, "{} of tx {} which is neither in the file nor on the transaction sheet", type_.as_str(), tx),
+            RowError::ExcessPrecision { value, decimal_places } => write!(f, "amount {} has more than {} decimal places", value, decimal_places),
+            RowError::MissingAmount { type_ } => write!(f, "{} without an amount", type_.as_str()),
+            RowError::Malformed(message) => f.write_str(message),
+        }
+    }
+}
+
+/// A row error along with the line of the input it occurred on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineError {
+    pub line: u64,
+    pub error: RowError,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+/// Problems found checking a csv without applying it, see [crate::bank::Bank::validate_csv_path]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Number of rows read, including ones that failed to parse
+    pub rows: usize,
+    /// Every problem found, ordered by line
+    pub problems: Vec<LineError>,
+}
+
+impl ValidationReport {
+    /// Whether no problems were found
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Outcomes of processing a batch of transactions, see [crate::bank::Bank::process_batch]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BatchReport {
+    /// The outcome of each transaction, in the order of the batch
+    pub outcomes: Vec<Result<(), RejectionReason>>,
+}
+
+impl BatchReport {
+    /// Number of transactions applied to the bank
+    pub fn applied(&self) -> usize {
+        self.outcomes.iter().filter(|outcome| outcome.is_ok()).count()
+    }
+
+    /// Number of rejected transactions
+    pub fn rejected(&self) -> usize {
+        self.outcomes.len() - self.applied()
+    }
+}
+
+/// What a batch of transactions would do to the bank, see [crate::bank::Bank::simulate_batch]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SimulationReport {
+    /// The outcome each transaction would have, in the order of the batch
+    pub outcomes: Vec<Result<(), RejectionReason>>,
+    /// The accounts touched by the batch as they would be after it, sorted by client
+    pub accounts: Vec<AccountSnapshot>,
+    /// Clients whose account the batch would lock
+    pub newly_locked: Vec<ClientId>,
+    /// Clients whose available balance in some currency the batch would take below zero
+    pub newly_negative: Vec<ClientId>,
+}
+
+impl SimulationReport {
+    /// Number of transactions that would be applied
+    pub fn applied(&self) -> usize {
+        self.outcomes.iter().filter(|outcome| outcome.is_ok()).count()
+    }
+
+    /// Number of transactions that would be rejected
+    pub fn rejected(&self) -> usize {
+        self.outcomes.len() - self.applied()
+    }
+}
+
+/// Where the time of a bank went while profiling was turned on, see [crate::bank::Bank::set_profiling]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ProfileReport {
+    /// Number of transactions processed
+    pub records: u64,
+    /// How long profiling has been turned on, or was until it was turned off
+    pub elapsed: Duration,
+    /// Waiting and holding times of each lock of the bank, in the order they are locked in
+    pub locks: Vec<LockProfile>,
+}
+
+impl ProfileReport {
+    /// Transactions processed per second of [ProfileReport::elapsed]
+    pub fn records_per_second(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            seconds if seconds > 0.0 => self.records as f64 / seconds,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Time spent on one lock of a bank, see [ProfileReport]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LockProfile {
+    /// The part of the bank behind the lock, e.g. `accounts`
+    pub lock: &'static str,
+    /// Number of times the lock was taken
+    pub acquisitions: u64,
+    /// Total time spent waiting to take the lock
+    pub wait: Duration,
+    /// Total time the lock was held for
+    pub hold: Duration,
+}
+
+/// Summary of writing accounts out
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OutputReport {
+    /// Number of accounts written
+    pub written: usize,
+    /// Requested clients that have no account, in the order they were requested
+    pub unknown_clients: Vec<ClientId>,
+}
+
+/// Summary of merging one bank into another, see [crate::bank::Bank::merge]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Number of accounts merged, whether they were added or folded into an existing account
+    pub accounts: usize,
+    /// Number of deposits and withdrawals added to the transaction sheet
+    pub transactions: usize,
+    /// Tx ids on both transaction sheets, in ascending order. The merged bank keeps its own transaction for these.
+    pub conflicts: Vec<TxId>,
+}
+
+/// Summary of processing a source of transactions
+///
+/// Rows that could not be parsed are counted separately from rows that parsed
+/// but were rejected by the bank.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProcessReport {
+    /// Number of rows read, including ones that failed to parse
+    pub rows: usize,
+    /// Number of bytes of csv data read, zero for transactions that didn't come from csv data
+    pub bytes: u64,
+    /// Number of transactions applied to the bank
+    pub applied: usize,
+    /// Number of rows that could not be parsed into a transaction,
+    /// other than the ones counted in `precision_errors`
+    pub parse_errors: usize,
+    /// Number of rows skipped because their amount had more decimal places than the bank keeps
+    pub precision_errors: usize,
+    /// Why rows could not be parsed, for the first [MAX_REPORTED_ROW_ERRORS] of them
+    pub row_errors: Vec<LineError>,
+    /// Number of rejected transactions by reason
+    pub rejections: BTreeMap<RejectionReason, usize>,
+}
+
+impl ProcessReport {
+    /// Total number of rejected transactions
+    pub fn rejected(&self) -> usize {
+        self.rejections.values().sum()
+    }
+
+    /// Number of transactions rejected for the given reason
+    pub fn rejected_for(&self, reason: RejectionReason) -> usize {
+        self.rejections.get(&reason).copied().unwrap_or(0)
+    }
+
+    /// Adds the counts of another report to this one
+    pub fn merge(&mut self, other: ProcessReport) {
+        self.rows += other.rows;
+        self.bytes += other.bytes;
+        self.applied += other.applied;
+        self.parse_errors += other.parse_errors;
+        self.precision_errors += other.precision_errors;
+        let room = MAX_REPORTED_ROW_ERRORS.saturating_sub(self.row_errors.len());
+        self.row_errors.extend(other.row_errors.into_iter().take(room));
+        for (reason, count) in other.rejections {
+            *self.rejections.entry(reason).or_insert(0) += count;
+        }
+    }
+
+    /// Records a row that could not be parsed
+    pub(crate) fn record_row_error(&mut self, error: LineError) {
+        match error.error {
+            RowError::ExcessPrecision { .. } => self.precision_errors += 1,
+            _ => self.parse_errors += 1,
+        }
+        if self.row_errors.len() < MAX_REPORTED_ROW_ERRORS {
+            self.row_errors.push(error);
+        }
+    }
+
+    /// Records the outcome of processing a single transaction
+    pub(crate) fn record(&mut self, outcome: Result<(), RejectionReason>) {
+        match outcome {
+            Ok(()) => self.applied += 1,
+            Err(reason) => *self.rejections.entry(reason).or_insert(0) += 1,
+        }
+    }
+}
+
+/// How far processing a source of transactions got, see [crate::config::ProcessOptions::on_progress]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgressUpdate {
+    /// Number of rows read so far, including ones that failed to parse
+    pub rows: usize,
+    /// Number of bytes of csv data read so far
+    pub bytes: u64,
+    /// Time since processing the source started
+    pub elapsed: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::Bank;
+    use crate::config::{ExcessPrecision, ProcessOptions};
+    use crate::report::{RejectionReason, RowError};
+
+    #[test]
+    fn test_report_zero_amount_distinct_from_parse_error() {
+        let path = std::env::temp_dir().join("bank_lib_test_report_zero_amount.csv");
+        std::fs::write(&path, "type, client, tx, amount\ndeposit, 1, 1, 0.0\ndeposit, x, 3, 1.0\ndeposit, 1, 2, 1.0\n").unwrap();
+
+        let report = Bank::new().process_transactions_from_csv_path_blocking(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(report.rows, 3);
+        assert_eq!(report.applied, 1);
+        assert_eq!(report.parse_errors, 1);
+        assert_eq!(report.row_errors[0].line, 3);
+        assert!(matches!(&report.row_errors[0].error, RowError::InvalidField { column, .. } if column == "client"));
+        assert_eq!(report.rejected_for(RejectionReason::ZeroAmount), 1);
+    }
+
+    #[test]
+    fn test_report_client_id_out_of_range() {
+        let path = std::env::temp_dir().join("bank_lib_test_report_client_out_of_range.csv");
+        std::fs::write(&path, "type, client, tx, amount\ndeposit, 70000, 1, 1.0\n").unwrap();
+
+        let report = Bank::new().process_transactions_from_csv_path_blocking(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(report.parse_errors, 1);
+        assert_eq!(report.row_errors[0].error, RowError::ClientIdOutOfRange { value: "70000".to_string() });
+        assert_eq!(report.row_errors[0].to_string(), "line 2: client id out of range (max 65535)");
+    }
+
+    #[test]
+    fn test_report_ids_out_of_range_with_line_numbers() {
+        let path = std::env::temp_dir().join("bank_lib_test_report_ids_out_of_range.csv");
+        std::fs::write(&path, "type, client, tx, amount\n\
+            deposit, 1, 1, 1.0\n\
+            deposit, 70000, 2, 1.0\n\
+            deposit, 1, 4294967296, 1.0\n\
+            deposit, -1, 3, 1.0\n\
+            deposit, 1, 99999999999999999999999, 1.0\n\
+            deposit, 1, 4294967295, 1.0\n").unwrap();
+
+        let report = Bank::new().process_transactions_from_csv_path_blocking(path.to_str().unwrap()).unwrap();
+
+        assert_eq!((report.applied, report.parse_errors), (2, 4));
+        let errors: Vec<String> = report.row_errors.iter().map(|error| error.to_string()).collect();
+        assert_eq!(errors, [
+            "line 3: client id out of range (max 65535)",
+            "line 4: tx id out of range (max 4294967295)",
+            "line 5: client \"-1\" is not a non-negative integer",
+            "line 6: tx id out of range (max 4294967295)",
+        ]);
+        assert_eq!(report.row_errors[1].error, RowError::TxIdOutOfRange { value: "4294967296".to_string() });
+    }
+
+    #[test]
+    fn test_report_counts_excess_precision_separately() {
+        let path = std::env::temp_dir().join("bank_lib_test_report_excess_precision.csv");
+        std::fs::write(&path, "type, client, tx, amount\ndeposit, 1, 1, 1.00005\ndeposit, 1, 2, 1.0\ndeposit, x, 3, 1.0\n").unwrap();
+        let options = ProcessOptions { excess_precision: ExcessPrecision::Reject, ..ProcessOptions::default() };
+
+        let report = Bank::new().process_transactions_from_csv_path_with_options_blocking(path.to_str().unwrap(), &options).unwrap();
+
+        assert_eq!(report.applied, 1);
+        assert_eq!(report.precision_errors, 1);
+        assert_eq!(report.parse_errors, 1);
+        assert_eq!(report.row_errors[0].error, RowError::ExcessPrecision { value: "1.00005".to_string(), decimal_places: 4 });
+    }
+}
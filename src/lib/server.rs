@@ -0,0 +1,184 @@
+//! An HTTP API in front of a [Bank], enabled with the `server` feature
+//!
+//! - `POST /transactions` processes a csv body, or a JSON lines body if the content type
+//!   mentions json, and responds with a summary of the rows. The body is processed as it
+//!   arrives, on a blocking thread of the runtime
+//! - `GET /accounts/{client}` responds with the client's account as JSON
+//! - `GET /accounts` responds with all accounts in csv format
+
+use std::io::{self, BufRead, BufReader};
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio_util::io::{StreamReader, SyncIoBridge};
+
+use crate::bank::Bank;
+use crate::config::{ExcessPrecision, ProcessOptions};
+use crate::error::BankError;
+use crate::shared_types::{Amount, ClientId, CurrencyCode, TxId};
+use crate::transaction::{TxRequest, TxType};
+
+/// Serves the API for the given bank on the listener until the server fails
+pub async fn serve(bank: Bank, listener: TcpListener) -> io::Result<()> {
+    axum::serve(listener, router(bank)).await
+}
+
+/// The routes of the API, for embedding them in another server
+pub fn router(bank: Bank) -> Router {
+    Router::new()
+        .route("/transactions", post(post_transactions))
+        .route("/accounts", get(get_accounts))
+        .route("/accounts/:client", get(get_account))
+        .with_state(Arc::new(bank))
+}
+
+async fn post_transactions(State(bank): State<Arc<Bank>>, headers: HeaderMap, body: Body) -> Response {
+    let is_json = headers.get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.contains("json"));
+    let body = SyncIoBridge::new(StreamReader::new(body.into_data_stream().map_err(io::Error::other)));
+    let processing = tokio::task::spawn_blocking(move || {
+        if is_json {
+            return process_json_lines(&bank, BufReader::new(body));
+        }
+        bank.process_transactions_from_reader(body, &ProcessOptions::default()).map(|report| json!({
+            "rows": report.rows,
+            "applied": report.applied,
+            "rejected": report.rejected(),
+            "parse_errors": report.parse_errors + report.precision_errors,
+            "errors": report.row_errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        }))
+    });
+    match processing.await {
+        Ok(Ok(summary)) => Json(summary).into_response(),
+        Ok(Err(e)) => error_response(&e),
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// A transaction in a JSON lines body, with the amount as a string or a number
+#[derive(Deserialize)]
+struct JsonTx {
+    #[serde(rename = "type")]
+    type_: String,
+    client: u16,
+    tx: u32,
+    #[serde(default)]
+    amount: Option<Value>,
+    #[serde(default)]
+    currency: Option<String>,
+}
+
+impl JsonTx {
+    /// The request for this transaction, with its amount parsed exactly with the given decimal places
+    fn into_request(self, decimal_places: u32) -> Result<TxRequest, String> {
+        let parse = |amount: &str| Amount::parse_with(amount, decimal_places, ExcessPrecision::Reject, false).map_err(|e| e.to_string());
+        let amount = match self.amount {
+            None | Some(Value::Null) => None,
+            Some(Value::String(amount)) => Some(parse(&amount)?),
+            Some(Value::Number(amount)) => Some(parse(&amount.to_string())?),
+            Some(other) => return Err(format!("invalid amount: {}", other)),
+        };
+        Ok(TxRequest {
+            type_: self.type_.parse::<TxType>()?,
+            client: ClientId(self.client),
+            tx: TxId(self.tx),
+            amount: amount.map(|amount| amount.scaled()),
+            currency: self.currency.map(|currency| currency.parse::<CurrencyCode>()).transpose()?,
+        })
+    }
+}
+
+/// Processes each non-empty line of the body as a JSON transaction, in order
+///
+/// Fails if the body can't be read, or if the bank is poisoned, see [Bank::is_poisoned].
+fn process_json_lines<R: BufRead>(bank: &Bank, body: R) -> Result<Value, BankError> {
+    let mut requests = Vec::new();
+    let mut errors = Vec::new();
+    let mut rows = 0;
+    for (index, line) in body.split(b'\n').enumerate() {
+        let line = line?;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        rows += 1;
+        let request = serde_json::from_slice::<JsonTx>(&line)
+            .map_err(|e| e.to_string())
+            .and_then(|tx| tx.into_request(bank.config.decimal_places));
+        match request {
+            Ok(request) => requests.push(request),
+            Err(e) => errors.push(format!("line {}: {}", index + 1, e)),
+        }
+    }
+    let report = bank.process_batch(requests);
+    bank.check_journal()?;
+    Ok(json!({
+        "rows": rows,
+        "applied": report.applied(),
+        "rejected": report.rejected(),
+        "parse_errors": errors.len(),
+        "errors": errors,
+    }))
+}
+
+async fn get_account(State(bank): State<Arc<Bank>>, Path(client): Path<u16>) -> Response {
+    let Some(snapshot) = bank.account_snapshot(ClientId(client)) else {
+        return (StatusCode::NOT_FOUND, format!("no account for client {}", client)).into_response();
+    };
+    let decimal = |value| Amount::from_scaled(value).to_decimal_string_with(bank.config.decimal_places);
+    Json(json!({
+        "client": snapshot.client.0,
+        "available": decimal(snapshot.available),
+        "held": decimal(snapshot.held),
+        "total": decimal(snapshot.total),
+        "locked": snapshot.locked,
+    })).into_response()
+}
+
+async fn get_accounts(State(bank): State<Arc<Bank>>) -> Response {
+    let mut output = Vec::new();
+    match bank.write_accounts_to(&mut output) {
+        Ok(()) => ([(header::CONTENT_TYPE, "text/csv")], output).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Maps errors of processing a request body to a response: malformed csv is the client's fault,
+/// while failing to read the body or to journal its transactions is the server's
+fn error_response(error: &BankError) -> Response {
+    let status = match error {
+        BankError::Csv(_) | BankError::OpeningBalances(_) => StatusCode::BAD_REQUEST,
+        BankError::Io(_) | BankError::Journal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        #[cfg(feature = "parquet")]
+        BankError::Parquet(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, error.to_string()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use axum::http::StatusCode;
+
+    use crate::error::BankError;
+
+    use super::error_response;
+
+    #[test]
+    fn test_error_status_by_cause() {
+        let csv_error = csv::Reader::from_reader("a,b\n1\n".as_bytes()).records().find_map(Result::err).unwrap();
+        assert_eq!(error_response(&BankError::Csv(csv_error)).status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error_response(&BankError::Io(io::Error::other("disk"))).status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error_response(&BankError::Journal(io::Error::other("disk"))).status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -1,14 +1,448 @@
-pub(crate) type ClientId = u16;
-pub(crate) type TxId = u32;
+use serde::{Deserialize, Serialize};
+use crate::config::ExcessPrecision;
+
+/// The globally unique id of a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ClientId(pub u16);
+
+/// The globally unique id of a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TxId(pub u32);
+
+/// The input file a transaction was read from, an index into the paths registered with a bank,
+/// see [crate::bank::Bank::source_path]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SourceId(pub(crate) u32);
+
+impl std::fmt::Display for ClientId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::fmt::Display for TxId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
 pub(crate) type AmountValue = i64;
 pub(crate) type RawAmountValue = f64;
 
-#[derive(Debug)]
-pub(crate) struct Amount {
+/// An ISO 4217 currency code such as `USD` or `EUR`
+///
+/// Codes are stored uppercase, so `eur` and `EUR` are the same currency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CurrencyCode([u8; 3]);
+
+impl CurrencyCode {
+    /// The currency used for transactions that don't specify one, unless configured otherwise
+    pub const USD: CurrencyCode = CurrencyCode(*b"USD");
+
+    pub fn as_str(&self) -> &str {
+        // Only constructed from ASCII letters
+        std::str::from_utf8(&self.0).unwrap()
+    }
+}
+
+impl std::str::FromStr for CurrencyCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s.as_bytes();
+        if bytes.len() != 3 || !bytes.iter().all(u8::is_ascii_alphabetic) {
+            return Err(format!("Invalid currency code: {:?}", s));
+        }
+        Ok(CurrencyCode([
+            bytes[0].to_ascii_uppercase(),
+            bytes[1].to_ascii_uppercase(),
+            bytes[2].to_ascii_uppercase(),
+        ]))
+    }
+}
+
+impl std::fmt::Display for CurrencyCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An amount of money
+///
+/// Stored as an integer scaled by `10^decimal_places`, see [Amount::scale], where `decimal_places` is
+/// [crate::config::BankConfig::decimal_places], four by default, so `1.5` is `15000`. This is the bank's
+/// internal integer representation, which every amount the API takes or returns as an integer is in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Amount {
     pub(crate) value: AmountValue,
 }
 impl Amount {
+    /// Number of decimal places amounts have unless a bank is configured otherwise
+    pub const DECIMAL_PLACES: u32 = 4;
+
+    /// Most decimal places a bank can keep, leaving amounts of up to nine billion
+    pub const MAX_DECIMAL_PLACES: u32 = 9;
+
     pub(crate) fn new() -> Self {
         Self { value: 0 }
     }
-}
\ No newline at end of file
+
+    /// The factor amounts with the given number of decimal places are scaled by, e.g. `10000` for four
+    pub fn scale(decimal_places: u32) -> i64 {
+        10_i64.pow(decimal_places)
+    }
+
+    /// An amount from its value in the internal integer representation, e.g. `15000` for `1.5` with four decimal places
+    pub fn from_scaled(value: i64) -> Self {
+        Self { value }
+    }
+
+    /// The value of this amount in the internal integer representation, e.g. `15000` for `1.5` with four decimal places
+    pub fn scaled(&self) -> i64 {
+        self.value
+    }
+
+    /// An amount from a floating point number, rounded to four decimal places
+    ///
+    /// Returns None if the number is not finite or too large.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        let scaled = (value * Amount::scale(Amount::DECIMAL_PLACES) as RawAmountValue).round();
+        if !scaled.is_finite() || scaled.abs() >= AmountValue::MAX as RawAmountValue {
+            return None;
+        }
+        Some(Self { value: scaled as AmountValue })
+    }
+
+    /// The amount with all four decimal places, e.g. `1.5000`
+    pub fn as_decimal_string(&self) -> String {
+        self.to_string()
+    }
+
+    /// The amount without trailing zeros but at least one decimal place, e.g. `1.5` or `4.0`, as written in outputs
+    ///
+    /// Built from the integer value like [Amount::as_decimal_string], so it is exact at any magnitude,
+    /// never in scientific notation and never `-0.0`.
+    pub(crate) fn to_short_decimal(self) -> String {
+        self.to_short_decimal_with(Amount::DECIMAL_PLACES)
+    }
+
+    /// Like [Amount::to_short_decimal] for an amount with the given number of decimal places
+    pub(crate) fn to_short_decimal_with(self, decimal_places: u32) -> String {
+        let mut decimal = self.to_decimal_string_with(decimal_places);
+        match decimal.find('.') {
+            Some(point) => {
+                let trimmed_len = decimal.trim_end_matches('0').len();
+                decimal.truncate(trimmed_len.max(point + 2));
+            },
+            None => decimal.push_str(".0"),
+        }
+        decimal
+    }
+
+    /// An amount with `decimal_places` written with exactly `output_places` decimal places, e.g. `1.50`
+    /// for two or `2` for none, rounded half away from zero if it has more
+    pub(crate) fn to_fixed_decimal(self, decimal_places: u32, output_places: u32) -> String {
+        let value = self.value as i128;
+        let value = if output_places >= decimal_places {
+            value * 10_i128.pow(output_places - decimal_places)
+        } else {
+            let divisor = 10_i128.pow(decimal_places - output_places);
+            let (quotient, remainder) = (value / divisor, value % divisor);
+            if remainder.abs() * 2 >= divisor { quotient + value.signum() } else { quotient }
+        };
+        let sign = if value < 0 { "-" } else { "" };
+        let (magnitude, scale) = (value.unsigned_abs(), 10_u128.pow(output_places));
+        if output_places == 0 {
+            return format!("{}{}", sign, magnitude);
+        }
+        format!("{}{}.{:0width$}", sign, magnitude / scale, magnitude % scale, width = output_places as usize)
+    }
+
+    /// Like [Amount::as_decimal_string] for an amount with the given number of decimal places, e.g. `1.50` for two
+    pub(crate) fn to_decimal_string_with(self, decimal_places: u32) -> String {
+        let sign = if self.value < 0 { "-" } else { "" };
+        let magnitude = self.value.unsigned_abs();
+        let scale = Amount::scale(decimal_places) as u64;
+        if decimal_places == 0 {
+            return format!("{}{}", sign, magnitude);
+        }
+        format!("{}{}.{:0width$}", sign, magnitude / scale, magnitude % scale, width = decimal_places as usize)
+    }
+
+    /// Parses a decimal amount such as `-12.5` into the internal representation
+    /// without going through floating point, so no precision is lost
+    ///
+    /// If `allow_scientific` is set, amounts such as `2.5E2` are accepted too. The exponent
+    /// is applied to the digits as written, so the decimal places limit applies to the result.
+    pub(crate) fn parse(s: &str, excess_precision: ExcessPrecision, allow_scientific: bool) -> Result<Self, AmountParseError> {
+        Amount::parse_with(s, Amount::DECIMAL_PLACES, excess_precision, allow_scientific)
+    }
+
+    /// Like [Amount::parse] for an amount with the given number of decimal places
+    pub(crate) fn parse_with(s: &str, decimal_places: u32, excess_precision: ExcessPrecision, allow_scientific: bool) -> Result<Self, AmountParseError> {
+        let (negative, unsigned) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+            Some(_) if !allow_scientific => return Err(AmountParseError::Scientific),
+            Some(index) => (&unsigned[..index], Some(&unsigned[index + 1..])),
+            None => (unsigned, None),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if whole.is_empty() && fraction.is_empty()
+            || !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+            return Err(AmountParseError::Invalid);
+        }
+        let value = match exponent {
+            None => parse_decimal_digits(whole, fraction, decimal_places, excess_precision)?,
+            Some(exponent) => {
+                let exponent: i32 = exponent.parse().map_err(|_| AmountParseError::Invalid)?;
+                if exponent.unsigned_abs() > MAX_EXPONENT {
+                    return Err(AmountParseError::Overflow);
+                }
+                let digits = format!("{}{}", whole, fraction);
+                let point = whole.len() as i32 + exponent;
+                let (whole, fraction) = if point <= 0 {
+                    (String::new(), format!("{}{}", "0".repeat(point.unsigned_abs() as usize), digits))
+                } else if point as usize >= digits.len() {
+                    (format!("{}{}", digits, "0".repeat(point as usize - digits.len())), String::new())
+                } else {
+                    let (whole, fraction) = digits.split_at(point as usize);
+                    (whole.to_string(), fraction.to_string())
+                };
+                parse_decimal_digits(&whole, &fraction, decimal_places, excess_precision)?
+            },
+        };
+        Ok(Self { value: if negative { -value } else { value } })
+    }
+}
+
+/// Turns the digits before and after the decimal point of an amount into its internal value
+fn parse_decimal_digits(whole: &str, fraction: &str, decimal_places: u32, excess_precision: ExcessPrecision) -> Result<AmountValue, AmountParseError> {
+    let (kept, excess) = fraction.split_at(fraction.len().min(decimal_places as usize));
+    let mut value: AmountValue = 0;
+    for digit in whole.bytes().chain(kept.bytes()) {
+        value = value.checked_mul(10)
+            .and_then(|value| value.checked_add((digit - b'0') as AmountValue))
+            .ok_or(AmountParseError::Overflow)?;
+    }
+    value = value.checked_mul(Amount::scale(decimal_places - kept.len() as u32))
+        .ok_or(AmountParseError::Overflow)?;
+    if excess.bytes().any(|b| b != b'0') {
+        match excess_precision {
+            ExcessPrecision::Reject => return Err(AmountParseError::ExcessPrecision { decimal_places }),
+            ExcessPrecision::RoundHalfUp if excess.as_bytes()[0] >= b'5' => {
+                value = value.checked_add(1).ok_or(AmountParseError::Overflow)?;
+            },
+            ExcessPrecision::RoundHalfUp | ExcessPrecision::Truncate => (),
+        }
+    }
+    Ok(value)
+}
+
+impl std::fmt::Display for Amount {
+    /// Writes the amount with all four decimal places, e.g. `-1.5000`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_decimal_string_with(Amount::DECIMAL_PLACES))
+    }
+}
+
+/// Largest exponent accepted in scientific notation, far beyond any amount that fits
+const MAX_EXPONENT: u32 = 64;
+
+impl std::str::FromStr for Amount {
+    type Err = AmountParseError;
+
+    /// Parses a decimal amount such as `1.50` exactly.
+    /// Amounts with more than four decimal places or in scientific notation are rejected.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Amount::parse(s, ExcessPrecision::Reject, false)
+    }
+}
+
+/// Why an amount could not be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountParseError {
+    /// Not a decimal number
+    Invalid,
+    /// Too large to be stored
+    Overflow,
+    /// In scientific notation, which wasn't allowed
+    Scientific,
+    /// More decimal places than the bank keeps, and the policy is to reject those
+    ExcessPrecision { decimal_places: u32 },
+}
+
+impl std::fmt::Display for AmountParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AmountParseError::Invalid => f.write_str("invalid amount"),
+            AmountParseError::Overflow => f.write_str("amount too large"),
+            AmountParseError::Scientific => f.write_str("amount in scientific notation, which is not allowed unless allow_scientific is set"),
+            AmountParseError::ExcessPrecision { decimal_places } => write!(f, "amount has more than {} decimal places", decimal_places),
+        }
+    }
+}
+
+impl std::error::Error for AmountParseError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ExcessPrecision;
+    use crate::shared_types::{Amount, AmountParseError};
+
+    fn parse(s: &str, excess_precision: ExcessPrecision) -> Result<i64, AmountParseError> {
+        Amount::parse(s, excess_precision, false).map(|amount| amount.value)
+    }
+
+    #[test]
+    fn test_parse_within_precision() {
+        for policy in [ExcessPrecision::Reject, ExcessPrecision::RoundHalfUp, ExcessPrecision::Truncate] {
+            assert_eq!(parse("1.5", policy), Ok(15000));
+            assert_eq!(parse("-0.0001", policy), Ok(-1));
+            assert_eq!(parse("3", policy), Ok(30000));
+            assert_eq!(parse(".25", policy), Ok(2500));
+            assert_eq!(parse("1.000500", policy), Ok(10005));
+        }
+    }
+
+    #[test]
+    fn test_parse_excess_precision_reject() {
+        assert_eq!(parse("1.00005", ExcessPrecision::Reject), Err(AmountParseError::ExcessPrecision { decimal_places: 4 }));
+        assert_eq!(parse("1.00004", ExcessPrecision::Reject), Err(AmountParseError::ExcessPrecision { decimal_places: 4 }));
+        assert_eq!(parse("2.999999", ExcessPrecision::Reject), Err(AmountParseError::ExcessPrecision { decimal_places: 4 }));
+    }
+
+    #[test]
+    fn test_parse_excess_precision_round_half_up() {
+        assert_eq!(parse("1.00005", ExcessPrecision::RoundHalfUp), Ok(10001));
+        assert_eq!(parse("1.00004", ExcessPrecision::RoundHalfUp), Ok(10000));
+        assert_eq!(parse("2.999999", ExcessPrecision::RoundHalfUp), Ok(30000));
+        assert_eq!(parse("-1.00005", ExcessPrecision::RoundHalfUp), Ok(-10001));
+    }
+
+    #[test]
+    fn test_parse_excess_precision_truncate() {
+        assert_eq!(parse("1.00005", ExcessPrecision::Truncate), Ok(10000));
+        assert_eq!(parse("1.00004", ExcessPrecision::Truncate), Ok(10000));
+        assert_eq!(parse("2.999999", ExcessPrecision::Truncate), Ok(29999));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for input in ["", ".", "-", "abc", "1.2.3", "NaN"] {
+            assert_eq!(parse(input, ExcessPrecision::RoundHalfUp), Err(AmountParseError::Invalid), "{input}");
+        }
+        assert_eq!(parse("99999999999999999999", ExcessPrecision::RoundHalfUp), Err(AmountParseError::Overflow));
+    }
+
+    fn parse_scientific(s: &str, excess_precision: ExcessPrecision) -> Result<i64, AmountParseError> {
+        Amount::parse(s, excess_precision, true).map(|amount| amount.value)
+    }
+
+    #[test]
+    fn test_parse_scientific_rejected_by_default() {
+        assert_eq!(parse("1e3", ExcessPrecision::RoundHalfUp), Err(AmountParseError::Scientific));
+        assert_eq!(parse("2.5E2", ExcessPrecision::RoundHalfUp), Err(AmountParseError::Scientific));
+        assert_eq!(parse("1.5e3", ExcessPrecision::RoundHalfUp), Err(AmountParseError::Scientific));
+        assert_eq!(parse("1E-4", ExcessPrecision::RoundHalfUp), Err(AmountParseError::Scientific));
+    }
+
+    #[test]
+    fn test_parse_scientific() {
+        assert_eq!(parse_scientific("1e3", ExcessPrecision::Reject), Ok(10_000_000));
+        assert_eq!(parse_scientific("2.5E2", ExcessPrecision::Reject), Ok(2_500_000));
+        assert_eq!(parse_scientific("1.23e2", ExcessPrecision::Reject), Ok(1_230_000));
+        assert_eq!(parse_scientific("1.5e3", ExcessPrecision::Reject), Ok(15_000_000));
+        assert_eq!(parse_scientific("1E-4", ExcessPrecision::Reject), Ok(1));
+        assert_eq!(parse_scientific("-1.5e-4", ExcessPrecision::RoundHalfUp), Ok(-2));
+        assert_eq!(parse_scientific("1e+2", ExcessPrecision::Reject), Ok(1_000_000));
+        assert_eq!(parse_scientific("12.5e-1", ExcessPrecision::Reject), Ok(12_500));
+        assert_eq!(parse_scientific("1e", ExcessPrecision::Reject), Err(AmountParseError::Invalid));
+        assert_eq!(parse_scientific("e3", ExcessPrecision::Reject), Err(AmountParseError::Invalid));
+    }
+
+    #[test]
+    fn test_parse_scientific_enforces_decimal_places() {
+        assert_eq!(parse_scientific("1e-5", ExcessPrecision::Reject), Err(AmountParseError::ExcessPrecision { decimal_places: 4 }));
+        assert_eq!(parse_scientific("1e-5", ExcessPrecision::Truncate), Ok(0));
+        assert_eq!(parse_scientific("5e-5", ExcessPrecision::RoundHalfUp), Ok(1));
+    }
+
+    #[test]
+    fn test_parse_scientific_overflow() {
+        assert_eq!(parse_scientific("1e14", ExcessPrecision::Reject), Ok(1_000_000_000_000_000_000));
+        assert_eq!(parse_scientific("1e15", ExcessPrecision::Reject), Err(AmountParseError::Overflow));
+        assert_eq!(parse_scientific("1e1000", ExcessPrecision::Reject), Err(AmountParseError::Overflow));
+    }
+
+    #[test]
+    fn test_amount_display() {
+        assert_eq!(Amount { value: -15000 }.to_string(), "-1.5000");
+        assert_eq!(Amount { value: 1 }.to_string(), "0.0001");
+    }
+
+    #[test]
+    fn test_amount_from_str() {
+        assert_eq!("1.50".parse::<Amount>().unwrap().scaled(), 15000);
+        assert_eq!("-0.0001".parse::<Amount>().unwrap().scaled(), -1);
+        assert_eq!("1.00005".parse::<Amount>(), Err(AmountParseError::ExcessPrecision { decimal_places: 4 }));
+        assert_eq!("1e3".parse::<Amount>(), Err(AmountParseError::Scientific));
+    }
+
+    #[test]
+    fn test_amount_from_f64() {
+        assert_eq!(Amount::from_f64(1.5), Some(Amount::from_scaled(15000)));
+        assert_eq!(Amount::from_f64(0.1 + 0.2), Some(Amount::from_scaled(3000)));
+        assert_eq!(Amount::from_f64(f64::NAN), None);
+        assert_eq!(Amount::from_f64(1e30), None);
+    }
+
+    #[test]
+    fn test_amount_scaled_round_trip() {
+        for scaled in [0, 1, -1, 15000, 123456789, i64::MIN + 1, i64::MAX] {
+            let amount = Amount::from_scaled(scaled);
+            assert_eq!(amount.scaled(), scaled);
+            assert_eq!(amount.as_decimal_string().parse::<Amount>(), Ok(amount));
+        }
+        assert_eq!(Amount::from_scaled(15000).as_decimal_string(), "1.5000");
+    }
+
+    #[test]
+    fn test_amount_short_decimal() {
+        assert_eq!(Amount::from_scaled(0).to_short_decimal(), "0.0");
+        assert_eq!(Amount::from_scaled(40000).to_short_decimal(), "4.0");
+        assert_eq!(Amount::from_scaled(-15000).to_short_decimal(), "-1.5");
+        assert_eq!(Amount::from_scaled(-1).to_short_decimal(), "-0.0001");
+        assert_eq!(Amount::from_scaled(1_000_000_000_000_000_000).to_short_decimal(), "100000000000000.0");
+        assert_eq!(Amount::from_scaled(i64::MAX).to_short_decimal(), "922337203685477.5807");
+        assert_eq!(Amount::from_scaled(i64::MIN + 1).to_short_decimal(), "-922337203685477.5807");
+    }
+
+    #[test]
+    fn test_other_decimal_places() {
+        assert_eq!(Amount::parse_with("1.25", 2, ExcessPrecision::Reject, false), Ok(Amount::from_scaled(125)));
+        assert_eq!(Amount::parse_with("1.255", 2, ExcessPrecision::Reject, false), Err(AmountParseError::ExcessPrecision { decimal_places: 2 }));
+        assert_eq!(Amount::parse_with("1.255", 2, ExcessPrecision::RoundHalfUp, false), Ok(Amount::from_scaled(126)));
+        assert_eq!(Amount::parse_with("0.000001", 6, ExcessPrecision::Reject, false), Ok(Amount::from_scaled(1)));
+        assert_eq!(Amount::from_scaled(-125).to_decimal_string_with(2), "-1.25");
+        assert_eq!(Amount::from_scaled(1_500_000).to_short_decimal_with(6), "1.5");
+        assert_eq!(Amount::from_scaled(7).to_decimal_string_with(0), "7");
+        assert_eq!(Amount::from_scaled(7).to_short_decimal_with(0), "7.0");
+    }
+
+    #[test]
+    fn test_fixed_decimal() {
+        assert_eq!(Amount::from_scaled(15000).to_fixed_decimal(4, 2), "1.50");
+        assert_eq!(Amount::from_scaled(15000).to_fixed_decimal(4, 0), "2");
+        assert_eq!(Amount::from_scaled(-15000).to_fixed_decimal(4, 0), "-2");
+        assert_eq!(Amount::from_scaled(14999).to_fixed_decimal(4, 0), "1");
+        assert_eq!(Amount::from_scaled(-1).to_fixed_decimal(4, 2), "0.00");
+        assert_eq!(Amount::from_scaled(15).to_fixed_decimal(1, 8), "1.50000000");
+        assert_eq!(Amount::from_scaled(i64::MAX).to_fixed_decimal(0, 9), "9223372036854775807.000000000");
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::Ordering;
+
+use crate::bank::{Account, Bank};
+use crate::report::SimulationReport;
+use crate::shared_types::ClientId;
+use crate::store::StoredTx;
+use crate::transaction::{Tx, TxRequest};
+
+impl Bank {
+    /// Works out what processing a batch of transactions would do, without changing the bank
+    ///
+    /// Copies of the accounts of the batch's clients, of the transactions the batch refers to and their
+    /// owners' accounts, and of the disputes parked for those transactions are put into a temporary bank,
+    /// which the batch is applied to with the same rules as [Bank::process_batch]. The report holds the
+    /// outcomes, the touched accounts as they would be afterwards, and which of them would be locked or
+    /// overdrawn by the batch. Nothing is journaled and the bank's metrics and activity are left alone.
+    pub fn simulate_batch(&self, batch: &[TxRequest]) -> SimulationReport {
+        let mut clients: BTreeSet<ClientId> = batch.iter().map(|tx| tx.client).collect();
+        let (accounts, transactions) = self.with_accounts_and_sheet(|accounts, transactions| {
+            let referenced: Vec<StoredTx> = batch.iter().filter_map(|tx| transactions.get(tx.tx)).collect();
+            clients.extend(referenced.iter().map(|tx| tx.client));
+            let accounts: Vec<Account> = clients.iter().filter_map(|client| accounts.get(client)).cloned().collect();
+            (accounts, referenced)
+        });
+        let parked: Vec<_> = {
+            let pending_disputes = self.pending_disputes.lock();
+            batch.iter().map(|tx| (tx.tx, pending_disputes.clone_waiting(tx.tx))).collect()
+        };
+
+        let overlay = Bank::with_config((*self.config).clone());
+        overlay.accounts_created.store(self.accounts_created.load(Ordering::Relaxed), Ordering::Relaxed);
+        overlay.tx_seq.store(self.tx_seq(), Ordering::Relaxed);
+        if let (Some(recent), Some(overlay_recent)) = (&self.recent_tx_ids, &overlay.recent_tx_ids) {
+            let recent = recent.lock().unwrap().clone();
+            *overlay_recent.lock().unwrap() = recent;
+        }
+        let before: Vec<(ClientId, bool, bool)> = accounts.iter()
+            .map(|account| (account.client, account.locked, account.is_overdrawn()))
+            .collect();
+        overlay.with_accounts_and_sheet(|overlay_accounts, overlay_transactions| {
+            overlay_accounts.extend(accounts.into_iter().map(|account| (account.client, account)));
+            for tx in transactions {
+                if !overlay_transactions.contains(tx.tx) {
+                    overlay_transactions.insert(tx);
+                }
+            }
+        });
+        let mut pending_disputes = overlay.pending_disputes.lock();
+        for (id, waiting) in parked {
+            if pending_disputes.clone_waiting(id).is_empty() {
+                pending_disputes.restore(id, waiting);
+            }
+        }
+        drop(pending_disputes);
+
+        let outcomes = batch.iter().map(|tx| Tx::from(tx.clone()).process(&overlay)).collect();
+
+        let overlay_accounts = overlay.accounts.lock();
+        let was = |client: ClientId| before.iter().find(|(before, ..)| *before == client).map(|(_, locked, overdrawn)| (*locked, *overdrawn));
+        let mut report = SimulationReport { outcomes, ..SimulationReport::default() };
+        for account in overlay_accounts.values() {
+            let (was_locked, was_overdrawn) = was(account.client).unwrap_or((false, false));
+            if account.locked && !was_locked {
+                report.newly_locked.push(account.client);
+            }
+            if account.is_overdrawn() && !was_overdrawn {
+                report.newly_negative.push(account.client);
+            }
+            report.accounts.push(account.snapshot());
+        }
+        report.accounts.sort_by_key(|account| account.client);
+        report.newly_locked.sort();
+        report.newly_negative.sort();
+        report
+    }
+}
+
+impl Account {
+    /// Whether the available balance is below zero in any currency
+    fn is_overdrawn(&self) -> bool {
+        self.available.value < 0 || self.currencies.values().any(|balance| balance.available.value < 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::Bank;
+    use crate::config::BankConfig;
+    use crate::report::RejectionReason;
+    use crate::shared_types::{ClientId, TxId};
+    use crate::transaction::{TxRequest, TxType};
+
+    fn request(type_: TxType, client: u16, tx: u32, amount: Option<i64>) -> TxRequest {
+        TxRequest { type_, client: ClientId(client), tx: TxId(tx), amount, currency: None }
+    }
+
+    #[test]
+    fn test_simulated_chargeback_reports_lock_and_leaves_bank_untouched() {
+        let bank = Bank::new();
+        bank.process_batch(vec![
+            request(TxType::Deposit, 1, 1, Some(20000)),
+            request(TxType::Withdrawal, 1, 2, Some(15000)),
+            request(TxType::Deposit, 2, 3, Some(10000)),
+            request(TxType::Deposit, 3, 4, Some(10000)),
+        ]);
+        let accounts_before = [1, 2, 3].map(|client| bank.account_snapshot(ClientId(client)));
+        let stats_before = bank.stats();
+
+        let report = bank.simulate_batch(&[
+            request(TxType::Dispute, 1, 1, None),
+            request(TxType::Chargeback, 1, 1, None),
+            request(TxType::Withdrawal, 2, 5, Some(4000)),
+            request(TxType::Withdrawal, 2, 3, Some(1000)),
+            request(TxType::Deposit, 4, 6, Some(1000)),
+        ]);
+
+        assert_eq!(report.outcomes, vec![Ok(()), Ok(()), Ok(()), Err(RejectionReason::DuplicateTxId), Ok(())]);
+        assert_eq!((report.applied(), report.rejected()), (4, 1));
+        assert_eq!(report.newly_locked, vec![ClientId(1)]);
+        assert_eq!(report.newly_negative, vec![ClientId(1)]);
+        let clients: Vec<_> = report.accounts.iter().map(|account| account.client).collect();
+        assert_eq!(clients, vec![ClientId(1), ClientId(2), ClientId(4)]);
+        let client_1 = &report.accounts[0];
+        assert_eq!((client_1.available, client_1.held, client_1.locked), (-15000, 0, true));
+        assert_eq!(report.accounts[1].available, 6000);
+
+        assert_eq!([1, 2, 3].map(|client| bank.account_snapshot(ClientId(client))), accounts_before);
+        assert!(bank.account_snapshot(ClientId(4)).is_none());
+        assert_eq!(bank.transactions.lock().len(), 4);
+        assert_eq!(bank.stats(), stats_before);
+        bank.apply(request(TxType::Dispute, 1, 1, None)).unwrap();
+        bank.apply(request(TxType::Chargeback, 1, 1, None)).unwrap();
+        assert!(bank.account_snapshot(ClientId(1)).unwrap().locked);
+    }
+
+    #[test]
+    fn test_simulation_resolves_disputes_parked_in_the_bank() {
+        let bank = Bank::with_config(BankConfig { max_pending_disputes: 10, ..BankConfig::default() });
+        bank.apply(request(TxType::Dispute, 1, 1, None)).unwrap();
+
+        let report = bank.simulate_batch(&[request(TxType::Deposit, 1, 1, Some(10000))]);
+
+        assert_eq!(report.outcomes, vec![Ok(())]);
+        let client_1 = &report.accounts[0];
+        assert_eq!((client_1.available, client_1.held), (0, 10000));
+        assert!(bank.account_snapshot(ClientId(1)).is_none());
+    }
+}
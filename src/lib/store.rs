@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::shared_types::{AmountValue, ClientId, CurrencyCode, SourceId, TxId};
+use crate::transaction::TxType;
+
+/// A deposit or withdrawal as kept on the transaction sheet, the record a [TransactionStore] stores
+///
+/// Serializable so a store can keep it anywhere, e.g. as a line of JSON in a file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredTx {
+    #[serde(rename = "type")]
+    pub type_: TxType,
+    pub client: ClientId,
+    pub tx: TxId,
+    /// In the bank's [internal integer representation](crate::shared_types::Amount)
+    pub amount: AmountValue,
+    /// None for the bank's base currency
+    pub currency: Option<CurrencyCode>,
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Whether the transaction is currently disputed, see [TransactionStore::set_disputed]
+    pub disputed: bool,
+    /// Whether the deposit was reversed, see [TransactionStore::set_reversed]
+    pub reversed: bool,
+    /// Whether the withdrawal was rejected for insufficient funds, which is stored all the same
+    /// but never took anything from the account
+    pub failed: bool,
+    /// Position in the order deposits and withdrawals were stored in, starting at 1,
+    /// see [crate::config::BankConfig::dispute_window]
+    pub seq: u64,
+    /// The file the transaction was read from, see [crate::bank::Bank::source_path]
+    pub source: Option<SourceId>,
+}
+
+impl StoredTx {
+    /// The currency of this transaction, falling back to the given base currency if none was given
+    pub(crate) fn currency_or(&self, base_currency: CurrencyCode) -> CurrencyCode {
+        self.currency.unwrap_or(base_currency)
+    }
+}
+
+/// Where a bank keeps its transaction sheet, the deposits and withdrawals disputes can reference
+///
+/// Banks keep it in memory in a [HashMap] unless created with [crate::bank::Bank::with_store],
+/// e.g. with a store on disk for feeds with more transactions than fit in memory.
+/// Transactions are passed in and out by value, so a store can keep them in any form.
+/// The bank only uses its store while holding the lock around it, so a store doesn't
+/// need to synchronize access itself.
+pub trait TransactionStore: fmt::Debug + Send {
+    /// A copy of the transaction with the given id
+    fn get(&self, id: TxId) -> Option<StoredTx>;
+
+    /// Marks the transaction with the given id disputed or no longer disputed, if it is stored.
+    /// The store must keep the change.
+    fn set_disputed(&mut self, id: TxId, disputed: bool);
+
+    /// Marks the deposit with the given id reversed, if it is stored. The store must keep the change.
+    fn set_reversed(&mut self, id: TxId, reversed: bool);
+
+    /// Adds a transaction, replacing any with the same id
+    fn insert(&mut self, tx: StoredTx);
+
+    /// Copies of all transactions in the store, in any order
+    fn iter(&self) -> Box<dyn Iterator<Item = StoredTx> + '_>;
+
+    /// Number of transactions in the store
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn contains(&self, id: TxId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Whether any transaction of the given client is disputed
+    fn has_disputed(&self, client: ClientId) -> bool;
+
+    /// Removes all transactions of the given client
+    fn remove_client(&mut self, client: ClientId);
+
+    /// Removes and returns all transactions
+    fn drain(&mut self) -> Vec<StoredTx>;
+
+    /// Removes all transactions
+    fn clear(&mut self) {
+        self.drain();
+    }
+}
+
+impl TransactionStore for HashMap<TxId, StoredTx> {
+    fn get(&self, id: TxId) -> Option<StoredTx> {
+        HashMap::get(self, &id).cloned()
+    }
+
+    fn set_disputed(&mut self, id: TxId, disputed: bool) {
+        if let Some(tx) = HashMap::get_mut(self, &id) {
+            tx.disputed = disputed;
+        }
+    }
+
+    fn set_reversed(&mut self, id: TxId, reversed: bool) {
+        if let Some(tx) = HashMap::get_mut(self, &id) {
+            tx.reversed = reversed;
+        }
+    }
+
+    fn insert(&mut self, tx: StoredTx) {
+        HashMap::insert(self, tx.tx, tx);
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = StoredTx> + '_> {
+        Box::new(self.values().cloned())
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn contains(&self, id: TxId) -> bool {
+        HashMap::contains_key(self, &id)
+    }
+
+    fn has_disputed(&self, client: ClientId) -> bool {
+        self.values().any(|tx| tx.client == client && tx.disputed)
+    }
+
+    fn remove_client(&mut self, client: ClientId) {
+        self.retain(|_, tx| tx.client != client);
+    }
+
+    fn drain(&mut self) -> Vec<StoredTx> {
+        HashMap::drain(self).map(|(_, tx)| tx).collect()
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{Arc, Mutex};
+
+    use crate::bank::Bank;
+    use crate::config::BankConfig;
+    use crate::shared_types::{ClientId, TxId};
+    use crate::store::{StoredTx, TransactionStore};
+    use crate::transaction::{TxRequest, TxType};
+
+    /// An in-memory store that counts the calls of each method
+    #[derive(Debug, Default)]
+    struct CountingStore {
+        transactions: HashMap<TxId, StoredTx>,
+        calls: Arc<Mutex<BTreeMap<&'static str, usize>>>,
+    }
+
+    impl CountingStore {
+        fn count(&self, method: &'static str) {
+            *self.calls.lock().unwrap().entry(method).or_insert(0) += 1;
+        }
+    }
+
+    impl TransactionStore for CountingStore {
+        fn get(&self, id: TxId) -> Option<StoredTx> {
+            self.count("get");
+            TransactionStore::get(&self.transactions, id)
+        }
+
+        fn set_disputed(&mut self, id: TxId, disputed: bool) {
+            self.count("set_disputed");
+            TransactionStore::set_disputed(&mut self.transactions, id, disputed);
+        }
+
+        fn set_reversed(&mut self, id: TxId, reversed: bool) {
+            self.count("set_reversed");
+            TransactionStore::set_reversed(&mut self.transactions, id, reversed);
+        }
+
+        fn insert(&mut self, tx: StoredTx) {
+            self.count("insert");
+            TransactionStore::insert(&mut self.transactions, tx);
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = StoredTx> + '_> {
+            self.count("iter");
+            TransactionStore::iter(&self.transactions)
+        }
+
+        fn len(&self) -> usize {
+            self.count("len");
+            self.transactions.len()
+        }
+
+        fn has_disputed(&self, client: ClientId) -> bool {
+            self.count("has_disputed");
+            TransactionStore::has_disputed(&self.transactions, client)
+        }
+
+        fn remove_client(&mut self, client: ClientId) {
+            self.count("remove_client");
+            TransactionStore::remove_client(&mut self.transactions, client);
+        }
+
+        fn drain(&mut self) -> Vec<StoredTx> {
+            self.count("drain");
+            TransactionStore::drain(&mut self.transactions)
+        }
+    }
+
+    #[test]
+    fn test_bank_uses_given_store() {
+        let store = CountingStore::default();
+        let calls = store.calls.clone();
+        let bank = Bank::with_store(BankConfig::default(), Box::new(store));
+        let apply = |type_, amount| bank.apply(TxRequest { type_, client: ClientId(1), tx: TxId(1), amount, currency: None });
+
+        apply(TxType::Deposit, Some(20000)).unwrap();
+        apply(TxType::Dispute, None).unwrap();
+        apply(TxType::Resolve, None).unwrap();
+
+        assert_eq!(bank.stats().transactions, 1);
+        assert_eq!(bank.close_account(ClientId(1)).unwrap().unwrap().available, 20000);
+        let expected = BTreeMap::from([("get", 3), ("set_disputed", 2), ("has_disputed", 1), ("insert", 1), ("len", 1), ("remove_client", 1)]);
+        assert_eq!(*calls.lock().unwrap(), expected);
+    }
+}
@@ -0,0 +1,152 @@
+use futures::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+
+use crate::bank::Bank;
+use crate::report::ProcessReport;
+use crate::transaction::{Tx, TxRequest};
+
+/// How many transactions of a stream are applied before yielding to the runtime, see [Bank::process_stream]
+const YIELD_EVERY: usize = 256;
+
+impl Bank {
+    /// Processes transactions as they arrive on a stream, e.g. from a message broker consumer,
+    /// until it ends or `cancel` is cancelled
+    ///
+    /// Each transaction is applied with the same rules as a row of a csv. The next one is only pulled
+    /// from the stream once it has been applied, so a producer is held back to the pace of the bank.
+    /// Every 256 transactions the task yields to the runtime, so a stream that always has
+    /// the next transaction ready doesn't starve other tasks.
+    ///
+    /// Once `cancel` is cancelled no more transactions are pulled and the report of the ones applied
+    /// so far is returned, as it is once the bank is poisoned, see [Bank::is_poisoned].
+    /// The report counts each transaction as a row, numbered from 1 in the order received.
+    pub async fn process_stream<S: Stream<Item = TxRequest>>(&self, stream: S, cancel: CancellationToken) -> ProcessReport {
+        let mut report = ProcessReport::default();
+        let mut stream = std::pin::pin!(stream);
+        loop {
+            let request = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => break,
+                request = stream.next() => match request {
+                    Some(request) => request,
+                    None => break,
+                },
+            };
+            if self.is_poisoned() {
+                break;
+            }
+            report.rows += 1;
+            let line = report.rows as u64;
+            self.process_record(&mut report, line, Tx::from(request));
+            if report.rows % YIELD_EVERY == 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::bank::Bank;
+    use crate::report::RejectionReason;
+    use crate::shared_types::{ClientId, TxId};
+    use crate::transaction::{TxRequest, TxType};
+
+    fn request(type_: TxType, client: u16, tx: u32, amount: Option<i64>) -> TxRequest {
+        TxRequest { type_, client: ClientId(client), tx: TxId(tx), amount, currency: None }
+    }
+
+    #[tokio::test]
+    async fn test_stream_of_transactions() {
+        let bank = Bank::new();
+        let transactions = futures::stream::iter(vec![
+            request(TxType::Deposit, 1, 1, Some(20000)),
+            request(TxType::Deposit, 2, 2, Some(10000)),
+            request(TxType::Withdrawal, 1, 3, Some(5000)),
+            request(TxType::Dispute, 2, 2, None),
+            request(TxType::Withdrawal, 2, 4, Some(1)),
+        ]);
+
+        let report = bank.process_stream(transactions, CancellationToken::new()).await;
+
+        assert_eq!((report.rows, report.applied), (5, 4));
+        assert_eq!(report.rejected_for(RejectionReason::InsufficientFunds), 1);
+        let client_1 = bank.account_snapshot(ClientId(1)).unwrap();
+        assert_eq!((client_1.available, client_1.held), (15000, 0));
+        let client_2 = bank.account_snapshot(ClientId(2)).unwrap();
+        assert_eq!((client_2.available, client_2.held), (0, 10000));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_stream_returns_partial_report() {
+        let bank = Bank::new();
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let transactions = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|request| (request, receiver))
+        });
+        let cancel = CancellationToken::new();
+        let tokio_bank = Bank::new_for_tokio(&bank);
+        let processing = tokio::spawn({
+            let cancel = cancel.clone();
+            async move { tokio_bank.process_stream(transactions.boxed(), cancel).await }
+        });
+
+        sender.send(request(TxType::Deposit, 1, 1, Some(20000))).unwrap();
+        sender.send(request(TxType::Deposit, 1, 2, Some(10000))).unwrap();
+        sender.send(request(TxType::Withdrawal, 1, 3, Some(50000))).unwrap();
+        let mut applied = false;
+        for _ in 0..100 {
+            if bank.account_snapshot(ClientId(1)).is_some_and(|account| account.available == 30000)
+                && bank.transactions.lock().len() == 3 {
+                applied = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(applied, "transactions were not applied as they arrived");
+
+        // The stream is still open, waiting for the next transaction
+        cancel.cancel();
+        let report = tokio::time::timeout(Duration::from_secs(1), processing).await
+            .expect("cancelled stream did not return promptly")
+            .unwrap();
+
+        assert_eq!((report.rows, report.applied), (3, 2));
+        assert_eq!(report.rejected_for(RejectionReason::InsufficientFunds), 1);
+        assert!(sender.send(request(TxType::Deposit, 1, 4, Some(10000))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_long_stream_yields_to_other_tasks() {
+        let bank = Bank::new();
+        let other_task_ran = Arc::new(AtomicBool::new(false));
+        tokio::spawn({
+            let other_task_ran = other_task_ran.clone();
+            async move { other_task_ran.store(true, Ordering::SeqCst) }
+        });
+        let seen_before_end = Arc::new(AtomicBool::new(false));
+        let transactions = futures::stream::iter(1..=2000).map({
+            let (other_task_ran, seen_before_end) = (other_task_ran.clone(), seen_before_end.clone());
+            move |tx| {
+                if tx == 2000 {
+                    seen_before_end.store(other_task_ran.load(Ordering::SeqCst), Ordering::SeqCst);
+                }
+                request(TxType::Deposit, 1, tx, Some(1))
+            }
+        });
+
+        // The test runtime has a single thread, so the other task only runs if processing yields
+        let report = bank.process_stream(transactions, CancellationToken::new()).await;
+
+        assert_eq!(report.applied, 2000);
+        assert!(seen_before_end.load(Ordering::SeqCst));
+    }
+}
@@ -1,104 +1,531 @@
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use crate::shared_types::{ClientId, TxId, Amount, AmountValue, RawAmountValue};
-use crate::bank::{Account, Bank};
+use chrono::{DateTime, Utc};
+use serde::{de, de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
+use crate::shared_types::{ClientId, TxId, Amount, AmountParseError, AmountValue, RawAmountValue, CurrencyCode, SourceId};
+use crate::activity::ActivityKind;
+use crate::bank::{Account, Accounts, Bank, Transactions};
+use crate::config::{ExcessPrecision, ZeroAmountPolicy};
+use crate::journal::JournalEntry;
+use crate::report::{RejectionReason, RowError};
+use crate::store::StoredTx;
 
 /// A Transaction is represented here.
-/// type, client, tx, and amount are to be supplied from a payment processor.
+/// type, client, tx, and amount are supplied from a payment processor, read as a [RawTxRecord]
+/// or given as a [TxRequest].
+/// amount is required for deposits and withdrawals. A chargeback with an amount only charges back
+/// that part of the disputed amount, disputes and resolves ignore it.
+/// currency is optional, transactions without one are in the bank's base currency.
+/// timestamp is optional, it is an ISO-8601 date and time used to order transactions when asked to.
 /// disputed is an internal variable to indicate whether the transaction has been disputed.
-#[derive(Deserialize, Debug)]
-pub(crate) struct Tx {
-    #[serde(rename = "type")]
+/// reversed is an internal variable to indicate whether the deposit has been reversed.
+/// seq is the position of a deposit or withdrawal in the order they were stored on the transaction sheet,
+/// starting at 1 and zero until it is stored, see [crate::config::BankConfig::dispute_window].
+/// source is the file the transaction was read from, if it was read from one.
+#[derive(Debug, Clone)]
+pub struct Tx {
     pub(crate) type_: TxType,
     pub(crate) client: ClientId,
     pub(crate) tx: TxId,
-    pub(crate) amount: Amount,
-    #[serde(skip)]
+    pub(crate) amount: Option<Amount>,
+    pub(crate) currency: Option<CurrencyCode>,
+    pub(crate) timestamp: Option<DateTime<Utc>>,
     pub(crate) disputed: bool,
+    pub(crate) reversed: bool,
+    pub(crate) seq: u64,
+    pub(crate) source: Option<SourceId>,
 }
 
 impl Tx {
+    pub fn id(&self) -> TxId {
+        self.tx
+    }
+
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    /// Whether this transaction is currently disputed
+    pub fn is_disputed(&self) -> bool {
+        self.disputed
+    }
+
+    /// The file this transaction was read from, see [Bank::source_path]
+    ///
+    /// None for transactions applied directly on the bank or received over the network.
+    pub fn source(&self) -> Option<SourceId> {
+        self.source
+    }
+
+    /// The amount of this transaction, zero for meta-transactions without one
+    pub(crate) fn amount_value(&self) -> AmountValue {
+        self.amount.as_ref().map_or(0, |amount| amount.value)
+    }
+
+    /// The currency of this transaction, falling back to the given base currency if none was given
+    pub(crate) fn currency_or(&self, base_currency: CurrencyCode) -> CurrencyCode {
+        self.currency.unwrap_or(base_currency)
+    }
+
+    /// This deposit or withdrawal as it is kept on the transaction sheet
+    pub(crate) fn into_stored(self) -> StoredTx {
+        StoredTx {
+            type_: self.type_,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount_value(),
+            currency: self.currency,
+            timestamp: self.timestamp,
+            disputed: self.disputed,
+            reversed: self.reversed,
+            failed: false,
+            seq: self.seq,
+            source: self.source,
+        }
+    }
+
     /// Processes this transaction
     /// Updates the bank transaction sheet and the client's account
     ///
-    /// If the client's account is locked, the transaction is not processed.
+    /// If the client's account is locked or their id is reserved, the transaction is not processed.
     ///
     /// Transactions of type Dispute, Resolve and Chargeback are
     /// meta-transactions that are not stored on the transaction sheet directly
     /// but instead affect the state of the client's account.
+    /// They are rejected if the transaction they reference belongs to another client.
+    ///
+    /// Returns the reason the transaction was rejected if it was not applied.
+    ///
+    /// If the bank allows it, meta-transactions referencing a transaction that is not
+    /// on the sheet yet are parked and count as applied. They are applied for real
+    /// once a deposit or withdrawal with that id is recorded.
+    ///
+    /// If the bank has a journal, the transaction is appended to it before the next one
+    /// can be processed if it changed the bank's state. If that fails the transaction is rejected as
+    /// [RejectionReason::JournalFailed] although it was applied, and so is every later one,
+    /// see [Bank::is_poisoned].
     ///
     /// # Arguments
     ///
     /// `bank` - The bank to process this transaction with
-    pub(crate) fn process(self, bank: &mut Bank) {
-        let mut accounts = bank.accounts.lock().unwrap();
-        let account = match accounts.get_mut(&self.client) {
-            Some(acc) => {
-                if acc.locked { return; }
-                acc
+    pub(crate) fn process(self, bank: &Bank) -> Result<(), RejectionReason> {
+        if bank.is_poisoned() {
+            return Err(RejectionReason::JournalFailed);
+        }
+        bank.record_seen_tx_id(self.tx);
+        bank.profiler.record_processed();
+        if bank.config.reserved_clients.contains(&self.client) {
+            return Err(RejectionReason::ReservedClient);
+        }
+        bank.with_accounts_and_sheet(|accounts, transactions| {
+            let (tx_id, client) = (self.tx, self.client);
+            let is_record = matches!(self.type_, TxType::Deposit | TxType::Withdrawal);
+            let journal_entry = bank.journal.as_ref().map(|_| JournalEntry::Transaction {
+                request: self.request(),
+                source: self.source.and_then(|source| bank.source_path(source)),
+            });
+            let outcome = self.apply_and_trace(accounts, transactions, bank);
+            // A withdrawal rejected for insufficient funds is still stored on the transaction sheet
+            let changed = matches!(outcome, Ok(()) | Err(RejectionReason::InsufficientFunds));
+            if let Some(entry) = journal_entry.filter(|_| changed) {
+                if bank.append_to_journal(&entry).is_err() {
+                    return Err(RejectionReason::JournalFailed);
+                }
+            }
+            // Not for a record rejected e.g. as a duplicate, whose id belongs to the one stored before
+            if is_record && changed {
+                let waiting = bank.pending_disputes.lock().take(tx_id);
+                // Checked for ownership like any meta-transaction, now that the owner is known
+                for meta_tx in waiting {
+                    let _ = meta_tx.apply_and_trace(accounts, transactions, bank);
+                }
+            }
+            if bank.config.debug_invariants {
+                bank.check_invariants(accounts, client);
+            }
+            outcome
+        })
+    }
+
+    /// This transaction as it could be given to [Bank::apply], without its timestamp and source
+    pub(crate) fn request(&self) -> TxRequest {
+        TxRequest {
+            type_: self.type_,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount.map(|amount| amount.value),
+            currency: self.currency,
+        }
+    }
+
+    /// Applies this transaction, emitting a debug event if it was applied or a warning if it was rejected
+    fn apply_and_trace(self, accounts: &mut Accounts, transactions: &mut Transactions, bank: &Bank) -> Result<(), RejectionReason> {
+        let tx_type = self.type_;
+        let (type_, client, tx, amount, source) = (tx_type.as_str(), self.client.0, self.tx.0, self.amount_value(), self.source);
+        let outcome = self.apply(accounts, transactions, bank);
+        let metrics = bank.metrics.get();
+        match outcome {
+            Ok(()) => {
+                tracing::debug!(tx_type = type_, client, tx, amount, "applied transaction");
+                metrics.transaction_applied(tx_type);
             },
-            None => {
-                let account = Account::new(self.client);
-                accounts.insert(self.client, account);
-                accounts.get_mut(&self.client).unwrap()
+            Err(reason) => {
+                let source = source.and_then(|source| bank.source_path(source));
+                tracing::warn!(tx_type = type_, client, tx, amount, ?reason, source = source.as_deref(), "rejected transaction");
+                metrics.transaction_rejected(tx_type, reason);
+            },
+        }
+        outcome
+    }
+
+    /// Applies this transaction to the given accounts and transaction sheet, which must be the locked ones of `bank`
+    fn apply(self, accounts: &mut Accounts, transactions: &mut Transactions, bank: &Bank) -> Result<(), RejectionReason> {
+        match self.type_ {
+            TxType::Deposit | TxType::Withdrawal => self.apply_record(accounts, transactions, bank),
+            TxType::Dispute | TxType::Resolve | TxType::Chargeback | TxType::Reversal => self.apply_meta(accounts, transactions, bank),
+        }
+    }
+
+    /// Applies a deposit or withdrawal, opening the client's account if needed and allowed,
+    /// and stores it on the transaction sheet
+    fn apply_record(self, accounts: &mut Accounts, transactions: &mut Transactions, bank: &Bank) -> Result<(), RejectionReason> {
+        match &self.amount {
+            None => return Err(RejectionReason::MissingAmount),
+            Some(amount) if amount.value < 0 => return Err(RejectionReason::NegativeAmount),
+            Some(amount) if amount.value == 0
+                && bank.config.zero_amount_policy == ZeroAmountPolicy::Reject => {
+                return Err(RejectionReason::ZeroAmount);
+            },
+            Some(_) => (),
+        }
+        if transactions.get(self.tx).is_some_and(|stored| bank.config.duplicate_tx_ids.rejects(stored.type_, self.type_))
+            && bank.is_recent_tx_id(self.tx) {
+            return Err(RejectionReason::DuplicateTxId);
+        }
+        match accounts.get(&self.client) {
+            Some(account) if account.locked => return Err(RejectionReason::AccountLocked),
+            None if bank.config.strict_accounts => return Err(RejectionReason::UnknownClient),
+            _ => (),
+        }
+        let base_currency = bank.config.base_currency;
+        let currency = self.currency_or(base_currency);
+        // Counted per file and currency, deposits not read from a file share a counter
+        let limit_key = (self.source, currency);
+        if self.type_ == TxType::Deposit {
+            let (deposited, available) = accounts.get(&self.client).map_or((0, 0), |account| {
+                (account.deposited.get(&limit_key).copied().unwrap_or(0), account.available_in(currency, base_currency))
+            });
+            bank.config.limits.check_deposit(self.amount_value(), deposited, available)?;
+        }
+        let account = accounts.entry(self.client).or_insert_with(|| {
+            bank.log_activity(ActivityKind::AccountCreated, self.client, Some(self.tx), None);
+            bank.new_account(self.client)
+        });
+        let amount = Amount { value: self.amount_value() };
+        let (outcome, net_flow) = if self.type_ == TxType::Deposit {
+            let outcome = account.deposit(amount, currency, base_currency);
+            if outcome.is_ok() {
+                let deposited = account.deposited.entry(limit_key).or_insert(0);
+                *deposited = deposited.saturating_add(amount.value);
+            }
+            (outcome, amount.value as i128)
+        } else {
+            let fee = Amount { value: bank.config.fee_schedule.fee(amount.value) };
+            let outcome = account.withdraw(amount, fee, currency, base_currency);
+            if outcome.is_ok() && fee.value != 0 {
+                *bank.fees.lock().entry(currency).or_default() += fee.value;
             }
+            (outcome, -(amount.value as i128 + fee.value as i128))
         };
-        match self.type_ {
-            TxType::Deposit => {
-                account.available.value += self.amount.value;
+        if outcome.is_ok() && bank.config.debug_invariants {
+            account.record_net_flow(currency, net_flow);
+        }
+        match outcome {
+            Ok(()) if bank.config.record_statements => {
+                account.record_statement(self.tx, self.type_, amount.value, currency, base_currency, self.source);
             },
-            TxType::Withdrawal => {
-                if account.available.value >= self.amount.value {
-                    account.available.value -= self.amount.value;
-                }
+            // A withdrawal rejected for insufficient funds is still stored on the transaction sheet
+            Ok(()) | Err(RejectionReason::InsufficientFunds) => (),
+            Err(_) => return outcome,
+        }
+        bank.record_recent_tx_id(self.tx);
+        let stored = Tx { seq: bank.next_tx_seq(), ..self }.into_stored();
+        transactions.insert(StoredTx { failed: outcome.is_err(), ..stored });
+        outcome
+    }
+
+    /// Applies a dispute, resolve, chargeback or reversal to the account of the client owning the
+    /// referenced transaction. The account is only touched once that transaction is found,
+    /// so a meta-transaction never opens an account.
+    fn apply_meta(self, accounts: &mut Accounts, transactions: &mut Transactions, bank: &Bank) -> Result<(), RejectionReason> {
+        match accounts.get(&self.client) {
+            Some(account) if account.locked => return Err(RejectionReason::AccountLocked),
+            None if bank.config.strict_accounts => return Err(RejectionReason::UnknownClient),
+            _ => (),
+        }
+        let Some(stored) = transactions.get(self.tx) else {
+            return bank.pending_disputes.lock().park(self);
+        };
+        let (tx_id, mut disputed_tx) = (self.tx, stored.clone());
+        let outcome = self.apply_meta_to(accounts, &mut disputed_tx, bank);
+        // Kept even when rejected, e.g. the dispute a direct chargeback opened before failing
+        if disputed_tx.disputed != stored.disputed {
+            transactions.set_disputed(tx_id, disputed_tx.disputed);
+        }
+        if disputed_tx.reversed != stored.reversed {
+            transactions.set_reversed(tx_id, disputed_tx.reversed);
+        }
+        outcome
+    }
+
+    /// Applies a dispute, resolve, chargeback or reversal referencing the given stored transaction,
+    /// changing the copy of it as the transaction sheet should
+    fn apply_meta_to(self, accounts: &mut Accounts, disputed_tx: &mut StoredTx, bank: &Bank) -> Result<(), RejectionReason> {
+        if disputed_tx.client != self.client {
+            return Err(RejectionReason::ClientMismatch);
+        }
+        // Left out of the client's net position too, see [Bank::net_position]
+        if disputed_tx.failed {
+            return Err(RejectionReason::FailedTx);
+        }
+        if bank.config.verify_meta_amounts && matches!(self.type_, TxType::Dispute | TxType::Resolve)
+            && self.amount.is_some_and(|amount| amount.value != disputed_tx.amount) {
+            return Err(RejectionReason::AmountMismatch);
+        }
+        // The owner of a transaction on the sheet always has an account
+        let account = accounts.get_mut(&self.client).ok_or(RejectionReason::UnknownClient)?;
+        let base_currency = bank.config.base_currency;
+        let currency = disputed_tx.currency_or(base_currency);
+        let disputed = Amount { value: disputed_tx.amount };
+        match self.type_ {
+            TxType::Dispute | TxType::Reversal if disputed_tx.reversed => return Err(RejectionReason::Reversed),
+            TxType::Chargeback if disputed_tx.reversed && bank.config.allow_direct_chargeback => return Err(RejectionReason::Reversed),
+            TxType::Dispute => self.open_dispute(account, disputed_tx, bank)?,
+            TxType::Resolve if disputed_tx.disputed => {
+                account.release(disputed, currency, base_currency)?;
+                disputed_tx.disputed = false;
+                account.open_disputes -= 1;
+                bank.log_activity(ActivityKind::DisputeResolved, self.client, Some(self.tx), Some(disputed.value));
+                bank.metrics.get().dispute_closed();
             },
-            TxType::Dispute => {
-                match bank.transactions.lock().unwrap().get_mut(&self.tx) {
-                    Some(disputed_tx) => {
-                        account.available.value -= disputed_tx.amount.value;
-                        account.held.value += disputed_tx.amount.value;
-                        disputed_tx.disputed = true;
+            TxType::Chargeback if disputed_tx.disputed || bank.config.allow_direct_chargeback => {
+                let charged = match self.amount {
+                    None => disputed,
+                    Some(amount) if amount.value == 0
+                        && bank.config.zero_amount_policy == ZeroAmountPolicy::Reject => {
+                        return Err(RejectionReason::ZeroAmount);
                     },
-                    None => ()
+                    Some(amount) => amount,
+                };
+                if !disputed_tx.disputed {
+                    // Checked before the implicit dispute so a refused chargeback leaves nothing held
+                    if charged.value < 0 || charged.value > disputed.value {
+                        return Err(RejectionReason::InvalidChargebackAmount);
+                    }
+                    self.open_dispute(account, disputed_tx, bank)?;
                 }
-            },
-            TxType::Resolve => {
-                match bank.transactions.lock().unwrap().get_mut(&self.tx) {
-                    Some(disputed_tx) => {
-                        if disputed_tx.disputed {
-                            account.available.value += disputed_tx.amount.value;
-                            account.held.value -= disputed_tx.amount.value;
-                            disputed_tx.disputed = false;
+                let shortfall = match account.charge_back(disputed, charged, bank.config.partial_chargeback_remainder, currency, base_currency) {
+                    Ok(shortfall) => shortfall,
+                    Err(reason) => {
+                        if reason == RejectionReason::InsufficientHeld {
+                            tracing::error!(client = self.client.0, tx = self.tx.0, disputed = disputed.value, "held balance does not cover disputed amount, refusing chargeback");
                         }
+                        return Err(reason);
                     },
-                    None => ()
+                };
+                if shortfall > 0 {
+                    let mut losses = bank.losses.lock();
+                    let loss = &mut losses.entry(self.client).or_default().value;
+                    *loss = loss.saturating_add(shortfall);
+                }
+                if bank.config.debug_invariants {
+                    account.record_net_flow(currency, -(charged.value as i128));
                 }
+                account.open_disputes -= 1;
+                bank.log_activity(ActivityKind::Chargeback, self.client, Some(self.tx), Some(charged.value));
+                bank.log_activity(ActivityKind::AccountLocked, self.client, Some(self.tx), None);
+                let metrics = bank.metrics.get();
+                metrics.dispute_closed();
+                metrics.account_locked();
             },
-            TxType::Chargeback => {
-                match bank.transactions.lock().unwrap().get(&self.tx) {
-                    Some(disputed_tx) => {
-                        if disputed_tx.disputed {
-                            account.locked = true;
-                            account.held.value -= disputed_tx.amount.value;
-                        }
-                    },
-                    None => ()
+            TxType::Reversal if disputed_tx.type_ != TxType::Deposit || disputed_tx.disputed => {
+                return Err(RejectionReason::NotReversible);
+            },
+            TxType::Reversal => {
+                account.withdraw(disputed, Amount::default(), currency, base_currency)?;
+                disputed_tx.reversed = true;
+                if bank.config.debug_invariants {
+                    account.record_net_flow(currency, -(disputed.value as i128));
                 }
+                bank.log_activity(ActivityKind::Reversal, self.client, Some(self.tx), Some(disputed.value));
             },
+            _ => return Err(RejectionReason::NotDisputed),
+        }
+        if bank.config.record_statements {
+            let amount = match (self.type_, &self.amount) {
+                (TxType::Chargeback, Some(amount)) => amount.value,
+                _ => disputed_tx.amount,
+            };
+            account.record_statement(self.tx, self.type_, amount, currency, base_currency, self.source);
+        }
+        Ok(())
+    }
+
+    /// Holds the amount of the transaction this dispute, or direct chargeback, references and marks it disputed
+    ///
+    /// Refused if more deposits and withdrawals than [crate::config::BankConfig::dispute_window] were stored after it.
+    fn open_dispute(&self, account: &mut Account, disputed_tx: &mut StoredTx, bank: &Bank) -> Result<(), RejectionReason> {
+        if bank.config.dispute_window.is_some_and(|window| bank.tx_seq().saturating_sub(disputed_tx.seq) > window) {
+            return Err(RejectionReason::DisputeWindowExpired);
+        }
+        let base_currency = bank.config.base_currency;
+        let disputed = Amount { value: disputed_tx.amount };
+        if let Err(reason) = account.hold(disputed, disputed_tx.currency_or(base_currency), base_currency) {
+            tracing::error!(client = self.client.0, tx = self.tx.0, disputed = disputed.value, ?reason, "refusing to hold disputed amount");
+            return Err(reason);
+        }
+        if !disputed_tx.disputed {
+            account.open_disputes += 1;
+        }
+        disputed_tx.disputed = true;
+        bank.log_activity(ActivityKind::DisputeOpened, self.client, Some(self.tx), Some(disputed.value));
+        bank.metrics.get().dispute_opened();
+        Ok(())
+    }
+}
+
+/// A transaction supplied programmatically rather than read from a csv
+///
+/// Mirrors the columns of the csv input. `amount` is in the bank's
+/// [internal integer representation](Amount). It is required for deposits
+/// and withdrawals and ignored for meta-transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxRequest {
+    #[serde(rename = "type")]
+    pub type_: TxType,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Option<AmountValue>,
+    pub currency: Option<CurrencyCode>,
+}
+
+/// The former name of [TxRequest]
+#[deprecated(note = "renamed to TxRequest")]
+pub type PublicTx = TxRequest;
+
+impl From<TxRequest> for Tx {
+    fn from(tx: TxRequest) -> Self {
+        Tx {
+            type_: tx.type_,
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount.map(|value| Amount { value }),
+            currency: tx.currency,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None,
         }
-        if matches!(self.type_, TxType::Deposit | TxType::Withdrawal) {
-            bank.transactions.lock().unwrap().insert(self.tx, self);
+    }
+}
+
+/// A row of csv input as it is read, before its type and amount are checked
+///
+/// The columns are named like the csv header, `amount`, `currency` and `timestamp` may be left out.
+/// Converted into a [TxRequest] with [TryFrom], which parses the amount with the default
+/// four decimal places, while the csv reader parses it as set by the bank and [crate::config::ProcessOptions].
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RawTxRecord {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(default)]
+    pub amount: Option<String>,
+    #[serde(default)]
+    pub currency: Option<CurrencyCode>,
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl RawTxRecord {
+    /// The request for this record, with its amount parsed with the given decimal places and options
+    ///
+    /// Deposits and withdrawals need an amount. Meta-transactions keep theirs if they have one,
+    /// for partial chargebacks and [crate::config::BankConfig::verify_meta_amounts], and are fine without.
+    pub(crate) fn into_request_with(self, decimal_places: u32, excess_precision: ExcessPrecision, allow_scientific: bool) -> Result<TxRequest, RowError> {
+        let type_: TxType = self.type_.parse().map_err(RowError::Malformed)?;
+        let amount = match self.amount.as_deref() {
+            None | Some("") => None,
+            Some(amount) => match Amount::parse_with(amount, decimal_places, excess_precision, allow_scientific) {
+                Ok(parsed) => Some(parsed.value),
+                Err(AmountParseError::ExcessPrecision { decimal_places }) => {
+                    return Err(RowError::ExcessPrecision { value: amount.to_string(), decimal_places });
+                },
+                Err(e) => return Err(RowError::Malformed(e.to_string())),
+            },
+        };
+        if amount.is_none() && matches!(type_, TxType::Deposit | TxType::Withdrawal) {
+            return Err(RowError::MissingAmount { type_ });
         }
+        Ok(TxRequest { type_, client: self.client, tx: self.tx, amount, currency: self.currency })
+    }
+}
+
+/// Checks a record as read from a csv, parsing its amount with the default four decimal places,
+/// rounding extra ones half up and without scientific notation
+impl TryFrom<RawTxRecord> for TxRequest {
+    type Error = RowError;
+
+    fn try_from(record: RawTxRecord) -> Result<Self, Self::Error> {
+        record.into_request_with(Amount::DECIMAL_PLACES, ExcessPrecision::RoundHalfUp, false)
     }
 }
 
 /// The type of transaction
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TxType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
-    Chargeback
+    Chargeback,
+    /// Undoes a deposit by taking its amount from the available balance, unlike a chargeback
+    /// without a dispute and without locking the account
+    Reversal,
+}
+
+impl TxType {
+    /// The canonical name of the transaction type as it appears in input files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::Reversal => "reversal",
+        }
+    }
+}
+
+/// Parses a transaction type case-insensitively
+///
+/// Besides the canonical names, `withdraw` is accepted for a withdrawal
+/// and `charge-back` or `charge_back` for a chargeback.
+impl std::str::FromStr for TxType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "deposit" => TxType::Deposit,
+            "withdrawal" | "withdraw" => TxType::Withdrawal,
+            "dispute" => TxType::Dispute,
+            "resolve" => TxType::Resolve,
+            "chargeback" | "charge-back" | "charge_back" => TxType::Chargeback,
+            "reversal" => TxType::Reversal,
+            _ => return Err(format!("Unrecognized transaction type: {:?}", s))
+        })
+    }
 }
 
 /// Used by serde to parse the transaction type given by a payment processor into a TxType
@@ -107,288 +534,874 @@ impl<'de> Deserialize<'de> for TxType {
         where D: Deserializer<'de>
         {
             let s = String::deserialize(deserializer)?;
-            Ok(match s.as_str() {
-                "deposit" => TxType::Deposit,
-                "withdrawal" => TxType::Withdrawal,
-                "dispute" => TxType::Dispute,
-                "resolve" => TxType::Resolve,
-                "chargeback" => TxType::Chargeback,
-                _ => panic!("Unrecognized transaction type: {:?}", s.as_str())
-            })
+            s.parse().map_err(serde::de::Error::custom)
+        }
+}
+
+/// Used by serde to parse an optional currency column, e.g. `EUR`
+impl<'de> Deserialize<'de> for CurrencyCode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+        {
+            let s = String::deserialize(deserializer)?;
+            s.parse().map_err(serde::de::Error::custom)
+        }
+}
+
+impl Serialize for TxType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+        {
+            self.as_str().serialize(serializer)
+        }
+}
+
+impl Serialize for CurrencyCode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+        {
+            self.as_str().serialize(serializer)
         }
 }
 
 /// Converts the amount of a transaction into an integer
 /// While the program is running on a lot of tx's, errors due to floating point representation
 /// are possible, so internally we use integers to represent the amount.
+///
+/// An empty amount column is handled by serde as a missing amount, anything else
+/// that isn't a number fails to parse.
+///
+/// Amounts are scaled as for a bank with the default four [decimal places](Amount).
+/// The csv reader parses them with the bank's decimal places instead, see [RawTxRecord].
 impl<'de> Deserialize<'de> for Amount {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
         where D: Deserializer<'de>
         {
-            let value = match RawAmountValue::deserialize(deserializer) {
-                Ok(amount) => {
-                    (amount * 10000.0).round() as AmountValue
-                },
-                _ => 0
-            };
-            Ok(Amount {
-                value
-            })
+            deserializer.deserialize_str(AmountVisitor { decimal_places: Amount::DECIMAL_PLACES })
         }
 }
 
-/// When serializing the amount of a transaction or any amounts on a client account
-/// we divide by 10000 to turn it back into a float to get the desired output
-impl <'de> Serialize for Amount {
+/// Parses amounts given as text exactly, rounding ones with more than `decimal_places` decimal places half up,
+/// and scales numbers by the same decimal places. Other policies are applied by the csv reader,
+/// see [crate::config::ExcessPrecision].
+struct AmountVisitor {
+    decimal_places: u32,
+}
+
+impl Visitor<'_> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a decimal amount")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Amount, E> {
+        Amount::parse_with(value, self.decimal_places, ExcessPrecision::RoundHalfUp, false).map_err(E::custom)
+    }
+
+    fn visit_f64<E: de::Error>(self, value: RawAmountValue) -> Result<Amount, E> {
+        Ok(Amount { value: (value * Amount::scale(self.decimal_places) as RawAmountValue).round() as AmountValue })
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Amount, E> {
+        value.checked_mul(Amount::scale(self.decimal_places))
+            .map(|value| Amount { value })
+            .ok_or_else(|| E::custom(AmountParseError::Invalid))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Amount, E> {
+        i64::try_from(value).map_err(|_| E::custom(AmountParseError::Invalid))
+            .and_then(|value| self.visit_i64(value))
+    }
+}
+
+/// Amounts are serialized as exact decimal strings of four decimal places at most, e.g. `1.5`,
+/// the inverse of deserializing them
+impl Serialize for Amount {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where S: Serializer
         {
-            let value = self.value as RawAmountValue / 10000.0;
-            value.serialize(serializer)
+            serializer.serialize_str(&self.to_short_decimal())
         }
 }
 
 #[cfg(test)]
 mod tests {
+    use serde::Deserializer;
+
     use crate::bank::Bank;
-    use crate::shared_types::Amount;
-    use crate::transaction::{Tx, TxType};
+    use crate::config::{BankConfig, DuplicateTxIdPolicy, ExcessPrecision, ZeroAmountPolicy};
+    use crate::report::{RejectionReason, RowError};
+    use crate::shared_types::{Amount, ClientId, TxId};
+    use crate::transaction::{AmountVisitor, RawTxRecord, Tx, TxRequest, TxType};
+
+    /// Reads csv data with a header row into records the way the csv reader of a bank does
+    fn read_records(data: &str) -> Vec<RawTxRecord> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(data.as_bytes())
+            .deserialize()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    /// Reads csv data with a header row into transactions, see [read_records]
+    fn read_txs(data: &str) -> Vec<Tx> {
+        read_records(data).into_iter()
+            .map(|record| Tx::from(TxRequest::try_from(record).unwrap()))
+            .collect()
+    }
+
+    fn raw(type_: &str, amount: Option<&str>) -> RawTxRecord {
+        RawTxRecord { type_: type_.to_string(), client: ClientId(1), tx: TxId(2), amount: amount.map(str::to_string), currency: None, timestamp: None }
+    }
 
     #[test]
     fn test_amount_stored_as_integer() {
-        let mut rdr = csv::Reader::from_reader("deposit, 2, 2, 5.1234".as_bytes());
-        for record in rdr.deserialize() {
-            let tx: Tx = record.unwrap();
-            assert_eq!(tx.amount.value, 51234);
+        let txs = read_txs("type, client, tx, amount\ndeposit, 2, 2, 5.1234");
+
+        assert_eq!(txs[0].amount_value(), 51234);
+        assert!(format!("{:?}", txs[0]).contains("51234"));
+    }
+
+    #[test]
+    fn test_amount_deserialized_with_decimal_places() {
+        let amount = |value: serde_json::Value, decimal_places| value.deserialize_any(AmountVisitor { decimal_places }).map(|amount| amount.value);
+
+        assert_eq!(serde_json::from_str::<Amount>("\"1.5\"").unwrap().value, 15000);
+        assert_eq!(amount(serde_json::json!("1.255"), 2).unwrap(), 126);
+        assert_eq!(amount(serde_json::json!(1.5), 2).unwrap(), 150);
+        assert_eq!(amount(serde_json::json!(3), 2).unwrap(), 300);
+        assert_eq!(amount(serde_json::json!(3), 0).unwrap(), 3);
+        assert!(amount(serde_json::json!(i64::MAX), 2).is_err());
+    }
+
+    #[test]
+    fn test_conversion_of_every_type_and_amount() {
+        let types = [
+            (TxType::Deposit, true),
+            (TxType::Withdrawal, true),
+            (TxType::Dispute, false),
+            (TxType::Resolve, false),
+            (TxType::Chargeback, false),
+            (TxType::Reversal, false),
+        ];
+        for (type_, needs_amount) in types {
+            let convert = |amount| TxRequest::try_from(raw(type_.as_str(), amount));
+            let request = |amount| Ok(TxRequest { type_, client: ClientId(1), tx: TxId(2), amount, currency: None });
+
+            assert_eq!(convert(Some("1.5")), request(Some(15000)), "{:?}", type_);
+            assert_eq!(convert(Some("0.00005")), request(Some(1)), "{:?}", type_);
+            if needs_amount {
+                assert_eq!(convert(None), Err(RowError::MissingAmount { type_ }));
+                assert_eq!(convert(Some("")), Err(RowError::MissingAmount { type_ }));
+            } else {
+                assert_eq!(convert(None), request(None), "{:?}", type_);
+                assert_eq!(convert(Some("")), request(None), "{:?}", type_);
+            }
+            assert!(matches!(convert(Some("1.0x")), Err(RowError::Malformed(_))), "{:?}", type_);
+            assert!(matches!(convert(Some("2.5E2")), Err(RowError::Malformed(_))), "{:?}", type_);
+        }
+    }
 
-            let serialized = format!("{:?}", tx);
-            assert_eq!(serialized.contains("5.1234"), true)
+    #[test]
+    fn test_conversion_maps_type_spellings() {
+        assert_eq!(TxRequest::try_from(raw("Withdraw", Some("1.0"))).unwrap().type_, TxType::Withdrawal);
+        assert_eq!(TxRequest::try_from(raw("CHARGE_BACK", None)).unwrap().type_, TxType::Chargeback);
+        assert!(matches!(TxRequest::try_from(raw("refund", Some("1.0"))), Err(RowError::Malformed(message)) if message.contains("refund")));
+    }
+
+    #[test]
+    fn test_conversion_with_bank_amount_options() {
+        let request = raw("deposit", Some("1.234")).into_request_with(2, ExcessPrecision::Reject, false);
+        assert_eq!(request, Err(RowError::ExcessPrecision { value: "1.234".to_string(), decimal_places: 2 }));
+
+        let request = raw("deposit", Some("1.5e1")).into_request_with(2, ExcessPrecision::Reject, true);
+        assert_eq!(request.unwrap().amount, Some(1500));
+    }
+
+    #[test]
+    fn test_amount_serialized_as_plain_decimal() {
+        let csv_row = |value| {
+            let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(vec![]);
+            wtr.serialize([Amount { value }]).unwrap();
+            String::from_utf8(wtr.into_inner().unwrap()).unwrap()
+        };
+        assert_eq!(csv_row(i64::MAX), "922337203685477.5807\n");
+        assert_eq!(csv_row(i64::MIN + 1), "-922337203685477.5807\n");
+        assert_eq!(csv_row(0), "0.0\n");
+        assert_eq!(csv_row(-1), "-0.0001\n");
+
+        assert_eq!(serde_json::to_string(&Amount { value: i64::MIN + 1 }).unwrap(), "\"-922337203685477.5807\"");
+    }
+
+    #[test]
+    fn test_id_newtypes_read_from_numeric_columns() {
+        let txs = read_txs("type, client, tx, amount\ndeposit, 65535, 4294967295, 1.0");
+
+        assert_eq!(txs[0].client, ClientId(65535));
+        assert_eq!(txs[0].tx, TxId(4294967295));
+    }
+
+    #[test]
+    fn test_optional_currency_column() {
+        let txs = read_txs("type, client, tx, amount, currency\ndeposit, 1, 1, 1.0, eur\ndeposit, 1, 2, 1.0,");
+
+        assert_eq!(txs[0].currency, Some("EUR".parse().unwrap()));
+        assert_eq!(txs[1].currency, None);
+    }
+
+    #[test]
+    fn test_tx_type_accepted_spellings() {
+        let accepted = [
+            ("deposit", TxType::Deposit),
+            ("Deposit", TxType::Deposit),
+            ("DEPOSIT", TxType::Deposit),
+            ("withdrawal", TxType::Withdrawal),
+            ("WITHDRAWAL", TxType::Withdrawal),
+            ("withdraw", TxType::Withdrawal),
+            ("Withdraw", TxType::Withdrawal),
+            ("dispute", TxType::Dispute),
+            ("Dispute", TxType::Dispute),
+            ("resolve", TxType::Resolve),
+            ("RESOLVE", TxType::Resolve),
+            ("chargeback", TxType::Chargeback),
+            ("ChargeBack", TxType::Chargeback),
+            ("charge-back", TxType::Chargeback),
+            ("charge_back", TxType::Chargeback),
+            ("CHARGE_BACK", TxType::Chargeback),
+            ("reversal", TxType::Reversal),
+            ("Reversal", TxType::Reversal),
+        ];
+        for (spelling, type_) in accepted {
+            assert_eq!(spelling.parse::<TxType>(), Ok(type_), "{}", spelling);
+            assert_eq!(type_.as_str().parse::<TxType>(), Ok(type_));
         }
     }
 
+    #[test]
+    fn test_tx_type_unknown_is_error_not_panic() {
+        assert!("refund".parse::<TxType>().is_err());
+
+        let records = read_records("type,client,tx,amount\nrefund,1,1,1.0");
+        assert!(TxRequest::try_from(records[0].clone()).is_err());
+    }
+
     #[test]
     fn test_process_tx_deposit() {
-        let mut bank = Bank::new();
+        let bank = Bank::new();
 
         Tx {
             type_: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 5},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 5 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
 
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().client, &1);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().available.value, &5);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().held.value, &0);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().locked, &false);
-        assert_eq!(bank.transactions.lock().unwrap().len() as i32, 1);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().client, &ClientId(1));
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().available.value, &5);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().held.value, &0);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().locked, &false);
+        assert_eq!(bank.transactions.lock().len() as i32, 1);
 
     }
 
     #[test]
     fn test_process_tx_deposit_locked() {
-        let mut bank = Bank::new();
+        let bank = Bank::new();
 
         Tx {
             type_: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 5},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 5 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
         Tx {
             type_: TxType::Dispute,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 0},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 0 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
         Tx {
             type_: TxType::Chargeback,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 0},
-            disputed: false
-        }.process(&mut bank);
-        Tx {
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: None,
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
+        let outcome = Tx {
             type_: TxType::Deposit,
-            client: 1,
-            tx: 2,
-            amount: Amount { value: 1},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(2),
+            amount: Some(Amount { value: 1 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank);
+        assert_eq!(outcome, Err(RejectionReason::AccountLocked));
 
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().client, &1);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().available.value, &0);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().held.value, &0);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().locked, &true);
-        assert_eq!(&bank.transactions.lock().unwrap().get(&1).unwrap().disputed, &true);
-        assert_eq!(bank.transactions.lock().unwrap().len() as i32, 1);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().client, &ClientId(1));
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().available.value, &0);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().held.value, &0);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().locked, &true);
+        assert_eq!(&bank.transactions.lock().get(TxId(1)).unwrap().disputed, &true);
+        assert_eq!(bank.transactions.lock().len() as i32, 1);
     }
 
     #[test]
     fn test_process_tx_withdrawal() {
-        let mut bank = Bank::new();
+        let bank = Bank::new();
 
         Tx {
             type_: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 5},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 5 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
         Tx {
             type_: TxType::Withdrawal,
-            client: 1,
-            tx: 2,
-            amount: Amount { value: 5},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(2),
+            amount: Some(Amount { value: 5 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
 
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().client, &1);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().available.value, &0);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().held.value, &0);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().locked, &false);
-        assert_eq!(bank.transactions.lock().unwrap().len() as i32, 2);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().client, &ClientId(1));
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().available.value, &0);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().held.value, &0);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().locked, &false);
+        assert_eq!(bank.transactions.lock().len() as i32, 2);
     }
 
     #[test]
     fn test_process_tx_withdrawal_insufficient_funds() {
-        let mut bank = Bank::new();
+        let bank = Bank::new();
 
         Tx {
             type_: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 3},
-            disputed: false
-        }.process(&mut bank);
-        Tx {
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 3 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
+        let outcome = Tx {
             type_: TxType::Withdrawal,
-            client: 1,
-            tx: 2,
-            amount: Amount { value: 5},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(2),
+            amount: Some(Amount { value: 5 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank);
+        assert_eq!(outcome, Err(RejectionReason::InsufficientFunds));
 
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().client, &1);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().available.value, &3);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().held.value, &0);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().locked, &false);
-        assert_eq!(bank.transactions.lock().unwrap().len() as i32, 2);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().client, &ClientId(1));
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().available.value, &3);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().held.value, &0);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().locked, &false);
+        assert_eq!(bank.transactions.lock().len() as i32, 2);
     }
 
     #[test]
     fn test_process_tx_withdrawal_mid_dispute() {
-        let mut bank = Bank::new();
+        let bank = Bank::new();
         Tx {
             type_: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 3},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 3 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
         Tx {
             type_: TxType::Dispute,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 0},
-            disputed: false
-        }.process(&mut bank);
-        Tx {
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 0 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
+        let outcome = Tx {
             type_: TxType::Withdrawal,
-            client: 1,
-            tx: 2,
-            amount: Amount { value: 3},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(2),
+            amount: Some(Amount { value: 3 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank);
+        assert_eq!(outcome, Err(RejectionReason::InsufficientFunds));
 
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().client, &1);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().available.value, &0);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().held.value, &3);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().locked, &false);
-        assert_eq!(&bank.transactions.lock().unwrap().get(&1).unwrap().disputed, &true);
-        assert_eq!(bank.transactions.lock().unwrap().len() as i32, 2);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().client, &ClientId(1));
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().available.value, &0);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().held.value, &3);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().locked, &false);
+        assert_eq!(&bank.transactions.lock().get(TxId(1)).unwrap().disputed, &true);
+        assert_eq!(bank.transactions.lock().len() as i32, 2);
     }
 
     #[test]
     fn test_process_tx_dispute_resolved() {
-        let mut bank = Bank::new();
+        let bank = Bank::new();
         Tx {
             type_: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 3},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 3 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
         Tx {
             type_: TxType::Dispute,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 0},
-            disputed: false
-        }.process(&mut bank);
-        Tx {
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 0 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
+        let outcome = Tx {
             type_: TxType::Withdrawal,
-            client: 1,
-            tx: 2,
-            amount: Amount { value: 3},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(2),
+            amount: Some(Amount { value: 3 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank);
+        assert_eq!(outcome, Err(RejectionReason::InsufficientFunds));
         Tx {
             type_: TxType::Resolve,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 0},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 0 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
         Tx {
             type_: TxType::Withdrawal,
-            client: 1,
-            tx: 3,
-            amount: Amount { value: 3},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(3),
+            amount: Some(Amount { value: 3 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
 
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().client, &1);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().available.value, &0);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().held.value, &0);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().locked, &false);
-        assert_eq!(&bank.transactions.lock().unwrap().get(&1).unwrap().disputed, &false);
-        assert_eq!(bank.transactions.lock().unwrap().len() as i32, 3);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().client, &ClientId(1));
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().available.value, &0);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().held.value, &0);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().locked, &false);
+        assert_eq!(&bank.transactions.lock().get(TxId(1)).unwrap().disputed, &false);
+        assert_eq!(bank.transactions.lock().len() as i32, 3);
     }
 
     #[test]
     fn test_process_tx_resolve_wrong_tx_id() {
-        let mut bank = Bank::new();
+        let bank = Bank::new();
         Tx {
             type_: TxType::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 3},
-            disputed: false
-        }.process(&mut bank);
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 3 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
         Tx {
             type_: TxType::Dispute,
-            client: 1,
-            tx: 1,
-            amount: Amount { value: 0},
-            disputed: false
-        }.process(&mut bank);
-        Tx {
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 0 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
+        let outcome = Tx {
             type_: TxType::Resolve,
-            client: 1,
-            tx: 34,
-            amount: Amount { value: 0},
-            disputed: false
-        }.process(&mut bank);
-
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().client, &1);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().available.value, &0);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().held.value, &3);
-        assert_eq!(&bank.accounts.lock().unwrap().get(&1).unwrap().locked, &false);
-        assert_eq!(&bank.transactions.lock().unwrap().get(&1).unwrap().disputed, &true);
-        assert_eq!(bank.transactions.lock().unwrap().len() as i32, 1);
-    }
-}
\ No newline at end of file
+            client: ClientId(1),
+            tx: TxId(34),
+            amount: Some(Amount { value: 0 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank);
+        assert_eq!(outcome, Err(RejectionReason::UnknownTx));
+
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().client, &ClientId(1));
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().available.value, &0);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().held.value, &3);
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().locked, &false);
+        assert_eq!(&bank.transactions.lock().get(TxId(1)).unwrap().disputed, &true);
+        assert_eq!(bank.transactions.lock().len() as i32, 1);
+    }
+
+    #[test]
+    fn test_process_tx_zero_deposit_rejected_by_default() {
+        let bank = Bank::new();
+        let outcome = Tx {
+            type_: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 0 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank);
+
+        assert_eq!(outcome, Err(RejectionReason::ZeroAmount));
+        assert_eq!(bank.accounts.lock().len() as i32, 0);
+        assert_eq!(bank.transactions.lock().len() as i32, 0);
+    }
+
+    #[test]
+    fn test_process_tx_zero_deposit_accepted_when_permissive() {
+        let bank = Bank::with_config(BankConfig {
+            zero_amount_policy: ZeroAmountPolicy::Accept,
+            ..BankConfig::default()
+        });
+        Tx {
+            type_: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 0 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank).unwrap();
+
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().available.value, &0);
+        assert_eq!(bank.transactions.lock().len() as i32, 1);
+    }
+
+    #[test]
+    fn test_tx_id_reused_by_other_type_rejected() {
+        let bank = Bank::new();
+        let process = |type_, amount| Tx {
+            type_,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: amount }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank);
+
+        process(TxType::Deposit, 50000).unwrap();
+        assert_eq!(process(TxType::Withdrawal, 20000), Err(RejectionReason::DuplicateTxId));
+        process(TxType::Dispute, 0).unwrap();
+
+        let account = bank.account_snapshot(ClientId(1)).unwrap();
+        assert_eq!((account.available, account.held), (0, 50000));
+        assert_eq!(bank.transactions.lock().get(TxId(1)).unwrap().type_, TxType::Deposit);
+    }
+
+    #[test]
+    fn test_tx_id_reuse_policies() {
+        let deposit_twice = |duplicate_tx_ids| {
+            let bank = Bank::with_config(BankConfig { duplicate_tx_ids, ..BankConfig::default() });
+            let deposit = |amount| Tx {
+                type_: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Some(Amount { value: amount }),
+                currency: None,
+                timestamp: None,
+                disputed: false,
+                reversed: false,
+                seq: 0,
+                source: None
+            }.process(&bank);
+            deposit(10000).unwrap();
+            let outcome = deposit(20000);
+            let stored = bank.transactions.lock().get(TxId(1)).unwrap().amount;
+            (outcome, stored)
+        };
+
+        assert_eq!(deposit_twice(DuplicateTxIdPolicy::RejectOtherType), (Ok(()), 20000));
+        assert_eq!(deposit_twice(DuplicateTxIdPolicy::Overwrite), (Ok(()), 20000));
+        assert_eq!(deposit_twice(DuplicateTxIdPolicy::Reject), (Err(RejectionReason::DuplicateTxId), 10000));
+    }
+
+    #[test]
+    fn test_tx_id_reuse_only_rejected_within_window() {
+        let bank = Bank::with_config(BankConfig {
+            duplicate_tx_ids: DuplicateTxIdPolicy::Reject,
+            duplicate_window: Some(2),
+            ..BankConfig::default()
+        });
+        let deposit = |tx, amount| bank.apply(TxRequest { type_: TxType::Deposit, client: ClientId(1), tx: TxId(tx), amount: Some(amount), currency: None });
+
+        deposit(1, 10000).unwrap();
+        deposit(2, 10000).unwrap();
+        assert_eq!(deposit(1, 10000), Err(RejectionReason::DuplicateTxId));
+        deposit(3, 10000).unwrap();
+        // Tx 1 is no longer among the last two stored
+        deposit(1, 20000).unwrap();
+        assert_eq!(deposit(1, 20000), Err(RejectionReason::DuplicateTxId));
+
+        assert_eq!(bank.transactions.lock().get(TxId(1)).unwrap().amount, 20000);
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 50000);
+    }
+
+    #[test]
+    fn test_process_tx_reversal() {
+        let bank = Bank::new();
+        let apply = |type_, tx, amount| bank.apply(TxRequest { type_, client: ClientId(1), tx: TxId(tx), amount, currency: None });
+        apply(TxType::Deposit, 1, Some(30000)).unwrap();
+        apply(TxType::Deposit, 2, Some(20000)).unwrap();
+        apply(TxType::Withdrawal, 3, Some(5000)).unwrap();
+
+        apply(TxType::Reversal, 1, None).unwrap();
+
+        let account = bank.account_snapshot(ClientId(1)).unwrap();
+        assert_eq!((account.available, account.held, account.locked), (15000, 0, false));
+        assert!(bank.transactions.lock().get(TxId(1)).unwrap().reversed);
+        assert_eq!(apply(TxType::Reversal, 1, None), Err(RejectionReason::Reversed));
+        assert_eq!(apply(TxType::Dispute, 1, None), Err(RejectionReason::Reversed));
+        assert_eq!(apply(TxType::Reversal, 3, None), Err(RejectionReason::NotReversible));
+        apply(TxType::Dispute, 2, None).unwrap();
+        assert_eq!(apply(TxType::Reversal, 2, None), Err(RejectionReason::NotReversible));
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, -5000);
+    }
+
+    #[test]
+    fn test_process_tx_reversal_insufficient_funds() {
+        let bank = Bank::new();
+        let apply = |type_, tx, amount| bank.apply(TxRequest { type_, client: ClientId(1), tx: TxId(tx), amount, currency: None });
+        apply(TxType::Deposit, 1, Some(30000)).unwrap();
+        apply(TxType::Withdrawal, 2, Some(10000)).unwrap();
+
+        assert_eq!(apply(TxType::Reversal, 1, None), Err(RejectionReason::InsufficientFunds));
+
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 20000);
+        assert!(!bank.transactions.lock().get(TxId(1)).unwrap().reversed);
+        // Once the funds are back the deposit can be reversed
+        apply(TxType::Deposit, 3, Some(10000)).unwrap();
+        apply(TxType::Reversal, 1, None).unwrap();
+        assert_eq!(bank.account_snapshot(ClientId(1)).unwrap().available, 0);
+    }
+
+    #[test]
+    fn test_process_tx_dispute_rejected_zero_deposit() {
+        let bank = Bank::new();
+        let _ = Tx {
+            type_: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 0 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank);
+        let outcome = Tx {
+            type_: TxType::Dispute,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Some(Amount { value: 0 }),
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank);
+
+        assert_eq!(outcome, Err(RejectionReason::UnknownTx));
+        assert!(bank.accounts.lock().get(&ClientId(1)).is_none());
+    }
+
+    #[test]
+    fn test_meta_tx_without_amount() {
+        let txs = read_txs("type, client, tx, amount\ndeposit, 1, 1, 2.0\ndispute, 1, 1\nresolve, 1, 1,");
+        assert!(txs[1].amount.is_none());
+        assert!(txs[2].amount.is_none());
+
+        let bank = Bank::new();
+        let mut txs = txs.into_iter();
+        txs.next().unwrap().process(&bank).unwrap();
+        txs.next().unwrap().process(&bank).unwrap();
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().held.value, &20000);
+        txs.next().unwrap().process(&bank).unwrap();
+        assert_eq!(&bank.accounts.lock().get(&ClientId(1)).unwrap().available.value, &20000);
+    }
+
+    #[test]
+    fn test_process_tx_deposit_missing_amount_rejected() {
+        let bank = Bank::new();
+        let outcome = Tx {
+            type_: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: None,
+            currency: None,
+            timestamp: None,
+            disputed: false,
+            reversed: false,
+            seq: 0,
+            source: None
+        }.process(&bank);
+
+        assert_eq!(outcome, Err(RejectionReason::MissingAmount));
+        assert_eq!(bank.accounts.lock().len() as i32, 0);
+        assert_eq!(bank.transactions.lock().len() as i32, 0);
+    }
+
+    /// Collects formatted log output of the subscriber used in a test
+    #[derive(Clone, Default)]
+    struct CapturedLogs(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_insufficient_funds_emits_one_warning() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::WARN)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let bank = Bank::new();
+            Tx {
+                type_: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Some(Amount { value: 3 }),
+                currency: None,
+                timestamp: None,
+                disputed: false,
+                reversed: false,
+                seq: 0,
+                source: None
+            }.process(&bank).unwrap();
+            let _ = Tx {
+                type_: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Some(Amount { value: 5 }),
+                currency: None,
+                timestamp: None,
+                disputed: false,
+                reversed: false,
+                seq: 0,
+                source: None
+            }.process(&bank);
+        });
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let warnings: Vec<&str> = output.lines().filter(|line| line.contains("WARN")).collect();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("tx_type=\"withdrawal\""));
+        assert!(warnings[0].contains("client=1"));
+        assert!(warnings[0].contains("tx=2"));
+        assert!(warnings[0].contains("amount=5"));
+        assert!(warnings[0].contains("reason=InsufficientFunds"));
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+
+use crate::bank::Bank;
+use crate::config::ProcessOptions;
+use crate::error::BankError;
+use crate::reader::TxReader;
+use crate::report::{LineError, RowError, ValidationReport};
+use crate::shared_types::TxId;
+use crate::transaction::TxType;
+
+impl Bank {
+    /// Checks the csv at the given path without applying any of its transactions
+    ///
+    /// Every row is parsed as it would be by [Bank::process_transactions_from_csv_path], and the report
+    /// lists the rows that fail to parse along with deposits and withdrawals reusing a tx id of an
+    /// earlier row or of a transaction already on the bank's sheet, and disputes, resolves and chargebacks
+    /// referencing a tx id that is in neither the file nor the sheet.
+    /// Fails if the file can't be opened.
+    pub fn validate_csv_path(&self, csv_path: &str) -> Result<ValidationReport, BankError> {
+        let tx_reader = TxReader::from_path(csv_path, &ProcessOptions::default())?.with_decimal_places(self.config.decimal_places);
+        self.validate_records(tx_reader)
+    }
+
+    fn validate_records<R: std::io::Read>(&self, mut tx_reader: TxReader<R>) -> Result<ValidationReport, BankError> {
+        let mut report = ValidationReport::default();
+        let mut seen = HashSet::new();
+        let mut first_uses: Vec<(u64, TxId)> = Vec::new();
+        let mut references: Vec<(u64, TxType, TxId)> = Vec::new();
+        while let Some(record) = tx_reader.next() {
+            report.rows += 1;
+            let line = tx_reader.line();
+            let tx = match record {
+                Ok(tx) => tx,
+                Err(e) => {
+                    report.problems.push(e);
+                    continue;
+                },
+            };
+            match tx.type_ {
+                TxType::Deposit | TxType::Withdrawal => {
+                    if seen.insert(tx.tx) {
+                        first_uses.push((line, tx.tx));
+                    } else {
+                        report.problems.push(LineError { line, error: RowError::DuplicateTx { tx: tx.tx } });
+                    }
+                },
+                TxType::Dispute | TxType::Resolve | TxType::Chargeback | TxType::Reversal => references.push((line, tx.type_, tx.tx)),
+            }
+        }
+        // Only compared with the sheet once the whole file is read, so transactions aren't held up meanwhile
+        let transactions = self.transactions.lock();
+        for (line, tx) in first_uses {
+            if transactions.contains(tx) {
+                report.problems.push(LineError { line, error: RowError::DuplicateTx { tx } });
+            }
+        }
+        for (line, type_, tx) in references {
+            if !seen.contains(&tx) && !transactions.contains(tx) {
+                report.problems.push(LineError { line, error: RowError::UnknownTx { type_, tx } });
+            }
+        }
+        report.problems.sort_by_key(|problem| problem.line);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use crate::bank::Bank;
+    use crate::config::ProcessOptions;
+    use crate::reader::TxReader;
+    use crate::report::RowError;
+    use crate::shared_types::{ClientId, TxId};
+    use crate::transaction::{TxRequest, TxType};
+
+    #[test]
+    fn test_validation_reports_each_problem_by_line() {
+        let bank = Bank::new();
+        bank.apply(TxRequest { type_: TxType::Deposit, client: ClientId(1), tx: TxId(100), amount: Some(10000), currency: None }).unwrap();
+
+        let report = bank.validate_csv_path("tests/fixtures/validation_problems.csv").unwrap();
+
+        let problems: Vec<(u64, &RowError)> = report.problems.iter().map(|problem| (problem.line, &problem.error)).collect();
+        assert_eq!(report.rows, 8);
+        assert_eq!(problems.len(), 5);
+        assert!(matches!(problems[0], (3, RowError::Malformed(message)) if message.contains("Unrecognized transaction type")));
+        assert!(matches!(problems[1], (4, RowError::Malformed(_))));
+        assert_eq!(problems[2], (6, &RowError::DuplicateTx { tx: TxId(1) }));
+        assert_eq!(problems[3], (7, &RowError::DuplicateTx { tx: TxId(100) }));
+        assert_eq!(problems[4], (9, &RowError::UnknownTx { type_: TxType::Dispute, tx: TxId(42) }));
+    }
+
+    /// Applies a transaction to the bank the first time the file is read, as another thread could
+    struct ApplyingReader<'a> {
+        bank: &'a Bank,
+        content: &'a [u8],
+    }
+
+    impl Read for ApplyingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.bank.stats().transactions == 0 {
+                self.bank.apply(TxRequest { type_: TxType::Deposit, client: ClientId(1), tx: TxId(7), amount: Some(10000), currency: None }).unwrap();
+            }
+            self.content.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_transactions_applied_while_reading_the_file() {
+        let bank = Bank::new();
+        let reader = ApplyingReader { bank: &bank, content: b"type,client,tx,amount\ndeposit,1,7,1.0\ndispute,1,7,\n" };
+
+        let report = bank.validate_records(TxReader::from_reader(reader, &ProcessOptions::default()).unwrap()).unwrap();
+
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!((report.problems[0].line, &report.problems[0].error), (2, &RowError::DuplicateTx { tx: TxId(7) }));
+    }
+
+    #[test]
+    fn test_validation_applies_nothing() {
+        let bank = Bank::new();
+
+        let report = bank.validate_csv_path("tests/fixtures/dispute_resolve.csv").unwrap();
+
+        assert!(report.is_valid());
+        assert_eq!(report.rows, 6);
+        assert_eq!(bank.stats().accounts, 0);
+        assert_eq!(bank.stats().transactions, 0);
+    }
+}
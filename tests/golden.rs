@@ -0,0 +1,49 @@
+//! Processes the fixture csvs under `tests/fixtures` and compares the complete account
+//! output against the expected output stored next to them as `<scenario>.expected.csv`.
+//!
+//! After an intended change of the output, regenerate the expected files with
+//! `UPDATE_GOLDEN=1 cargo test --test golden` and review the diff.
+//!
+//! Processes the files with the async methods, so only built with the `async` feature.
+#![cfg(feature = "async")]
+
+use std::path::Path;
+
+use bank_lib::bank::Bank;
+
+const FIXTURES: &str = "tests/fixtures";
+
+/// Each scenario's name and the fixture files it processes in order
+const SCENARIOS: [(&str, &[&str]); 8] = [
+    ("deposits_and_withdrawals", &["deposits_and_withdrawals.csv"]),
+    ("dispute_resolve", &["dispute_resolve.csv"]),
+    ("dispute_chargeback", &["dispute_chargeback.csv"]),
+    ("ignored_rows", &["ignored_rows.csv"]),
+    ("multi_currency", &["multi_currency.csv"]),
+    ("precision", &["precision.csv"]),
+    ("split_files", &["split_part_1.csv", "split_part_2.csv"]),
+    ("empty_files", &["empty.csv", "header_only.csv"]),
+];
+
+async fn check_scenario(name: &str, inputs: &[&str]) {
+    let paths: Vec<String> = inputs.iter()
+        .map(|input| format!("{}/{}", FIXTURES, input))
+        .collect();
+    let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+    let output = Bank::run_files_to_string(&paths).await.unwrap();
+
+    let expected_path = Path::new(FIXTURES).join(format!("{}.expected.csv", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&expected_path, &output).unwrap();
+    }
+    let expected = std::fs::read_to_string(&expected_path)
+        .unwrap_or_else(|e| panic!("missing {}, run with UPDATE_GOLDEN=1: {}", expected_path.display(), e));
+    assert_eq!(output, expected, "output of scenario {} differs from {}", name, expected_path.display());
+}
+
+#[tokio::test]
+async fn test_golden_outputs() {
+    for (name, inputs) in SCENARIOS {
+        check_scenario(name, inputs).await;
+    }
+}
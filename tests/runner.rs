@@ -0,0 +1,179 @@
+//! Runs the binary, which is only built with the `async` feature
+#![cfg(feature = "async")]
+
+use std::process::Command;
+
+#[test]
+fn test_summary_counts_failed_files() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["transactions.csv", "does-not-exist.csv", "transactions.csv"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert!(stderr.contains("failed to process does-not-exist.csv"));
+    assert_eq!(stderr.lines().last(), Some("processed 2/3 files, 1 failed"));
+}
+
+#[test]
+fn test_summary_all_files_processed() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["transactions.csv"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(stderr, "processed 1/1 files, 0 failed\n");
+    assert!(stdout.starts_with("client,available,held,total,locked\n"));
+}
+
+#[test]
+fn test_summary_counts_empty_files() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["tests/fixtures/empty.csv", "tests/fixtures/header_only.csv", "tests/fixtures/dispute_resolve.csv", "does-not-exist.csv"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert!(stderr.contains("no rows in tests/fixtures/empty.csv\nno rows in tests/fixtures/header_only.csv\n"), "{}", stderr);
+    assert!(stderr.contains("failed to process does-not-exist.csv"));
+    assert_eq!(stderr.lines().last(), Some("processed 3/4 files, 1 failed, 2 empty"));
+}
+
+#[test]
+fn test_jobs_limit() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["tests/fixtures/split_part_1.csv", "tests/fixtures/split_part_2.csv", "--jobs", "1"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(stderr, "processed 2/2 files, 0 failed\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["transactions.csv", "--jobs", "0"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_progress_line_per_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["tests/fixtures/dispute_resolve.csv", "--progress"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert!(stderr.starts_with("tests/fixtures/dispute_resolve.csv: done, 6 rows, 0.0 MB in "), "{}", stderr);
+    assert_eq!(stderr.lines().last(), Some("processed 1/1 files, 0 failed"));
+}
+
+#[test]
+fn test_clients_filter() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["transactions.csv", "--clients", "2,9"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(stdout, "client,available,held,total,locked\n2,2.0,0.0,2.0,false\n");
+    assert!(stderr.contains("no account for client 9"));
+}
+
+#[test]
+fn test_extended_output() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["tests/fixtures/dispute_resolve.csv", "--extended-output"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(stdout, "client,available,held,total,locked,open_disputes\n1,4.0,0.0,4.0,false,0\n");
+}
+
+#[test]
+fn test_output_to_file() {
+    let path = std::env::temp_dir().join("async_bank_runner_test_output.csv");
+    let _ = std::fs::remove_file(&path);
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["tests/fixtures/dispute_resolve.csv", "--output", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "client,available,held,total,locked\n1,4.0,0.0,4.0,false\n");
+}
+
+#[test]
+fn test_json_format() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["tests/fixtures/dispute_resolve.csv", "--format", "json"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(stdout, "[{\"client\":1,\"available\":\"4.0\",\"held\":\"0.0\",\"total\":\"4.0\",\"locked\":false}]\n");
+}
+
+#[test]
+fn test_parquet_format_requires_output() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["tests/fixtures/dispute_resolve.csv", "--format", "parquet"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn test_parquet_format_writes_file() {
+    let path = std::env::temp_dir().join("bank_runner_test_accounts.parquet");
+    let _ = std::fs::remove_file(&path);
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["tests/fixtures/dispute_resolve.csv", "--format", "parquet", "--output", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(std::fs::read(&path).unwrap().starts_with(b"PAR1"));
+}
+
+#[test]
+fn test_dry_run_reports_problems() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["--dry-run", "tests/fixtures/dispute_resolve.csv", "tests/fixtures/validation_problems.csv"])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(stderr.contains("tests/fixtures/validation_problems.csv: line 6: tx 1 is already used by another transaction\n"), "{}", stderr);
+    assert_eq!(stderr.lines().last(), Some("validated 2 files, 4 problems"));
+}
+
+#[test]
+fn test_dry_run_of_valid_file() {
+    let output = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+        .args(["--dry-run", "tests/fixtures/dispute_resolve.csv"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "validated 1 files, 0 problems\n");
+}
@@ -0,0 +1,107 @@
+//! Runs the binary's HTTP API on an ephemeral port, only built with the `server` feature:
+//! `cargo test --features server --test server`
+#![cfg(feature = "server")]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+
+/// The running server, killed when dropped
+struct Server {
+    child: Child,
+    address: String,
+}
+
+impl Server {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_async_bank_runner"))
+            .args(["serve", "--listen", "127.0.0.1:0"])
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stderr.take().unwrap()).read_line(&mut line).unwrap();
+        let address = line.trim().strip_prefix("listening on ").unwrap().to_string();
+        Self { child, address }
+    }
+
+    /// Sends a request and returns the status code and body of the response
+    fn request(&self, method: &str, path: &str, content_type: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(&self.address).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method, path, self.address, content_type, body.len(), body
+        ).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, body.to_string())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+#[test]
+fn test_post_csv_fixture_then_get_account() {
+    let server = Server::start();
+    let fixture = std::fs::read_to_string("tests/fixtures/dispute_resolve.csv").unwrap();
+
+    let (status, body) = server.request("POST", "/transactions", "text/csv", &fixture);
+    assert_eq!(status, 200);
+    assert!(body.contains("\"applied\":5"), "{}", body);
+    assert!(body.contains("\"rejected\":1"), "{}", body);
+
+    let (status, body) = server.request("GET", "/accounts/1", "text/plain", "");
+    assert_eq!(status, 200);
+    assert_eq!(body, r#"{"available":"4.0000","client":1,"held":"0.0000","locked":false,"total":"4.0000"}"#);
+}
+
+#[test]
+fn test_post_json_lines_then_get_accounts() {
+    let server = Server::start();
+    let body = "{\"type\": \"deposit\", \"client\": 2, \"tx\": 1, \"amount\": \"1.5\"}\n\
+        {\"type\": \"deposit\", \"client\": 3, \"tx\": 2, \"amount\": 2}\n\
+        {\"type\": \"transfer\", \"client\": 3, \"tx\": 3}\n";
+
+    let (status, body) = server.request("POST", "/transactions", "application/x-ndjson", body);
+    assert_eq!(status, 200);
+    assert!(body.contains("\"applied\":2"), "{}", body);
+    assert!(body.contains("\"parse_errors\":1"), "{}", body);
+
+    let (status, body) = server.request("GET", "/accounts", "text/plain", "");
+    assert_eq!(status, 200);
+    let mut lines: Vec<&str> = body.lines().collect();
+    lines.sort();
+    assert_eq!(lines, vec!["2,1.5,0.0,1.5,false", "3,2.0,0.0,2.0,false", "client,available,held,total,locked"]);
+}
+
+#[test]
+fn test_get_unknown_account() {
+    let server = Server::start();
+
+    assert_eq!(server.request("GET", "/accounts/9", "text/plain", "").0, 404);
+    assert_eq!(server.request("GET", "/accounts/70000", "text/plain", "").0, 400);
+}
+
+#[test]
+fn test_post_csv_larger_than_default_body_limit() {
+    let server = Server::start();
+    let mut body = String::from("type,client,tx,amount\n");
+    for tx in 1..=100_000 {
+        body.push_str(&format!("deposit,4,{},0.0001\n", tx));
+    }
+    assert!(body.len() > 2 * 1024 * 1024);
+
+    let (status, response) = server.request("POST", "/transactions", "text/csv", &body);
+    assert_eq!(status, 200, "{}", response);
+    assert!(response.contains("\"applied\":100000"), "{}", response);
+
+    let (_, account) = server.request("GET", "/accounts/4", "text/plain", "");
+    assert!(account.contains("\"available\":\"10.0000\""), "{}", account);
+}
@@ -0,0 +1,89 @@
+//! Plugs a transaction store implemented outside of the library into a bank, as a user of the
+//! crate would, to check the `TransactionStore` trait can be implemented with only its public API.
+
+use std::collections::BTreeMap;
+
+use bank_lib::bank::Bank;
+use bank_lib::config::BankConfig;
+use bank_lib::shared_types::{ClientId, TxId};
+use bank_lib::store::{StoredTx, TransactionStore};
+use bank_lib::transaction::{TxRequest, TxType};
+
+/// Keeps every transaction as a line of JSON, as a store writing them to disk would
+#[derive(Debug, Default)]
+struct JsonLinesStore {
+    lines: BTreeMap<TxId, String>,
+}
+
+impl JsonLinesStore {
+    fn update(&mut self, id: TxId, change: impl FnOnce(&mut StoredTx)) {
+        if let Some(mut tx) = self.get(id) {
+            change(&mut tx);
+            self.insert(tx);
+        }
+    }
+}
+
+impl TransactionStore for JsonLinesStore {
+    fn get(&self, id: TxId) -> Option<StoredTx> {
+        self.lines.get(&id).map(|line| serde_json::from_str(line).unwrap())
+    }
+
+    fn set_disputed(&mut self, id: TxId, disputed: bool) {
+        self.update(id, |tx| tx.disputed = disputed);
+    }
+
+    fn set_reversed(&mut self, id: TxId, reversed: bool) {
+        self.update(id, |tx| tx.reversed = reversed);
+    }
+
+    fn insert(&mut self, tx: StoredTx) {
+        self.lines.insert(tx.tx, serde_json::to_string(&tx).unwrap());
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = StoredTx> + '_> {
+        Box::new(self.lines.values().map(|line| serde_json::from_str(line).unwrap()))
+    }
+
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn has_disputed(&self, client: ClientId) -> bool {
+        self.iter().any(|tx| tx.client == client && tx.disputed)
+    }
+
+    fn remove_client(&mut self, client: ClientId) {
+        let ids: Vec<TxId> = self.iter().filter(|tx| tx.client == client).map(|tx| tx.tx).collect();
+        for id in ids {
+            self.lines.remove(&id);
+        }
+    }
+
+    fn drain(&mut self) -> Vec<StoredTx> {
+        let transactions = self.iter().collect();
+        self.lines.clear();
+        transactions
+    }
+}
+
+fn apply(bank: &Bank, type_: TxType, tx: u32, amount: Option<i64>) {
+    bank.apply(TxRequest { type_, client: ClientId(1), tx: TxId(tx), amount, currency: None }).unwrap();
+}
+
+#[test]
+fn test_bank_with_external_store() {
+    let bank = Bank::with_store(BankConfig::default(), Box::new(JsonLinesStore::default()));
+
+    apply(&bank, TxType::Deposit, 1, Some(20000));
+    apply(&bank, TxType::Deposit, 2, Some(10000));
+    apply(&bank, TxType::Dispute, 1, None);
+    let account = bank.account_snapshot(ClientId(1)).unwrap();
+    assert_eq!((account.available, account.held), (10000, 20000));
+
+    apply(&bank, TxType::Resolve, 1, None);
+    apply(&bank, TxType::Dispute, 2, None);
+    apply(&bank, TxType::Chargeback, 2, None);
+    let account = bank.account_snapshot(ClientId(1)).unwrap();
+    assert_eq!((account.available, account.held, account.locked), (20000, 0, true));
+}